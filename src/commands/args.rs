@@ -0,0 +1,286 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+use clap::Args;
+use grrs::core::file_types::{self, TypeDef};
+use grrs::core::ignore::{SubmoduleMode, SymlinkMode, WalkOptions};
+
+/// Flags that control how the file tree is walked.
+#[derive(Args)]
+pub struct WalkArgs {
+    #[arg(short = 'd', long = "max-depth", default_value_t = u32::MAX - 1, help = "Limits the depth of directory traversal. -1 (default) to disable the maximum. 0 to disable recursion.")]
+    max_depth: u32,
+    #[arg(
+        long = "glob-case-insensitive",
+        default_value_t = false,
+        help = "Matches ignore files case insensitively."
+    )]
+    glob_case_insensitive: bool,
+    #[arg(
+        long = "hidden",
+        default_value_t = false,
+        overrides_with = "no_hidden",
+        help = "Searches hidden files and directories. `.git` is always skipped."
+    )]
+    hidden: bool,
+    #[arg(
+        long = "no-hidden",
+        default_value_t = false,
+        overrides_with = "hidden",
+        help = "Skips hidden files and directories (default)."
+    )]
+    no_hidden: bool,
+    #[arg(
+        long = "no-ignore",
+        default_value_t = false,
+        help = "Does not honor .gitignore and .ignore files."
+    )]
+    no_ignore: bool,
+    #[arg(
+        short = 'u',
+        long = "unrestricted",
+        action = clap::ArgAction::Count,
+        help = "Searches more files, to find out why a file is skipped: -u is the same as --no-ignore, -uu also adds --hidden, and with grep, -uuu also adds --text."
+    )]
+    pub unrestricted: u8,
+    #[arg(
+        long = "no-ignore-vcs",
+        default_value_t = false,
+        help = "Does not honor .gitignore files, but still honors .ignore files."
+    )]
+    no_ignore_vcs: bool,
+    #[arg(
+        long = "no-ignore-parent",
+        default_value_t = false,
+        help = "Does not honor the ignore files in the parent directories of the searched paths. They are only honored up to the root of the git repository."
+    )]
+    no_ignore_parent: bool,
+    #[arg(
+        long = "no-ignore-exclude",
+        default_value_t = false,
+        help = "Does not honor the .git/info/exclude file of the git repository."
+    )]
+    no_ignore_exclude: bool,
+    #[arg(
+        long = "no-global-ignore",
+        default_value_t = false,
+        help = "Does not honor the global ignore file from git's core.excludesFile (~/.config/git/ignore by default)."
+    )]
+    no_global_ignore: bool,
+    #[arg(
+        short = 'g',
+        long = "glob",
+        value_name = "GLOB",
+        help = "Only searches files that match GLOB, or skips them if GLOB starts with `!`. Can be repeated."
+    )]
+    globs: Vec<String>,
+    #[arg(
+        long = "glob-file",
+        value_name = "FILE",
+        help = "Reads globs from FILE, one per line, as if each was given with --glob."
+    )]
+    glob_files: Vec<PathBuf>,
+    #[arg(
+        long = "include",
+        value_name = "GLOB",
+        help = "Only searches files that match GLOB, relative to the searched path (e.g. src/*.rs). Can be repeated. Same as --glob GLOB."
+    )]
+    includes: Vec<String>,
+    #[arg(
+        long = "exclude",
+        value_name = "GLOB",
+        help = "Skips files and directories that match GLOB, even if they are included. Can be repeated. Same as --glob '!GLOB'."
+    )]
+    excludes: Vec<String>,
+    #[arg(
+        short = 't',
+        long = "type",
+        value_name = "TYPE",
+        help = "Only searches files of TYPE (e.g. rust, py, sh), by extension or shebang. Can be repeated."
+    )]
+    types: Vec<String>,
+    #[arg(
+        short = 'T',
+        long = "type-not",
+        value_name = "TYPE",
+        help = "Skips files of TYPE, even if they are included with --type. Can be repeated."
+    )]
+    types_not: Vec<String>,
+    #[arg(
+        long = "type-add",
+        value_name = "NAME:EXT[,EXT...]",
+        help = "Defines the file type NAME by its extensions (e.g. web:html,css), or adds them to a known type. Can be repeated."
+    )]
+    type_defs: Vec<TypeDef>,
+    #[arg(
+        long = "type-list",
+        default_value_t = false,
+        help = "Prints each known file type, along with its extensions and shebang interpreters, then exits."
+    )]
+    pub type_list: bool,
+    #[arg(
+        long = "max-line-length",
+        value_name = "BYTES",
+        default_value_t = 1024,
+        help = "Reads up to BYTES of a long first line when checking if a file is text. Only the first 1024 bytes are read by default."
+    )]
+    max_line_length: usize,
+    #[arg(
+        long = "min-filesize",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Skips files smaller than SIZE, in bytes or with a K, M or G suffix (e.g. 10K)."
+    )]
+    min_filesize: Option<u64>,
+    #[arg(
+        long = "max-filesize",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Skips files larger than SIZE, in bytes or with a K, M or G suffix (e.g. 50M)."
+    )]
+    max_filesize: Option<u64>,
+    #[arg(
+        long = "symlinks",
+        value_name = "MODE",
+        value_enum,
+        default_value_t = SymlinkMode::Skip,
+        help = "Skips symlinks (default), follows only those to files, or follows all of them. With all, each directory is walked once, so cycles are safe."
+    )]
+    symlinks: SymlinkMode,
+    #[arg(
+        long = "follow",
+        default_value_t = false,
+        conflicts_with = "symlinks",
+        help = "Follows all symlinks. Same as --symlinks all."
+    )]
+    follow: bool,
+    #[arg(
+        long = "submodules",
+        value_name = "MODE",
+        value_enum,
+        default_value_t = SubmoduleMode::Include,
+        help = "Searches git submodules like any other directory (default), or skips them. Submodules are detected by their .git file."
+    )]
+    submodules: SubmoduleMode,
+    #[arg(
+        long = "show-skipped",
+        default_value_t = false,
+        help = "Prints each skipped file and directory to stderr, along with why it was skipped (e.g. binary, ignored or symlink)."
+    )]
+    show_skipped: bool,
+}
+
+impl WalkArgs {
+    pub fn options(&self) -> Result<WalkOptions> {
+        let mut globs = Vec::new();
+        for glob_file in &self.glob_files {
+            globs.extend(grrs::core::ignore::read_globs(glob_file)?);
+        }
+        globs.extend(self.globs.iter().cloned());
+        globs.extend(self.includes.iter().cloned());
+        globs.extend(self.excludes.iter().map(|glob| format!("!{}", glob)));
+
+        for file_type in self.types.iter().chain(&self.types_not) {
+            if !file_types::is_known(file_type, &self.type_defs) {
+                let known = file_types::names(&self.type_defs);
+                bail!(
+                    "unknown file type: {} (expected one of {})",
+                    file_type,
+                    known.join(", ")
+                );
+            }
+        }
+
+        if let (Some(min), Some(max)) = (self.min_filesize, self.max_filesize)
+            && min > max
+        {
+            bail!(
+                "--min-filesize ({} bytes) is larger than --max-filesize ({} bytes)",
+                min,
+                max
+            );
+        }
+
+        Ok(WalkOptions {
+            max_depth: self.max_depth,
+            case_insensitive_globs: self.glob_case_insensitive,
+            hidden: (self.hidden || self.unrestricted >= 2) && !self.no_hidden,
+            ignore: !self.no_ignore && self.unrestricted == 0,
+            ignore_vcs: !self.no_ignore_vcs,
+            ignore_parent: !self.no_ignore_parent,
+            ignore_exclude: !self.no_ignore_exclude,
+            global_ignore: !self.no_global_ignore,
+            globs,
+            types: self.types.clone(),
+            types_not: self.types_not.clone(),
+            type_defs: self.type_defs.clone(),
+            max_line_length: self.max_line_length,
+            min_filesize: self.min_filesize,
+            max_filesize: self.max_filesize,
+            symlinks: if self.follow {
+                SymlinkMode::All
+            } else {
+                self.symlinks
+            },
+            submodules: self.submodules,
+            show_skipped: self.show_skipped,
+            ..Default::default()
+        })
+    }
+
+    /// Prints the known file types if `--type-list` is given, in which case nothing else should
+    /// be done.
+    pub fn print_type_list(&self) -> bool {
+        if self.type_list {
+            for line in file_types::describe(&self.type_defs) {
+                println!("{}", line);
+            }
+        }
+        self.type_list
+    }
+}
+
+/// Parses a human-readable file size, e.g. `100`, `10K`, `1M` or `2G` (in powers of 1024).
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match size.char_indices().next_back() {
+        Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    // `parse` also accepts a sign
+    if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "invalid size {:?}, expected a number of bytes with an optional K, M or G suffix",
+            size
+        ));
+    }
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("size {:?} is too large", size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("10k"), Ok(10 * 1024));
+        assert_eq!(parse_size("3M"), Ok(3 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("+1").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("10KB").is_err());
+        assert!(parse_size("99999999999999999999").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+}
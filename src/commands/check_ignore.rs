@@ -0,0 +1,74 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use grrs::core::ignore::{WalkOptions, check_ignore};
+
+/// Prints which ignore file, line and pattern decide if each path is ignored, like
+/// `git check-ignore -v`, taking every ignore file that a search would honor into account.
+#[derive(Parser)]
+pub struct CheckIgnoreCommand {
+    #[arg(
+        help = "The paths to check. Directories can be given with a trailing slash, even if they do not exist. Read from stdin, one per line, if there are none."
+    )]
+    paths: Vec<String>,
+
+    // Flags
+    #[arg(
+        short = 'n',
+        long = "non-matching",
+        default_value_t = false,
+        help = "Also prints the paths that no pattern matches, as ::<TAB>PATH."
+    )]
+    non_matching: bool,
+}
+
+impl CheckIgnoreCommand {
+    pub fn run(self) -> Result<ExitCode> {
+        let paths = if self.paths.is_empty() {
+            std::io::stdin()
+                .lock()
+                .lines()
+                .collect::<Result<Vec<_>, _>>()
+                .context("could not read paths from stdin")?
+        } else {
+            self.paths
+        };
+
+        // Like a search, which honors the global ignore file by default
+        let options = WalkOptions {
+            global_ignore: true,
+            ..Default::default()
+        };
+        let mut stdout = std::io::stdout().lock();
+        let mut any_ignored = false;
+        for path in paths.iter().filter(|path| !path.is_empty()) {
+            let is_dir = path.ends_with('/') || Path::new(path).is_dir();
+            match check_ignore(Path::new(path.trim_end_matches('/')), is_dir, &options) {
+                // Like `git check-ignore -v`, e.g. `.gitignore:3:*.log<TAB>a.log`
+                Some(found) => {
+                    any_ignored |= !found.rule.is_negated();
+                    writeln!(
+                        stdout,
+                        "{}:{}:{}\t{}",
+                        found.source.display(),
+                        found.rule.line_num,
+                        found.rule.pattern,
+                        path
+                    )?;
+                }
+                None if self.non_matching => writeln!(stdout, "::\t{}", path)?,
+                None => {}
+            }
+        }
+
+        // Like `git check-ignore`
+        if any_ignored {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+use grrs::core::ignore::WalkOptions;
+
+#[derive(Parser)]
+pub struct FilesCommand {
+    path: Option<PathBuf>,
+
+    // Flags
+    #[command(flatten)]
+    walk: super::args::WalkArgs,
+    #[arg(
+        long = "include-dirs",
+        default_value_t = false,
+        help = "Also prints directories, with a trailing slash."
+    )]
+    include_dirs: bool,
+}
+
+impl FilesCommand {
+    pub fn run(self) -> Result<()> {
+        if self.walk.print_type_list() {
+            return Ok(());
+        }
+        let path = self.path.unwrap_or(PathBuf::from("."));
+
+        list_files(
+            path,
+            &WalkOptions {
+                include_dirs: self.include_dirs,
+                ..self.walk.options()?
+            },
+        )
+    }
+}
+
+/// Prints every file that would be searched, without searching them.
+/// Directories are also printed if `walk_options.include_dirs` is set.
+pub fn list_files(path: PathBuf, walk_options: &WalkOptions) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    for file_path in grrs::core::ignore::walk_iter(path, walk_options)? {
+        writeln!(stdout, "{}", file_path?.display())?;
+    }
+
+    Ok(())
+}
@@ -1,18 +1,68 @@
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use clap::Parser;
-use log::error;
+use anyhow::{Context, Result, bail};
+use clap::{ArgGroup, Parser};
+use grrs::core::color::ColorChoice;
+use grrs::core::ignore::{SkipCounts, WalkOptions};
+use grrs::core::log_rotation::RotationOrder;
+use grrs::core::pager::{Pager, PagerChoice};
+use grrs::core::threads::ThreadPool;
+use grrs::core::writer::{MarkdownWriter, OrderedWriter, SharedWriter, SynchronizedWriter};
+use grrs::grep::basic;
+use grrs::grep::code::Lang;
+use grrs::grep::decode::Encoding;
+use grrs::grep::matcher::{
+    BinaryFiles, CaptureGroup, CaseMode, LineSelection, MatchOptions, PatternSet, SeenLines,
+};
+use log::{debug, error, warn};
 
 #[derive(Parser)]
+#[command(group = ArgGroup::new("extract").args(["only_matching", "captures"]).multiple(true))]
 pub struct GrepCommand {
-    pattern: String,
-    path: Option<PathBuf>,
+    // Not required when listing files or file types, since nothing is searched.
+    // When patterns are given with `-e` or `-f`, this is the first path instead.
+    #[arg(required_unless_present_any = ["files", "regexps", "pattern_files", "type_list"])]
+    pattern: Option<String>,
+    paths: Vec<PathBuf>,
 
     // Flags
-    #[arg(short = 'd', long = "max-depth", default_value_t = u32::MAX - 1, help = "Limits the depth of directory traversal. -1 (default) to disable the maximum. 0 to disable recursion.")]
-    max_depth: u32,
+    #[arg(
+        short = 'e',
+        long = "regexp",
+        value_name = "PATTERN",
+        help = "Searches for PATTERN. Can be repeated to search for any of the patterns."
+    )]
+    regexps: Vec<String>,
+    #[arg(
+        short = 'f',
+        long = "file",
+        value_name = "FILE",
+        help = "Searches for the patterns in FILE, one per line. Blank lines are skipped. Can be repeated, and combined with -e. With -, the patterns are read from stdin, which is then not searched."
+    )]
+    pattern_files: Vec<PathBuf>,
+    #[arg(
+        long = "path",
+        value_name = "PATH",
+        allow_hyphen_values = true,
+        help = "Searches PATH, even if it looks like a flag (e.g. -a.txt) or is -. Can be repeated, and combined with the positional paths."
+    )]
+    explicit_paths: Vec<PathBuf>,
+    #[arg(
+        long = "files-all",
+        default_value_t = false,
+        help = "Prints the files that contain all of the patterns, on any line."
+    )]
+    files_all: bool,
+    #[command(flatten)]
+    walk: super::args::WalkArgs,
     #[arg(short = 'N', long = "no-line-number", default_value_t = false)]
     no_line_numbers: bool,
     #[arg(
@@ -22,52 +72,1467 @@ pub struct GrepCommand {
         help = "ignore case"
     )]
     ignore_case: bool,
+    #[arg(
+        short = 'S',
+        long = "smart-case",
+        default_value_t = false,
+        overrides_with = "ignore_case",
+        help = "Ignores case, unless the pattern has an uppercase letter. The letters of escapes like \\W or \\p{Lu} do not count. Whichever of -i and -S comes last wins."
+    )]
+    smart_case: bool,
+    #[arg(
+        long = "max-matches-per-line",
+        value_name = "NUM",
+        help = "Stops looking for matches on a line after NUM matches."
+    )]
+    max_matches_per_line: Option<NonZeroUsize>,
+    #[arg(
+        long = "files",
+        default_value_t = false,
+        help = "Prints the files that would be searched, without searching them."
+    )]
+    files: bool,
+    #[arg(
+        long = "include-dirs",
+        default_value_t = false,
+        requires = "files",
+        help = "Also prints directories when listing files, with a trailing slash."
+    )]
+    include_dirs: bool,
+    #[arg(
+        long = "color",
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        help = "Highlights matches."
+    )]
+    color: ColorChoice,
+    #[arg(
+        long = "ignore-races",
+        default_value_t = false,
+        help = "Skips files that are removed after they are found, instead of treating it as an error."
+    )]
+    ignore_races: bool,
+    #[arg(
+        long = "base",
+        value_name = "DIR",
+        help = "Prints paths relative to DIR. Paths outside of DIR are printed as absolute paths."
+    )]
+    base: Option<PathBuf>,
+    #[arg(
+        short = 'c',
+        long = "count",
+        default_value_t = false,
+        help = "Prints the number of matching lines in each file."
+    )]
+    count: bool,
+    #[arg(
+        long = "include-zero",
+        default_value_t = false,
+        requires = "count",
+        help = "Also prints the files without matching lines, with a count of 0."
+    )]
+    include_zero: bool,
+    #[arg(
+        long = "distinct",
+        default_value_t = false,
+        requires = "count",
+        help = "Counts identical matching lines once."
+    )]
+    distinct: bool,
+    #[arg(
+        long = "max-distinct",
+        value_name = "NUM",
+        default_value_t = 1_000_000,
+        requires = "distinct",
+        help = "Fails a file when it has more than NUM distinct matching lines, to bound memory usage."
+    )]
+    max_distinct: usize,
+    #[arg(
+        long = "output",
+        value_name = "FILE",
+        help = "Writes the results to FILE instead of stdout."
+    )]
+    output: Option<PathBuf>,
+    #[arg(
+        long = "not-pattern",
+        value_name = "PATTERN",
+        help = "Excludes lines that also match PATTERN. With -v, those lines are selected instead."
+    )]
+    not_pattern: Option<String>,
+    #[arg(
+        short = 'v',
+        long = "invert-match",
+        default_value_t = false,
+        conflicts_with_all = ["only_matching", "captures", "replace", "nth_match"],
+        help = "Selects the lines that do not match instead."
+    )]
+    invert_match: bool,
+    #[arg(
+        short = 'r',
+        long = "replace",
+        value_name = "TEMPLATE",
+        help = "Prints each matching line with every match replaced by TEMPLATE, e.g. to preview a bulk edit. Files are not changed. Supports capture groups like $1 (or ${1:upper}, ${1:lower} and ${1:trim}), as well as {path} and {lineno}."
+    )]
+    replace: Option<String>,
+    #[arg(
+        long = "first",
+        default_value_t = false,
+        conflicts_with = "last",
+        help = "Only prints the first matching line in each file."
+    )]
+    first: bool,
+    #[arg(
+        long = "last",
+        default_value_t = false,
+        help = "Only prints the last matching line in each file."
+    )]
+    last: bool,
+    #[arg(
+        long = "context-auto",
+        value_name = "MAX",
+        conflicts_with_all = ["first", "last"],
+        help = "Shows more context for isolated matches and less for clustered matches, up to MAX lines."
+    )]
+    context_auto: Option<usize>,
+    #[arg(
+        short = 'z',
+        long = "search-zip",
+        default_value_t = false,
+        help = "Searches the decompressed contents of gzip files (*.gz)."
+    )]
+    search_zip: bool,
+    #[arg(
+        long = "binary-files",
+        value_name = "TYPE",
+        value_enum,
+        default_value_t = BinaryFiles::WithoutMatch,
+        help = "Skips binary files (default), searches them but only prints that they match, or searches them as text. Files with a NUL byte or invalid UTF-8 near the start are binary."
+    )]
+    binary_files: BinaryFiles,
+    #[arg(
+        short = 'a',
+        long = "text",
+        default_value_t = false,
+        conflicts_with = "binary_files",
+        help = "Searches binary files as if they were text. Same as --binary-files text."
+    )]
+    text: bool,
+    #[arg(
+        long = "log-rotation",
+        value_name = "ORDER",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "newest-first",
+        help = "Searches rotated logs (e.g. app.log, app.log.1, app.log.2.gz) one at a time, in rotation ORDER."
+    )]
+    log_rotation: Option<RotationOrder>,
+    #[arg(
+        long = "summary-only",
+        default_value_t = false,
+        conflicts_with_all = ["count", "files", "files_all"],
+        help = "Only prints the total number of matching lines across all files. Exits with 1 if there are none."
+    )]
+    summary_only: bool,
+    #[arg(
+        long = "markdown",
+        default_value_t = false,
+        conflicts_with_all = ["count", "summary_only", "files", "files_all"],
+        help = "Prints each file as a Markdown heading followed by its matches in a code block."
+    )]
+    markdown: bool,
+    #[arg(
+        long = "capture",
+        value_name = "GROUP",
+        conflicts_with_all = ["replace", "context_auto"],
+        help = "Prints capture GROUP (an index or a name) of each match instead of the line. Can be repeated."
+    )]
+    captures: Vec<CaptureGroup>,
+    #[arg(
+        long = "capture-separator",
+        value_name = "SEP",
+        default_value = "\t",
+        help = "Separates the capture groups given with --capture."
+    )]
+    capture_separator: String,
+    #[arg(
+        long = "pager",
+        value_name = "WHEN",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = PagerChoice::Never,
+        default_missing_value = "auto",
+        help = "Pipes the results through $PAGER (or less). By default, only when stdout is a terminal."
+    )]
+    pager: PagerChoice,
+    #[arg(
+        short = 'j',
+        long = "threads",
+        value_name = "NUM",
+        help = "Searches NUM files at a time. With 1, the output is reproducible: files are searched one at a time, in order of their paths."
+    )]
+    threads: Option<NonZeroUsize>,
+    #[arg(
+        short = 'o',
+        long = "only-matching",
+        default_value_t = false,
+        conflicts_with_all = ["replace", "captures", "context_auto", "after_context", "before_context", "context"],
+        help = "Prints each match on its own line, instead of the whole line. Context lines are not printed."
+    )]
+    only_matching: bool,
+    #[arg(
+        long = "nth-match",
+        value_name = "N",
+        requires = "extract",
+        help = "With -o or --capture, only prints the Nth match on each line, skipping lines with fewer matches. Matches after --max-matches-per-line are not counted."
+    )]
+    nth_match: Option<NonZeroUsize>,
+    #[arg(
+        long = "bytes-searched",
+        default_value_t = false,
+        conflicts_with_all = ["files", "files_all"],
+        help = "Prints the total number of bytes searched, the time taken and the throughput at the end."
+    )]
+    bytes_searched: bool,
+    #[arg(
+        long = "stats-json",
+        value_name = "TO",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "stderr",
+        conflicts_with_all = ["files", "files_all"],
+        help = "Prints statistics about the search as a JSON object at the end, to stderr (default) or stdout: {\"files_searched\",\"files_matched\",\"matching_lines\",\"matches\",\"bytes_searched\",\"elapsed_ms\"}."
+    )]
+    stats_json: Option<StatsOutput>,
+    #[arg(
+        long = "stats",
+        default_value_t = false,
+        conflicts_with_all = ["files", "files_all"],
+        help = "Prints a summary of the search at the end: the matches, the files searched and those skipped as binary or ignored, the bytes searched and the time taken."
+    )]
+    stats: bool,
+    #[arg(
+        short = 'G',
+        long = "basic",
+        default_value_t = false,
+        help = "Treats the patterns as POSIX basic regular expressions, like grep: (, ), {, }, +, ? and | are literal unless escaped with a backslash."
+    )]
+    basic: bool,
+    #[arg(
+        short = 'F',
+        long = "fixed-strings",
+        default_value_t = false,
+        conflicts_with = "basic",
+        help = "Treats the patterns as literal strings."
+    )]
+    fixed_strings: bool,
+    #[arg(
+        short = 'w',
+        long = "word-regexp",
+        default_value_t = false,
+        conflicts_with = "line_regexp",
+        help = "Only matches whole words."
+    )]
+    word_regexp: bool,
+    #[arg(
+        short = 'x',
+        long = "line-regexp",
+        default_value_t = false,
+        help = "Only matches whole lines."
+    )]
+    line_regexp: bool,
+    #[arg(
+        long = "unique-output",
+        default_value_t = false,
+        conflicts_with_all = ["count", "summary_only", "context_auto"],
+        help = "Prints each distinct output line once across all files. Paths and line numbers are not compared."
+    )]
+    unique_output: bool,
+    #[arg(
+        long = "max-unique-output",
+        value_name = "NUM",
+        default_value_t = 1_000_000,
+        help = "Fails when there are more than NUM unique output lines, to bound memory usage."
+    )]
+    max_unique_output: usize,
+    #[arg(
+        short = 'm',
+        long = "max-count",
+        value_name = "NUM",
+        help = "Stops searching a file after NUM matching lines."
+    )]
+    max_count: Option<usize>,
+    #[arg(
+        long = "show-truncation",
+        default_value_t = false,
+        requires = "max_count",
+        help = "Prints a notice after the matches of a file that had more than --max-count matching lines."
+    )]
+    show_truncation: bool,
+    #[arg(
+        short = 'A',
+        long = "after-context",
+        value_name = "NUM",
+        conflicts_with_all = ["context_auto", "first", "last"],
+        help = "Prints NUM lines after each matching line."
+    )]
+    after_context: Option<usize>,
+    #[arg(
+        short = 'B',
+        long = "before-context",
+        value_name = "NUM",
+        conflicts_with_all = ["context_auto", "first", "last"],
+        help = "Prints NUM lines before each matching line."
+    )]
+    before_context: Option<usize>,
+    #[arg(
+        short = 'C',
+        long = "context",
+        value_name = "NUM",
+        conflicts_with_all = ["context_auto", "first", "last"],
+        help = "Prints NUM lines before and after each matching line. -A and -B take precedence."
+    )]
+    context: Option<usize>,
+    #[arg(
+        long = "cmd",
+        value_name = "COMMAND",
+        conflicts_with_all = ["paths", "files", "files_all", "log_rotation"],
+        help = "Searches the output of COMMAND instead of files. COMMAND is split on whitespace, without a shell."
+    )]
+    command: Option<String>,
+    #[arg(
+        long = "git-tracked",
+        default_value_t = false,
+        conflicts_with_all = ["files", "command"],
+        help = "Only searches the files that are tracked by git, as listed by `git ls-files`, instead of walking the paths. The other filters, like -t and --glob, still apply."
+    )]
+    git_tracked: bool,
+    #[arg(
+        long = "paragraph",
+        default_value_t = false,
+        conflicts_with_all = ["context_auto", "first", "last", "after_context", "before_context", "context"],
+        help = "Prints the whole paragraph around each matching line, where paragraphs are separated by blank lines."
+    )]
+    paragraph: bool,
+    #[arg(
+        long = "directories",
+        value_name = "ACTION",
+        value_enum,
+        default_value_t = Directories::Recurse,
+        help = "What to do with directories given as paths: search the files in them, skip them, or read them as files like grep (which fails)."
+    )]
+    directories: Directories,
+    #[arg(
+        long = "sort",
+        value_name = "BY",
+        value_enum,
+        conflicts_with = "log_rotation",
+        help = "Prints the results in order of the file paths. The results of each file are kept in memory until those of the files before it are printed."
+    )]
+    sort: Option<SortBy>,
+    #[arg(
+        long = "max-buffer",
+        value_name = "BYTES",
+        conflicts_with = "sort",
+        help = "Prints the results of a file early once BYTES of them are kept in memory, instead of all at once. The results of other files can then come in between, so each part starts with the path again."
+    )]
+    max_buffer: Option<usize>,
+    #[arg(
+        long = "ranges",
+        default_value_t = false,
+        conflicts_with_all = ["count", "summary_only", "markdown", "files", "files_all"],
+        help = "Prints the line numbers of the matching lines in each file, collapsed into ranges like path:10-14,20,33-35."
+    )]
+    ranges: bool,
+    #[arg(
+        long = "json",
+        default_value_t = false,
+        conflicts_with_all = ["count", "summary_only", "markdown", "ranges", "files", "files_all", "context_auto", "paragraph", "after_context", "before_context", "context", "show_truncation"],
+        help = "Prints each result as a JSON object with its path, line_number, byte_offset, text and submatches (with start and end byte offsets in the line), one per line (JSON Lines)."
+    )]
+    json: bool,
+    #[arg(
+        long = "code-only",
+        default_value_t = false,
+        requires = "lang",
+        conflicts_with_all = ["replace", "captures"],
+        help = "Ignores matches in comments and string literals, as written in the language given with --lang."
+    )]
+    code_only: bool,
+    #[arg(
+        long = "lang",
+        value_name = "LANG",
+        value_enum,
+        requires = "code_only",
+        help = "The language of the files searched with --code-only."
+    )]
+    lang: Option<Lang>,
+    #[arg(
+        short = 'l',
+        long = "files-with-matches",
+        default_value_t = false,
+        conflicts_with_all = ["files_without_match", "count", "summary_only", "markdown", "ranges", "json", "files", "files_all", "command"],
+        help = "Only prints the paths of the files with a matching line. Each file is only searched until its first match."
+    )]
+    files_with_matches: bool,
+    #[arg(
+        short = 'L',
+        long = "files-without-match",
+        default_value_t = false,
+        conflicts_with_all = ["count", "summary_only", "markdown", "ranges", "json", "files", "files_all", "command"],
+        help = "Only prints the paths of the files without a matching line. Exits with 1 if there are none."
+    )]
+    files_without_match: bool,
+    #[arg(
+        long = "decode",
+        value_name = "ENCODING",
+        value_enum,
+        conflicts_with_all = ["replace", "captures", "code_only"],
+        help = "Searches each line decoded from this encoding, but prints the original line. Lines that do not decode cleanly never match."
+    )]
+    decode: Option<Encoding>,
+    #[arg(
+        short = 'q',
+        long = "quiet",
+        default_value_t = false,
+        conflicts_with_all = ["files_without_match", "output", "pager", "bytes_searched", "stats_json", "stats", "files"],
+        help = "Prints nothing, and stops searching at the first match. Only the exit code tells if anything matched, even if some files could not be searched."
+    )]
+    quiet: bool,
+}
+
+/// The order to print the results of each file in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum SortBy {
+    Path,
+}
+
+/// Where to print the statistics of `--stats-json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum StatsOutput {
+    Stderr,
+    Stdout,
+}
+
+/// The number of files (or other inputs) searched so far, shared by the searches.
+#[derive(Default)]
+struct FileCounts {
+    searched: AtomicUsize,
+    matched: AtomicUsize,
+}
+
+impl FileCounts {
+    fn add(&self, num_matches: usize) {
+        self.searched.fetch_add(1, Ordering::Relaxed);
+        if num_matches > 0 {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The statistics of a search, for `--stats-json`.
+struct Stats {
+    files_searched: usize,
+    /// Files with at least one matching line.
+    files_matched: usize,
+    matching_lines: usize,
+    /// Every match on the matching lines, so at least `matching_lines`.
+    matches: usize,
+    bytes_searched: usize,
+    elapsed: Duration,
+    /// Files that were not searched because they are binary, only with `--stats`.
+    binary_skipped: usize,
+    /// Files and directories that were not searched because they are ignored, only with `--stats`.
+    ignored_skipped: usize,
+}
+
+impl Stats {
+    /// Formats the statistics as a JSON object, on one line.
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"files_searched\":{},\"files_matched\":{},\"matching_lines\":{},\"matches\":{},\"bytes_searched\":{},\"elapsed_ms\":{}}}",
+            self.files_searched,
+            self.files_matched,
+            self.matching_lines,
+            self.matches,
+            self.bytes_searched,
+            self.elapsed.as_millis()
+        )
+    }
+
+    /// Formats the statistics for people, one per line, like ripgrep's `--stats`.
+    fn to_text(&self) -> String {
+        format!(
+            "{} matches\n{} matched lines\n{} files contained matches\n{} files searched\n{} binary files skipped\n{} ignored paths skipped\n{} bytes searched\n{:.6} seconds\n",
+            self.matches,
+            self.matching_lines,
+            self.files_matched,
+            self.files_searched,
+            self.binary_skipped,
+            self.ignored_skipped,
+            self.bytes_searched,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// What to do with the directories that are given as paths.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Directories {
+    /// Tries to read the directory as if it was a file.
+    Read,
+    Skip,
+    #[default]
+    Recurse,
+}
+
+/// Formats `file_path` for output, relative to `base` (which must be absolute) if given.
+fn display_path(file_path: &Path, base: Option<&Path>) -> PathBuf {
+    let Some(base) = base else {
+        return file_path.to_path_buf();
+    };
+
+    let Ok(file_path) = std::path::absolute(file_path) else {
+        return file_path.to_path_buf();
+    };
+    match file_path.strip_prefix(base) {
+        Ok(relative_path) => relative_path.to_path_buf(),
+        Err(_) => file_path,
+    }
+}
+
+/// The same as for any other error, see `main`.
+const INVALID_PATH_EXIT_CODE: u8 = 2;
+
+/// Checks that `path` exists and can be read, returning a user-friendly message if not.
+fn check_path(path: &Path) -> std::result::Result<(), String> {
+    let describe = |err: std::io::Error| match err.kind() {
+        ErrorKind::NotFound => format!("path '{}' does not exist", path.display()),
+        ErrorKind::PermissionDenied => format!("path '{}' is not readable", path.display()),
+        _ => format!("path '{}' cannot be read: {}", path.display(), err),
+    };
+
+    let metadata = std::fs::metadata(path).map_err(describe)?;
+    if metadata.is_dir() {
+        std::fs::read_dir(path).map_err(describe)?;
+    } else {
+        File::open(path).map_err(describe)?;
+    }
+
+    Ok(())
+}
+
+/// The path that stands for stdin.
+const STDIN_ARG: &str = "-";
+
+/// How stdin is labelled in the output when it is searched.
+const STDIN_PATH: &str = "(standard input)";
+
+/// Checks if stdin is a pipe, e.g. `generate | grrs grep foo file`.
+/// Terminals and redirected files do not count, so that stdin is never read by accident.
+fn stdin_is_pipe() -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        std::fs::metadata("/dev/stdin").is_ok_and(|metadata| metadata.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Creates the writer for the results of a file, which starts with a header (or a Markdown
+/// heading) that is only written if there are any results.
+fn file_writer(
+    output: &SharedWriter,
+    display_path: &Path,
+    markdown: bool,
+    use_color: bool,
+    max_buffer: Option<usize>,
+) -> Box<dyn Write> {
+    let path = display_path.display().to_string();
+    if markdown {
+        return Box::new(MarkdownWriter::new(output.clone(), path).max_buffer(max_buffer));
+    }
+
+    let mut header = String::new();
+    if use_color {
+        grrs::core::color::paint(&mut header, grrs::core::color::PATH, &path);
+    } else {
+        header.push_str(&path);
+    }
+    header.push(':');
+    Box::new(SynchronizedWriter::new(output.clone(), header).max_buffer(max_buffer))
+}
+
+/// Opens a file that was found by the walk.
+///
+/// The file could have been removed after it was found but before it is opened.
+/// With `ignore_races`, such files are skipped (returning None) instead of being an error.
+/// With `search_zip`, compressed files are decompressed as they are read.
+fn open_file(
+    file_path: &Path,
+    ignore_races: bool,
+    search_zip: bool,
+) -> std::io::Result<Option<BufReader<Box<dyn Read + Send>>>> {
+    match File::open(file_path) {
+        Ok(f) if search_zip => Ok(Some(BufReader::new(grrs::core::compressed::reader(
+            file_path, f,
+        )))),
+        Ok(f) => Ok(Some(BufReader::new(Box::new(f)))),
+        Err(err) if ignore_races && err.kind() == ErrorKind::NotFound => {
+            debug!("{} was removed during the search", file_path.display());
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
 }
 
 impl GrepCommand {
-    pub fn run(self) -> Result<()> {
-        let path = self.path.unwrap_or(PathBuf::from("."));
+    /// Splits the positional arguments into the patterns and the paths.
+    ///
+    /// Like grep, once the patterns are given with `-e` or `-f`, every positional argument is a
+    /// path. Otherwise, the first one is the pattern. The pattern files are not read here.
+    fn patterns_and_paths(&mut self) -> (Vec<String>, Vec<PathBuf>) {
+        let mut patterns = std::mem::take(&mut self.regexps);
+        let mut paths = std::mem::take(&mut self.paths);
+
+        if self.files && paths.is_empty() {
+            // The pattern is not needed, so a lone positional argument is the path
+            paths.extend(self.pattern.take().map(PathBuf::from));
+        } else if !patterns.is_empty() || !self.pattern_files.is_empty() {
+            if let Some(path) = self.pattern.take() {
+                paths.insert(0, PathBuf::from(path));
+            }
+        } else {
+            patterns.extend(self.pattern.take());
+        }
+
+        if paths.is_empty() && self.explicit_paths.is_empty() {
+            paths.push(PathBuf::from("."));
+        }
+
+        (patterns, paths)
+    }
+
+    /// Walks `paths` for the files to search, calling `on_file` with each one as soon as it is
+    /// walked, so that the files are searched while the rest of the tree is walked.
+    /// With `--log-rotation`, every file is walked first, to put them in rotation order.
+    /// With `--git-tracked`, the files are listed by git instead.
+    /// The walk stops early once `on_file` breaks. The `--output` file is never searched.
+    /// The files that the walk skips are counted in `skip_counts`, if given.
+    fn for_each_file_path(
+        &self,
+        paths: Vec<PathBuf>,
+        skip_counts: Option<Arc<SkipCounts>>,
+        mut on_file: impl FnMut(PathBuf) -> ControlFlow<()>,
+    ) -> Result<()> {
+        // Created before the walk, so otherwise the results would be searched as they are written
+        let output_path = self
+            .output
+            .as_ref()
+            .and_then(|output_path| std::fs::canonicalize(output_path).ok());
+        let mut on_file = |file_path: PathBuf| {
+            if let Some(output_path) = &output_path
+                // Only files with the same name are canonicalized, since that requires a syscall
+                && file_path.file_name() == output_path.file_name()
+                && std::fs::canonicalize(&file_path).is_ok_and(|path| path == *output_path)
+            {
+                return ControlFlow::Continue(());
+            }
+            on_file(file_path)
+        };
+
+        let mut read_dirs = Vec::new();
+        let mut paths = match self.directories {
+            Directories::Recurse => paths,
+            Directories::Skip => paths.into_iter().filter(|path| !path.is_dir()).collect(),
+            Directories::Read => {
+                let (dirs, paths) = paths.into_iter().partition(|path| path.is_dir());
+                read_dirs = dirs;
+                paths
+            }
+        };
+
+        if self.sort.is_some() {
+            // Each path is walked in order, so the files of all of them are in order too
+            paths.sort();
+        }
+
+        // A single thread searches the files in the order that they are walked
+        let sorted = self.threads.is_some_and(|threads| threads.get() == 1) || self.sort.is_some();
+        let walk_options = self.walk_options(sorted, skip_counts)?;
+        if self.git_tracked || self.log_rotation.is_some() {
+            let mut file_paths = if self.git_tracked {
+                let mut file_paths = Vec::new();
+                for path in paths {
+                    let tracked = grrs::core::git::tracked_files(&path)?;
+                    file_paths.extend(grrs::core::ignore::filter_listed(
+                        &path,
+                        tracked,
+                        &walk_options,
+                    )?);
+                }
+                if sorted {
+                    file_paths.sort();
+                }
+                file_paths
+            } else {
+                walk_all(paths, &walk_options)?
+            };
+            file_paths.extend(read_dirs);
+            if let Some(order) = self.log_rotation {
+                grrs::core::log_rotation::sort_rotated(&mut file_paths, order);
+            }
+            let _ = file_paths.into_iter().try_for_each(on_file);
+            return Ok(());
+        }
+
+        for path in paths {
+            for file_path in grrs::core::ignore::walk_iter(path, &walk_options)? {
+                if on_file(file_path?).is_break() {
+                    return Ok(());
+                }
+            }
+        }
+        let _ = read_dirs.into_iter().try_for_each(on_file);
+
+        Ok(())
+    }
+
+    /// The options to walk the searched paths with, with the files in order of their paths if
+    /// `sorted`.
+    fn walk_options(
+        &self,
+        sorted: bool,
+        skip_counts: Option<Arc<SkipCounts>>,
+    ) -> Result<WalkOptions> {
+        Ok(WalkOptions {
+            search_zip: self.search_zip,
+            binary: self.binary_files() != BinaryFiles::WithoutMatch,
+            sorted,
+            skip_counts,
+            ignore_races: self.ignore_races,
+            ..self.walk.options()?
+        })
+    }
+
+    /// With `--sort`, the results of each file are written in the order that the files are
+    /// searched in, as soon as those of the files before them are written.
+    fn ordered_output(&self, output: &SharedWriter) -> Option<OrderedWriter> {
+        // Each file has its own writer, so the Markdown sections are only separated here
+        let separator: &[u8] = if self.markdown { b"\n" } else { b"" };
+        self.sort
+            .map(|_| OrderedWriter::new(output.clone(), separator))
+    }
+
+    /// The output for the results of a single file, which is a task of `ordered` if there is one.
+    fn task_output(output: &SharedWriter, ordered: Option<&OrderedWriter>) -> SharedWriter {
+        match ordered {
+            Some(ordered) => ordered.task(),
+            None => output.clone(),
+        }
+    }
+
+    /// How the case of letters is matched, with `-S` overriding `-i` if it comes last (and the
+    /// other way around).
+    fn case_mode(&self) -> CaseMode {
+        if self.smart_case {
+            CaseMode::Smart
+        } else if self.ignore_case {
+            CaseMode::Insensitive
+        } else {
+            CaseMode::Sensitive
+        }
+    }
+
+    /// How binary files are searched, since `-a` (or `-uuu`) is a shorthand for
+    /// `--binary-files text`.
+    fn binary_files(&self) -> BinaryFiles {
+        if self.text || self.walk.unrestricted >= 3 {
+            BinaryFiles::Text
+        } else {
+            self.binary_files
+        }
+    }
+
+    pub fn run(mut self) -> Result<ExitCode> {
+        if self.walk.print_type_list() {
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        // Piped stdin is searched along with the given paths, like `generate | grrs grep foo file`,
+        // or on its own without paths. It is also searched if given as `-`.
+        let has_paths = !self.paths.is_empty()
+            || !self.explicit_paths.is_empty()
+            || ((!self.regexps.is_empty() || !self.pattern_files.is_empty())
+                && self.pattern.is_some());
+        let patterns_from_stdin = self
+            .pattern_files
+            .iter()
+            .any(|path| path == Path::new(STDIN_ARG));
+        let is_piped =
+            !self.files && self.command.is_none() && !patterns_from_stdin && stdin_is_pipe();
+
+        let (mut patterns, mut paths) = self.patterns_and_paths();
+        if patterns_from_stdin && paths.iter().any(|path| path == Path::new(STDIN_ARG)) {
+            bail!("stdin cannot be searched when the patterns are read from it with -f -");
+        }
+        for pattern_file in &self.pattern_files {
+            patterns.extend(read_patterns(pattern_file)?);
+        }
+        if patterns.is_empty() && !self.files {
+            // Otherwise, the empty alternation would match every line
+            bail!("no patterns given: the pattern files are empty");
+        }
+        let num_paths = paths.len();
+        paths.retain(|path| path != Path::new(STDIN_ARG));
+        let search_stdin = is_piped || paths.len() < num_paths;
+        // Added after `-` is handled, since these are always files
+        paths.append(&mut self.explicit_paths);
+        if search_stdin && !has_paths {
+            // Instead of the current directory
+            paths.clear();
+        }
 
-        let thread_pool = grrs::core::threads::ThreadPool::all_cores();
+        if self.basic {
+            // Translated before the patterns are combined, since that adds groups
+            patterns = patterns.iter().map(|p| basic::translate(p)).collect();
+            self.not_pattern = self.not_pattern.as_deref().map(basic::translate);
+        }
+
+        for path in &paths {
+            if let Err(message) = check_path(path) {
+                eprintln!("error: {}", message);
+                return Ok(ExitCode::from(INVALID_PATH_EXIT_CODE));
+            }
+        }
+
+        self.search(patterns, paths, search_stdin)
+    }
+
+    /// Searches `paths` for any of `patterns`. With `search_stdin`, stdin is searched first.
+    /// If stdin is the only input, its results do not have a header, just like the output of
+    /// `--cmd`.
+    fn search(
+        mut self,
+        patterns: Vec<String>,
+        paths: Vec<PathBuf>,
+        search_stdin: bool,
+    ) -> Result<ExitCode> {
+        if self.files {
+            let walk_options = WalkOptions {
+                include_dirs: self.include_dirs,
+                ..self.walk.options()?
+            };
+            for path in paths {
+                super::files::list_files(path, &walk_options)?;
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let mut output = match &self.output {
+            None if self.quiet => SharedWriter::new(std::io::sink()),
+            Some(output_path) => {
+                let f = File::create(output_path)
+                    .with_context(|| format!("could not create file {:?}", output_path))?;
+                SharedWriter::new(BufWriter::new(f))
+            }
+            None if self.pager.is_enabled() => match Pager::spawn() {
+                Ok(pager) => SharedWriter::new(pager),
+                Err(err) => {
+                    warn!("could not start the pager: {}", err);
+                    SharedWriter::new(std::io::stdout())
+                }
+            },
+            None => SharedWriter::new(std::io::stdout()),
+        };
+
+        let base = match &self.base {
+            Some(base) => Some(Arc::<Path>::from(std::path::absolute(base)?)),
+            None => None,
+        };
+
+        // Rotated logs are searched one at a time, so that they are printed in order
+        let thread_pool = if self.log_rotation.is_some() {
+            ThreadPool::new(1)
+        } else if let Some(threads) = self.threads {
+            ThreadPool::new(threads.get())
+        } else {
+            ThreadPool::all_cores()
+        };
+        let had_error = Arc::new(AtomicBool::new(false));
+
+        if self.files_all {
+            let patterns = Arc::new(PatternSet::new(
+                &patterns,
+                &MatchOptions {
+                    case: self.case_mode(),
+                    fixed_strings: self.fixed_strings,
+                    ..Default::default()
+                },
+            )?);
+            let is_found = Arc::new(AtomicBool::new(false));
+
+            let ordered = self.ordered_output(&output);
+            let walked = self.for_each_file_path(paths, None, |file_path| {
+                if self.quiet && is_found.load(Ordering::Relaxed) {
+                    return ControlFlow::Break(());
+                }
+                let patterns = Arc::clone(&patterns);
+                let base = base.clone();
+                let mut output = Self::task_output(&output, ordered.as_ref());
+                let had_error = Arc::clone(&had_error);
+                let is_found = Arc::clone(&is_found);
+
+                thread_pool.execute(move || {
+                    if self.quiet && is_found.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let reader = match open_file(&file_path, self.ignore_races, self.search_zip) {
+                        Ok(Some(reader)) => reader,
+                        Ok(None) => return,
+                        Err(err) => {
+                            error!("could not read file {:?}: {}", file_path, err);
+                            had_error.store(true, Ordering::Relaxed);
+                            return;
+                        }
+                    };
+
+                    match grrs::grep::matcher::contains_all_patterns(reader, &patterns) {
+                        Ok(true) => {
+                            is_found.store(true, Ordering::Relaxed);
+                            write_line(
+                                &mut output,
+                                &display_path(&file_path, base.as_deref())
+                                    .display()
+                                    .to_string(),
+                            );
+                        }
+                        Ok(false) => {}
+                        Err(err) => {
+                            error!(
+                                "failed to read {}: {}",
+                                file_path.display(),
+                                err.root_cause()
+                            );
+                            had_error.store(true, Ordering::Relaxed);
+                        }
+                    };
+                });
+                ControlFlow::Continue(())
+            });
+
+            // The files that were found before a walk failed are still searched
+            wait(thread_pool, &had_error);
+            if self.quiet && is_found.load(Ordering::Relaxed) {
+                // Like grep, a match takes precedence over any errors
+                return Ok(ExitCode::SUCCESS);
+            }
+            if let Some(ordered) = ordered {
+                ordered.finish()?;
+            }
+            output.flush()?;
+
+            walked?;
+            check_errors(&had_error)?;
+            return Ok(if is_found.load(Ordering::Relaxed) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+
+        let start_time = Instant::now();
+        let skip_counts = self.stats.then(|| Arc::new(SkipCounts::default()));
+        let bytes_searched = Arc::new(AtomicUsize::new(0));
+        let matches_found = Arc::new(AtomicUsize::new(0));
 
-        let pattern = Arc::new(self.pattern);
+        let options = Arc::new(MatchOptions {
+            show_line_numbers: !self.no_line_numbers,
+            case: self.case_mode(),
+            fixed_strings: self.fixed_strings,
+            word_regexp: self.word_regexp,
+            line_regexp: self.line_regexp,
+            max_matches_per_line: self.max_matches_per_line.map(NonZeroUsize::get),
+            // Color codes would show up as is in Markdown
+            color: if self.markdown {
+                ColorChoice::Never
+            } else {
+                self.color
+            },
+            count_only: self.quiet
+                || self.count
+                || self.summary_only
+                || self.ranges
+                || self.files_with_matches
+                || self.files_without_match,
+            distinct: self.distinct,
+            max_distinct: Some(self.max_distinct),
+            not_pattern: self.not_pattern.take(),
+            invert_match: self.invert_match,
+            replace: self.replace.take(),
+            context_auto: self.context_auto,
+            captures: std::mem::take(&mut self.captures),
+            capture_separator: std::mem::take(&mut self.capture_separator),
+            only_matching: self.only_matching,
+            nth_match: self.nth_match.map(NonZeroUsize::get),
+            // Only counted if they are printed
+            bytes_searched: (self.bytes_searched || self.stats || self.stats_json.is_some())
+                .then(|| Arc::clone(&bytes_searched)),
+            matches_found: Some(Arc::clone(&matches_found)),
+            max_count: self.max_count,
+            show_truncation: self.show_truncation,
+            before_context: self.before_context.or(self.context).unwrap_or(0),
+            after_context: self.after_context.or(self.context).unwrap_or(0),
+            paragraph: self.paragraph,
+            ranges: self.ranges,
+            json: self.json,
+            code_only: self.lang.filter(|_| self.code_only),
+            decode: self.decode,
+            binary_files: Some(self.binary_files()),
+            unique_output: self
+                .unique_output
+                .then(|| Arc::new(SeenLines::new(Some(self.max_unique_output)))),
+            // Whether a file matches is known after its first match
+            select: if self.quiet
+                || self.first
+                || self.files_with_matches
+                || self.files_without_match
+            {
+                LineSelection::First
+            } else if self.last {
+                LineSelection::Last
+            } else {
+                LineSelection::All
+            },
+            ..Default::default()
+        });
 
-        let file_paths = grrs::core::ignore::walk(path, self.max_depth)?;
-        for file_path in file_paths {
-            let pattern = Arc::clone(&pattern);
+        // Compiled once up front and shared by every search, so that an invalid pattern fails
+        // before anything is searched
+        let patterns = Arc::new(PatternSet::new(&patterns, &options)?);
+
+        let total_matches = Arc::new(AtomicUsize::new(0));
+        let file_counts = Arc::new(FileCounts::default());
+        let use_color = options.color.is_enabled();
+
+        // The command's output is searched instead of any files
+        let paths = match &self.command {
+            Some(command) => {
+                let writer: Box<dyn Write> = if self.markdown {
+                    Box::new(MarkdownWriter::new(output.clone(), command.clone()))
+                } else {
+                    Box::new(output.clone())
+                };
+                let num_matches = search_command(command, writer, &patterns, &options)?;
+                total_matches.fetch_add(num_matches, Ordering::Relaxed);
+                file_counts.add(num_matches);
+                if self.count && (num_matches > 0 || self.include_zero) {
+                    write_line(&mut output, &num_matches.to_string());
+                }
+                Vec::new()
+            }
+            None if search_stdin => {
+                // Searched before any of the files
+                let display_path = Path::new(STDIN_PATH);
+                let has_header = !paths.is_empty();
+                let writer = if (has_header && !self.ranges && !self.json) || self.markdown {
+                    file_writer(
+                        &output,
+                        display_path,
+                        self.markdown,
+                        use_color,
+                        self.max_buffer,
+                    )
+                } else {
+                    Box::new(output.clone())
+                };
+                // Like the counts, the ranges only have the path along with other paths
+                let searched_path = (has_header || !self.ranges).then_some(display_path);
+                let stdin = std::io::stdin().lock();
+                match grrs::grep::matcher::find_matches(
+                    stdin,
+                    writer,
+                    searched_path,
+                    &patterns,
+                    &options,
+                ) {
+                    Ok(num_matches) => {
+                        total_matches.fetch_add(num_matches, Ordering::Relaxed);
+                        file_counts.add(num_matches);
+                        if self.count && (num_matches > 0 || self.include_zero) {
+                            let line = if has_header {
+                                format!("{}:{}", STDIN_PATH, num_matches)
+                            } else {
+                                num_matches.to_string()
+                            };
+                            write_line(&mut output, &line);
+                        }
+                        if (self.files_with_matches && num_matches > 0)
+                            || (self.files_without_match && num_matches == 0)
+                        {
+                            write_line(&mut output, STDIN_PATH);
+                        }
+                    }
+                    Err(err) => {
+                        error!("failed to read {}: {}", STDIN_PATH, err.root_cause());
+                        had_error.store(true, Ordering::Relaxed);
+                    }
+                };
+                paths
+            }
+            None => paths,
+        };
+        let ordered = self.ordered_output(&output);
+        let walked = self.for_each_file_path(paths, skip_counts.clone(), |file_path| {
+            if self.quiet && total_matches.load(Ordering::Relaxed) > 0 {
+                return ControlFlow::Break(());
+            }
+            let patterns = Arc::clone(&patterns);
+            let options = Arc::clone(&options);
+            let base = base.clone();
+            let mut output = Self::task_output(&output, ordered.as_ref());
+            let had_error = Arc::clone(&had_error);
+            let total_matches = Arc::clone(&total_matches);
+            let file_counts = Arc::clone(&file_counts);
 
             thread_pool.execute(move || {
-                let Ok(f) = std::fs::File::open(&file_path) else {
-                    error!("could not read file {:?}", file_path);
+                if self.quiet && total_matches.load(Ordering::Relaxed) > 0 {
                     return;
+                }
+                let reader = match open_file(&file_path, self.ignore_races, self.search_zip) {
+                    Ok(Some(reader)) => reader,
+                    Ok(None) => return,
+                    Err(err) => {
+                        error!("could not read file {:?}: {}", file_path, err);
+                        had_error.store(true, Ordering::Relaxed);
+                        return;
+                    }
                 };
-                let reader = std::io::BufReader::new(f);
 
-                // header will only be printed if something was actually written
-                let header = format!("{}:", file_path.display());
-                let writer = grrs::core::writer::SynchronizedWriter::new(std::io::stdout(), header);
+                let display_path = display_path(&file_path, base.as_deref());
+                let writer = if self.ranges || self.json {
+                    // Each line has the path instead
+                    Box::new(output.clone())
+                } else {
+                    file_writer(
+                        &output,
+                        &display_path,
+                        self.markdown,
+                        use_color,
+                        self.max_buffer,
+                    )
+                };
 
                 match grrs::grep::matcher::find_matches(
                     reader,
                     writer,
-                    &pattern,
-                    grrs::grep::matcher::MatchOptions {
-                        show_line_numbers: !self.no_line_numbers,
-                        case_insensitive: self.ignore_case,
-                    },
+                    Some(&display_path),
+                    &patterns,
+                    &options,
                 ) {
-                    Ok(_) => {}
-                    Err(err) => error!(
-                        "failed to read {}: {}",
-                        file_path.display(),
-                        err.root_cause()
-                    ),
+                    Ok(num_matches) => {
+                        total_matches.fetch_add(num_matches, Ordering::Relaxed);
+                        file_counts.add(num_matches);
+                        if self.count && (num_matches > 0 || self.include_zero) {
+                            write_line(
+                                &mut output,
+                                &format!("{}:{}", display_path.display(), num_matches),
+                            );
+                        }
+                        if (self.files_with_matches && num_matches > 0)
+                            || (self.files_without_match && num_matches == 0)
+                        {
+                            write_line(&mut output, &display_path.display().to_string());
+                        }
+                    }
+                    Err(err) => {
+                        error!(
+                            "failed to read {}: {}",
+                            file_path.display(),
+                            err.root_cause()
+                        );
+                        had_error.store(true, Ordering::Relaxed);
+                    }
                 };
             });
+            ControlFlow::Continue(())
+        });
+
+        // The files that were found before a walk failed are still searched
+        wait(thread_pool, &had_error);
+        if self.quiet && total_matches.load(Ordering::Relaxed) > 0 {
+            // Like grep, a match takes precedence over any errors
+            return Ok(ExitCode::SUCCESS);
         }
+        if let Some(ordered) = ordered {
+            ordered.finish()?;
+        }
+
+        let total_matches = total_matches.load(Ordering::Relaxed);
+        if self.summary_only {
+            write_line(&mut output, &total_matches.to_string());
+        }
+        if self.bytes_searched {
+            let bytes_searched = bytes_searched.load(Ordering::Relaxed);
+            let seconds = start_time.elapsed().as_secs_f64();
+            write_line(&mut output, &format!("{} bytes searched", bytes_searched));
+            write_line(&mut output, &format!("{:.3} seconds", seconds));
+            // The clock might not have advanced at all for a tiny search
+            if seconds > 0.0 {
+                write_line(
+                    &mut output,
+                    &format!("{:.2} MB/s", bytes_searched as f64 / 1e6 / seconds),
+                );
+            }
+        }
+        // Before any errors are returned, so that the statistics are always printed
+        let stats = Stats {
+            files_searched: file_counts.searched.load(Ordering::Relaxed),
+            files_matched: file_counts.matched.load(Ordering::Relaxed),
+            matching_lines: total_matches,
+            matches: matches_found.load(Ordering::Relaxed),
+            bytes_searched: bytes_searched.load(Ordering::Relaxed),
+            elapsed: start_time.elapsed(),
+            binary_skipped: skip_counts
+                .as_ref()
+                .map_or(0, |counts| counts.binary.load(Ordering::Relaxed)),
+            ignored_skipped: skip_counts
+                .as_ref()
+                .map_or(0, |counts| counts.ignored.load(Ordering::Relaxed)),
+        };
+        if let Some(stats_output) = self.stats_json {
+            match stats_output {
+                StatsOutput::Stderr => eprintln!("{}", stats.to_json()),
+                StatsOutput::Stdout => write_line(&mut output, &stats.to_json()),
+            }
+        }
+        if self.stats {
+            // Separated from the results by an empty line
+            write_line(&mut output, &format!("\n{}", stats.to_text().trim_end()));
+        }
+        output.flush()?;
 
-        thread_pool.wait();
+        // Like grep, errors take precedence over matches (see `main`), and finding nothing fails
+        walked?;
+        check_errors(&had_error)?;
+        let is_found = if self.files_without_match {
+            // Like GNU grep, -L succeeds if any file was listed, i.e. if any file did not match
+            file_counts.matched.load(Ordering::Relaxed)
+                < file_counts.searched.load(Ordering::Relaxed)
+        } else {
+            total_matches > 0
+        };
+        if !is_found {
+            return Ok(ExitCode::FAILURE);
+        }
 
-        Ok(())
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Runs `command` and searches its stdout, which has no path, so there is no header.
+/// Fails if the command cannot be started or exits unsuccessfully.
+fn search_command<W: Write>(
+    command: &str,
+    writer: W,
+    patterns: &PatternSet,
+    options: &MatchOptions,
+) -> Result<usize> {
+    let mut words = command.split_whitespace();
+    let Some(program) = words.next() else {
+        bail!("the command to search is empty");
+    };
+    let mut child = Command::new(program)
+        .args(words)
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("could not run command '{}'", command))?;
+
+    let mut reader = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let result = grrs::grep::matcher::find_matches(&mut reader, writer, None, patterns, options);
+    // The search can stop early (e.g. with --max-count), but the command should not fail
+    // because nothing is reading its output anymore
+    let _ = std::io::copy(&mut reader, &mut std::io::sink());
+    let status = child
+        .wait()
+        .with_context(|| format!("could not run command '{}'", command))?;
+
+    let num_matches = result?;
+    if !status.success() {
+        bail!("command '{}' failed: {}", command, status);
+    }
+
+    Ok(num_matches)
+}
+
+/// Reads the patterns in `patterns_path` (or stdin if it is `-`), one per line, skipping blank
+/// lines.
+fn read_patterns(patterns_path: &Path) -> Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = if patterns_path == Path::new(STDIN_ARG) {
+        Box::new(std::io::stdin().lock())
+    } else {
+        let f = File::open(patterns_path)
+            .with_context(|| format!("could not read pattern file {:?}", patterns_path))?;
+        Box::new(BufReader::new(f))
+    };
+
+    let mut patterns = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        // Also handles files with CRLF line endings
+        let pattern = line.strip_suffix('\r').unwrap_or(&line);
+        if !pattern.is_empty() {
+            patterns.push(pattern.to_string());
+        }
+    }
+
+    Ok(patterns)
+}
+
+/// Walks each of `paths` in turn, collecting the files to search.
+fn walk_all(paths: Vec<PathBuf>, walk_options: &WalkOptions) -> Result<Vec<PathBuf>> {
+    let mut file_paths = Vec::new();
+    for path in paths {
+        file_paths.extend(grrs::core::ignore::walk(path, walk_options)?);
+    }
+
+    Ok(file_paths)
+}
+
+/// Writes `line` in one go, so that it is not interleaved with output from other threads.
+fn write_line(output: &mut SharedWriter, line: &str) {
+    // TODO: Do we need to handle this failing?
+    let _ = output.write_all(format!("{}\n", line).as_bytes());
+}
+
+/// Waits for the searches in `thread_pool` to finish. A search that panicked counts as an error.
+fn wait(thread_pool: ThreadPool, had_error: &AtomicBool) {
+    if let Err(errors) = thread_pool.wait() {
+        for err in errors {
+            error!("{}", err);
+        }
+        had_error.store(true, Ordering::Relaxed);
+    }
+}
+
+fn check_errors(had_error: &AtomicBool) -> Result<()> {
+    if had_error.load(Ordering::Relaxed) {
+        bail!("some files could not be searched");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_open_removed_file() {
+        let dir = TempDir::new();
+        let path = dir.write("a.txt", "hello\n");
+
+        let file_paths = grrs::core::ignore::walk(
+            dir.path().to_path_buf(),
+            &grrs::core::ignore::WalkOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(file_paths, vec![path]);
+
+        // The file is removed after it is found
+        std::fs::remove_file(&file_paths[0]).unwrap();
+
+        assert!(open_file(&file_paths[0], false, false).is_err());
+        assert!(open_file(&file_paths[0], true, false).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_patterns_and_paths() {
+        let split = |args: &[&str]| {
+            let mut command =
+                GrepCommand::try_parse_from(std::iter::once("grep").chain(args.iter().copied()))
+                    .unwrap();
+            command.patterns_and_paths()
+        };
+
+        assert_eq!(
+            split(&["foo"]),
+            (vec![String::from("foo")], vec![PathBuf::from(".")])
+        );
+        assert_eq!(
+            split(&["foo", "a.txt"]),
+            (vec![String::from("foo")], vec![PathBuf::from("a.txt")])
+        );
+        assert_eq!(
+            split(&["foo", "a.txt", "b.txt"]),
+            (
+                vec![String::from("foo")],
+                vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]
+            )
+        );
+        assert_eq!(
+            split(&["-e", "foo", "a.txt", "b.txt"]),
+            (
+                vec![String::from("foo")],
+                vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]
+            )
+        );
+        assert_eq!(
+            split(&["-e", "foo", "-e", "bar"]),
+            (
+                vec![String::from("foo"), String::from("bar")],
+                vec![PathBuf::from(".")]
+            )
+        );
+        assert_eq!(
+            split(&["-f", "patterns.txt", "a.txt"]),
+            (vec![], vec![PathBuf::from("a.txt")])
+        );
+        assert_eq!(
+            split(&["--files", "src"]),
+            (vec![], vec![PathBuf::from("src")])
+        );
+        assert_eq!(
+            split(&["--files", "foo", "src"]),
+            (vec![String::from("foo")], vec![PathBuf::from("src")])
+        );
+    }
+
+    #[test]
+    fn test_check_path() {
+        assert_eq!(check_path(Path::new(".")), Ok(()));
+        assert_eq!(check_path(Path::new("Cargo.toml")), Ok(()));
+        assert_eq!(
+            check_path(Path::new("/nonexistent")),
+            Err(String::from("path '/nonexistent' does not exist"))
+        );
+    }
+
+    #[test]
+    fn test_display_path() {
+        let base = Path::new("/project");
+
+        assert_eq!(
+            display_path(Path::new("/project/src/a.rs"), None),
+            PathBuf::from("/project/src/a.rs")
+        );
+        assert_eq!(
+            display_path(Path::new("/project/src/a.rs"), Some(base)),
+            PathBuf::from("src/a.rs")
+        );
+        assert_eq!(
+            display_path(Path::new("/other/a.rs"), Some(base)),
+            PathBuf::from("/other/a.rs")
+        );
+        assert_eq!(
+            display_path(Path::new("/project2/a.rs"), Some(base)),
+            PathBuf::from("/project2/a.rs")
+        );
     }
 }
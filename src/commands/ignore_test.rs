@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use grrs::core::ignore::GitIgnore;
+
+/// Checks which paths an ignore file ignores, and by which rule, without walking any files.
+#[derive(Parser)]
+pub struct IgnoreTestCommand {
+    #[arg(help = "The ignore file to test, e.g. .gitignore.")]
+    ignore_file: PathBuf,
+    #[arg(
+        help = "The paths to test, relative to the directory of the ignore file. Directories need a trailing slash. Read from stdin, one per line, if there are none."
+    )]
+    paths: Vec<String>,
+}
+
+impl IgnoreTestCommand {
+    pub fn run(self) -> Result<ExitCode> {
+        let f = File::open(&self.ignore_file)
+            .with_context(|| format!("could not read file {:?}", self.ignore_file))?;
+        // The paths are already relative to the ignore file
+        let gitignore = GitIgnore::from(PathBuf::new(), BufReader::new(f))?;
+
+        let paths = if self.paths.is_empty() {
+            std::io::stdin()
+                .lock()
+                .lines()
+                .collect::<Result<Vec<_>, _>>()
+                .context("could not read paths from stdin")?
+        } else {
+            self.paths
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let mut any_ignored = false;
+        for path in paths.iter().filter(|path| !path.is_empty()) {
+            let is_ignored = test_path(&mut stdout, &gitignore, &self.ignore_file, path)?;
+            any_ignored |= is_ignored;
+        }
+
+        // Like `git check-ignore`
+        if any_ignored {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Writes whether `path` is ignored by `gitignore` and by which rule, e.g.
+/// `build/: ignored by .gitignore:3: build/`. Returns whether it is ignored.
+fn test_path<W: Write>(
+    writer: &mut W,
+    gitignore: &GitIgnore,
+    ignore_file: &Path,
+    path: &str,
+) -> Result<bool> {
+    let is_dir = path.ends_with('/');
+    let relative_path = Path::new(path.trim_end_matches('/'));
+    let is_ignored = gitignore.is_match(relative_path, is_dir);
+
+    let status = if is_ignored { "ignored" } else { "not ignored" };
+    match gitignore.explain(relative_path, is_dir) {
+        Some(rule) => writeln!(
+            writer,
+            "{}: {} by {}:{}: {}",
+            path,
+            status,
+            ignore_file.display(),
+            rule.line_num,
+            rule.pattern
+        )?,
+        None => writeln!(writer, "{}: {}", path, status)?,
+    }
+
+    Ok(is_ignored)
+}
@@ -0,0 +1,115 @@
+use std::io::Write;
+use std::process::ExitCode;
+
+use anyhow::Result;
+use clap::Parser;
+use grrs::grep::matcher::{CaseMode, MatchOptions, PatternSet, find_matches};
+
+/// Checks if a pattern matches a string, without searching any files.
+#[derive(Parser)]
+pub struct MatchCommand {
+    pattern: String,
+    string: String,
+
+    // Flags
+    #[arg(
+        short = 'i',
+        long = "ignore-case",
+        default_value_t = false,
+        help = "ignore case"
+    )]
+    ignore_case: bool,
+    #[arg(
+        short = 'F',
+        long = "fixed-strings",
+        default_value_t = false,
+        help = "Treats the pattern as a literal string."
+    )]
+    fixed_strings: bool,
+    #[arg(
+        short = 'w',
+        long = "word-regexp",
+        default_value_t = false,
+        conflicts_with = "line_regexp",
+        help = "Only matches whole words."
+    )]
+    word_regexp: bool,
+    #[arg(
+        short = 'x',
+        long = "line-regexp",
+        default_value_t = false,
+        help = "Only matches whole lines."
+    )]
+    line_regexp: bool,
+    #[arg(
+        short = 'o',
+        long = "only-matching",
+        default_value_t = false,
+        help = "Prints each match on its own line, instead of the whole string."
+    )]
+    only_matching: bool,
+    #[arg(
+        long = "column",
+        default_value_t = false,
+        help = "Prints the (1-indexed) byte column of each match."
+    )]
+    column: bool,
+}
+
+impl MatchCommand {
+    pub fn run(self) -> Result<ExitCode> {
+        let options = MatchOptions {
+            case: if self.ignore_case {
+                CaseMode::Insensitive
+            } else {
+                CaseMode::Sensitive
+            },
+            fixed_strings: self.fixed_strings,
+            word_regexp: self.word_regexp,
+            line_regexp: self.line_regexp,
+            ..Default::default()
+        };
+
+        let num_matches = if self.only_matching || self.column {
+            let patterns = PatternSet::new(std::slice::from_ref(&self.pattern), &options)?;
+
+            let mut stdout = std::io::stdout().lock();
+            let mut num_matches = 0;
+            for line in self.string.lines() {
+                let submatches = patterns.find_submatches(line, None);
+                if submatches.is_empty() {
+                    continue;
+                }
+                num_matches += 1;
+
+                if !self.only_matching {
+                    writeln!(stdout, "{}:{}", submatches[0].byte_start + 1, line)?;
+                    continue;
+                }
+                for submatch in submatches {
+                    if self.column {
+                        writeln!(stdout, "{}:{}", submatch.byte_start + 1, submatch.text)?;
+                    } else {
+                        writeln!(stdout, "{}", submatch.text)?;
+                    }
+                }
+            }
+            num_matches
+        } else {
+            let patterns = PatternSet::new(std::slice::from_ref(&self.pattern), &options)?;
+            find_matches(
+                self.string.as_bytes(),
+                std::io::stdout().lock(),
+                None,
+                &patterns,
+                &options,
+            )?
+        };
+
+        if num_matches > 0 {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
@@ -1,7 +1,15 @@
+use std::process::ExitCode;
+
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 
+mod args;
+mod check_ignore;
+mod files;
 mod grep;
+mod ignore_test;
+mod r#match;
+mod replace;
 
 #[derive(Parser)]
 struct Application {
@@ -14,15 +22,26 @@ struct Application {
 
 #[derive(Subcommand)]
 enum Program {
-    Grep(grep::GrepCommand),
+    // Boxed because they have far more flags than the other commands
+    Grep(Box<grep::GrepCommand>),
+    Files(Box<files::FilesCommand>),
+    Replace(Box<replace::ReplaceCommand>),
+    Match(r#match::MatchCommand),
+    IgnoreTest(ignore_test::IgnoreTestCommand),
+    CheckIgnore(check_ignore::CheckIgnoreCommand),
 }
 
-pub fn run() -> Result<()> {
+pub fn run() -> Result<ExitCode> {
     let application = Application::parse();
 
     // TODO: Set up verbose
 
     match application.program {
         Program::Grep(cmd) => cmd.run(),
+        Program::Files(cmd) => cmd.run().map(|_| ExitCode::SUCCESS),
+        Program::Match(cmd) => cmd.run(),
+        Program::Replace(cmd) => cmd.run(),
+        Program::IgnoreTest(cmd) => cmd.run(),
+        Program::CheckIgnore(cmd) => cmd.run(),
     }
 }
@@ -0,0 +1,195 @@
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use grrs::core::ignore::WalkOptions;
+use grrs::core::threads::ThreadPool;
+use grrs::core::writer::SharedWriter;
+use grrs::grep::matcher::{CaseMode, MatchOptions, build_regex};
+use grrs::grep::replace::Template;
+use grrs::grep::rewrite::{replace_lines, write_atomically};
+use log::{debug, error};
+use regex::Regex;
+
+/// Replaces the matches of a pattern in every searched file, in place.
+///
+/// Prints each file that changed, with the number of lines that changed, like `PATH:COUNT`.
+#[derive(Parser)]
+pub struct ReplaceCommand {
+    pattern: String,
+    #[arg(
+        help = "What to replace each match with. Capture groups are referenced with $1 or ${name}, like with grep -r."
+    )]
+    replacement: String,
+    path: Option<PathBuf>,
+
+    // Flags
+    #[command(flatten)]
+    walk: super::args::WalkArgs,
+    #[arg(
+        short = 'i',
+        long = "ignore-case",
+        default_value_t = false,
+        help = "ignore case"
+    )]
+    ignore_case: bool,
+    #[arg(
+        short = 'F',
+        long = "fixed-strings",
+        default_value_t = false,
+        help = "Treats the pattern as a literal string."
+    )]
+    fixed_strings: bool,
+    #[arg(
+        short = 'w',
+        long = "word-regexp",
+        default_value_t = false,
+        help = "Only matches whole words."
+    )]
+    word_regexp: bool,
+    #[arg(
+        long = "dry-run",
+        default_value_t = false,
+        help = "Prints the files that would change, without changing them."
+    )]
+    dry_run: bool,
+    #[arg(
+        long = "backup",
+        default_value_t = false,
+        help = "Keeps a copy of each file that changes, as FILE.bak. The backups of earlier runs are not rewritten."
+    )]
+    backup: bool,
+    #[arg(
+        short = 'j',
+        long = "threads",
+        value_name = "NUM",
+        help = "Rewrites NUM files at a time. With 1, the files are printed in order of their paths."
+    )]
+    threads: Option<NonZeroUsize>,
+}
+
+impl ReplaceCommand {
+    pub fn run(self) -> Result<ExitCode> {
+        if self.walk.print_type_list() {
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let options = MatchOptions {
+            case: if self.ignore_case {
+                CaseMode::Insensitive
+            } else {
+                CaseMode::Sensitive
+            },
+            fixed_strings: self.fixed_strings,
+            word_regexp: self.word_regexp,
+            ..Default::default()
+        };
+        let regex = Arc::new(build_regex(&self.pattern, &options)?);
+        let template = Arc::new(Template::parse(&self.replacement)?);
+
+        let sorted = self.threads.is_some_and(|threads| threads.get() == 1);
+        let thread_pool = match self.threads {
+            Some(threads) => ThreadPool::new(threads.get()),
+            None => ThreadPool::all_cores(),
+        };
+        let walk_options = WalkOptions {
+            sorted,
+            ..self.walk.options()?
+        };
+        let output = SharedWriter::new(std::io::stdout());
+        let had_error = Arc::new(AtomicBool::new(false));
+        let is_found = Arc::new(AtomicBool::new(false));
+
+        let path = self.path.unwrap_or(PathBuf::from("."));
+        let walked = (|| -> Result<()> {
+            for file_path in grrs::core::ignore::walk_iter(path, &walk_options)? {
+                let file_path = file_path?;
+                if self.backup && is_backup(&file_path) {
+                    debug!("skipped backup {}", file_path.display());
+                    continue;
+                }
+                let regex = Arc::clone(&regex);
+                let template = Arc::clone(&template);
+                let mut output = output.clone();
+                let had_error = Arc::clone(&had_error);
+                let is_found = Arc::clone(&is_found);
+
+                thread_pool.execute(move || {
+                    match rewrite_file(&file_path, &regex, &template, self.dry_run, self.backup) {
+                        Ok(0) => {}
+                        Ok(num_changed) => {
+                            is_found.store(true, Ordering::Relaxed);
+                            let line = format!("{}:{}\n", file_path.display(), num_changed);
+                            let _ = output.write_all(line.as_bytes());
+                        }
+                        Err(err) => {
+                            error!("{:#}", err);
+                            had_error.store(true, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+            Ok(())
+        })();
+
+        // The files that were found before a walk failed are still rewritten
+        if let Err(errors) = thread_pool.wait() {
+            for err in errors {
+                error!("{}", err);
+            }
+            had_error.store(true, Ordering::Relaxed);
+        }
+
+        walked?;
+        if had_error.load(Ordering::Relaxed) {
+            bail!("some files could not be rewritten");
+        }
+        Ok(if is_found.load(Ordering::Relaxed) {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        })
+    }
+}
+
+/// Checks if `path` looks like a backup that `--backup` made, i.e. `FILE.bak` next to `FILE`. With
+/// `--backup`, these are not rewritten, so that those of an earlier run are kept as they were.
+fn is_backup(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "bak")
+        && path.with_extension("").is_file()
+}
+
+/// Replaces the matches of `regex` in the file at `path`, unless `dry_run`, and returns the
+/// number of lines that changed. The original file is copied to `FILE.bak` first if `backup`.
+fn rewrite_file(
+    path: &Path,
+    regex: &Regex,
+    template: &Template,
+    dry_run: bool,
+    backup: bool,
+) -> Result<usize> {
+    let bytes = std::fs::read(path).with_context(|| format!("could not read file {:?}", path))?;
+    // Other encodings are not rewritten, since replacing in them could corrupt them
+    let contents = String::from_utf8(bytes)
+        .with_context(|| format!("could not rewrite file {:?}: it is not UTF-8", path))?;
+
+    let (replaced, num_changed) = replace_lines(&contents, regex, template, Some(path));
+    if num_changed == 0 || dry_run {
+        return Ok(num_changed);
+    }
+
+    if backup {
+        let mut backup_path = path.as_os_str().to_owned();
+        backup_path.push(".bak");
+        std::fs::copy(path, &backup_path)
+            .with_context(|| format!("could not back up file {:?}", path))?;
+    }
+    write_atomically(path, replaced.as_bytes())?;
+
+    Ok(num_changed)
+}
@@ -0,0 +1,40 @@
+//! ANSI color codes for highlighting output.
+
+use std::io::IsTerminal;
+
+pub const RESET: &str = "\x1b[0m";
+pub const MATCH: &str = "\x1b[1;31m";
+pub const PATH: &str = "\x1b[35m";
+pub const LINE_NUMBER: &str = "\x1b[32m";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Colors only if stdout is a terminal.
+    Auto,
+    Always,
+    /// Never colors.
+    // The library's default, so that embedders never get color codes unless they ask for them.
+    // The CLI defaults to `Auto` instead
+    #[default]
+    Never,
+}
+
+impl ColorChoice {
+    /// Checks if output should be colored.
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            ColorChoice::Auto => std::io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Appends `text` wrapped in `style` and a reset to `buf`.
+///
+/// The style and the reset are always written together, so a reset is never left dangling.
+pub fn paint(buf: &mut String, style: &str, text: &str) {
+    buf.push_str(style);
+    buf.push_str(text);
+    buf.push_str(RESET);
+}
@@ -0,0 +1,33 @@
+//! Transparent decompression of compressed files.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+
+/// Checks if `path` is gzip compressed, going by its extension.
+pub fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+/// Wraps `file` in a decoder if `path` is compressed, so that its decompressed contents are read.
+pub fn reader(path: &Path, file: File) -> Box<dyn Read + Send> {
+    if is_gzip(path) {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_gzip() {
+        assert!(is_gzip(Path::new("app.log.2.gz")));
+        assert!(!is_gzip(Path::new("app.log.2")));
+        assert!(!is_gzip(Path::new("gz")));
+    }
+}
@@ -1,4 +1,4 @@
-/// Utility functions for string operations that support escaping.
+//! Utility functions for string operations that support escaping.
 
 // We do not always support using escape char as a target.
 const ESCAPE_CHAR: char = '\\';
@@ -11,11 +11,7 @@ pub fn find_char(string: &str, target_char: char) -> Option<usize> {
             return Some(idx);
         }
 
-        if c == ESCAPE_CHAR && !escaped {
-            escaped = true;
-        } else {
-            escaped = false;
-        }
+        escaped = c == ESCAPE_CHAR && !escaped;
     }
 
     None
@@ -104,7 +100,7 @@ pub fn split(string: &str, split_by: char) -> Split<'_> {
 
     Split {
         chars: string.chars(),
-        split_by: split_by,
+        split_by,
         is_escaped: false,
         done: false,
     }
@@ -0,0 +1,334 @@
+//! File types for filtering the searched files, like ripgrep's `--type`.
+
+use std::path::Path;
+use std::str::FromStr;
+
+/// A named file type, recognized by its extensions or the interpreter in its shebang.
+struct FileType {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    interpreters: &'static [&'static str],
+}
+
+const FILE_TYPES: &[FileType] = &[
+    FileType {
+        name: "c",
+        extensions: &["c", "h"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "cpp",
+        extensions: &["cpp", "cc", "cxx", "hpp", "hh", "hxx"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "go",
+        extensions: &["go"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "java",
+        extensions: &["java"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "js",
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        interpreters: &["node", "nodejs"],
+    },
+    FileType {
+        name: "json",
+        extensions: &["json"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "md",
+        extensions: &["md", "markdown"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "perl",
+        extensions: &["pl", "pm"],
+        interpreters: &["perl"],
+    },
+    FileType {
+        name: "py",
+        extensions: &["py", "pyi"],
+        interpreters: &["python"],
+    },
+    FileType {
+        name: "ruby",
+        extensions: &["rb"],
+        interpreters: &["ruby"],
+    },
+    FileType {
+        name: "rust",
+        extensions: &["rs"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "sh",
+        extensions: &["sh", "bash", "zsh", "ksh"],
+        interpreters: &["sh", "bash", "zsh", "ksh", "dash"],
+    },
+    FileType {
+        name: "toml",
+        extensions: &["toml"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "txt",
+        extensions: &["txt"],
+        interpreters: &[],
+    },
+    FileType {
+        name: "yaml",
+        extensions: &["yaml", "yml"],
+        interpreters: &[],
+    },
+];
+
+/// A file type defined by the user, like ripgrep's `--type-add`, e.g. `web:html,css`.
+///
+/// If there is a built-in type with the same name, the extensions are added to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeDef {
+    pub name: String,
+    pub extensions: Vec<String>,
+}
+
+impl FromStr for TypeDef {
+    type Err = String;
+
+    /// Parses `NAME:EXT[,EXT...]`, where each extension can also be written as a glob like `*.ext`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid file type {:?}, expected NAME:EXT[,EXT...]", s);
+        let (name, extensions) = s.split_once(':').ok_or_else(invalid)?;
+        let extensions: Vec<String> = extensions
+            .split(',')
+            .map(|extension| extension.trim().trim_start_matches("*.").to_string())
+            .collect();
+        if name.is_empty() || extensions.iter().any(|extension| extension.is_empty()) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            extensions,
+        })
+    }
+}
+
+fn find(name: &str) -> Option<&'static FileType> {
+    FILE_TYPES.iter().find(|file_type| file_type.name == name)
+}
+
+/// Checks if `name` is a known file type, either built in or in `type_defs`.
+pub fn is_known(name: &str, type_defs: &[TypeDef]) -> bool {
+    find(name).is_some() || type_defs.iter().any(|type_def| type_def.name == name)
+}
+
+/// Returns the names of all known file types, in order, including those in `type_defs`.
+pub fn names(type_defs: &[TypeDef]) -> Vec<&str> {
+    let mut names: Vec<&str> = FILE_TYPES.iter().map(|file_type| file_type.name).collect();
+    names.extend(type_defs.iter().map(|type_def| type_def.name.as_str()));
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// Describes each known file type on its own line, e.g. `sh: *.sh, *.bash, #!sh, #!bash`.
+pub fn describe(type_defs: &[TypeDef]) -> Vec<String> {
+    names(type_defs)
+        .into_iter()
+        .map(|name| {
+            let mut rules: Vec<String> = Vec::new();
+            let file_type = find(name);
+            let extensions = file_type
+                .into_iter()
+                .flat_map(|file_type| file_type.extensions.iter().copied())
+                .chain(
+                    type_defs
+                        .iter()
+                        .filter(|type_def| type_def.name == name)
+                        .flat_map(|type_def| type_def.extensions.iter().map(String::as_str)),
+                );
+            for extension in extensions {
+                rules.push(format!("*.{}", extension));
+            }
+            for interpreter in file_type
+                .into_iter()
+                .flat_map(|file_type| file_type.interpreters)
+            {
+                rules.push(format!("#!{}", interpreter));
+            }
+            format!("{}: {}", name, rules.join(", "))
+        })
+        .collect()
+}
+
+/// Finds the interpreter named by the shebang at the start of `sample`, without its version.
+///
+/// For example, both `#!/usr/bin/python3.12` and `#!/usr/bin/env -S python3 -u` give `python`.
+fn shebang_interpreter(sample: &[u8]) -> Option<&str> {
+    let sample = sample.strip_prefix(b"#!")?;
+    let line = sample.split(|&b| b == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // The interpreter is the first argument to env that is not an option
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+
+    let interpreter = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    (!interpreter.is_empty()).then_some(interpreter)
+}
+
+/// Checks if the file at `path`, starting with `sample`, is any of the file types in `names`,
+/// where the types can also be (or be extended by) those in `type_defs`.
+///
+/// Files are recognized by their extension, or failing that, by their shebang.
+pub fn matches_any(path: &Path, sample: &[u8], names: &[String], type_defs: &[TypeDef]) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    if let Some(extension) = extension
+        && type_defs.iter().any(|type_def| {
+            names.contains(&type_def.name)
+                && type_def
+                    .extensions
+                    .iter()
+                    .any(|type_extension| type_extension == extension)
+        })
+    {
+        return true;
+    }
+
+    let mut interpreter = None;
+    names.iter().filter_map(|name| find(name)).any(|file_type| {
+        if let Some(extension) = extension {
+            return file_type.extensions.contains(&extension);
+        }
+
+        let interpreter = *interpreter.get_or_insert_with(|| shebang_interpreter(sample));
+        interpreter.is_some_and(|interpreter| file_type.interpreters.contains(&interpreter))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shebang_interpreter() {
+        assert_eq!(shebang_interpreter(b"#!/bin/sh\necho hi\n"), Some("sh"));
+        assert_eq!(shebang_interpreter(b"#! /bin/bash -e\n"), Some("bash"));
+        assert_eq!(
+            shebang_interpreter(b"#!/usr/bin/env python3\n"),
+            Some("python")
+        );
+        assert_eq!(
+            shebang_interpreter(b"#!/usr/bin/env -S python3.12 -u\n"),
+            Some("python")
+        );
+        assert_eq!(shebang_interpreter(b"#!/usr/bin/env\n"), None);
+        assert_eq!(shebang_interpreter(b"echo hi\n"), None);
+        assert_eq!(shebang_interpreter(b""), None);
+    }
+
+    #[test]
+    fn test_type_def() {
+        assert_eq!(
+            "web:html,*.css".parse(),
+            Ok(TypeDef {
+                name: String::from("web"),
+                extensions: vec![String::from("html"), String::from("css")],
+            })
+        );
+        assert!("web".parse::<TypeDef>().is_err());
+        assert!(":html".parse::<TypeDef>().is_err());
+        assert!("web:".parse::<TypeDef>().is_err());
+        assert!("web:html,".parse::<TypeDef>().is_err());
+    }
+
+    #[test]
+    fn test_type_defs() {
+        let type_defs: Vec<TypeDef> =
+            vec!["web:html,css".parse().unwrap(), "rust:ron".parse().unwrap()];
+        let types =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|s| s.to_string()).collect() };
+
+        assert!(is_known("web", &type_defs));
+        assert!(!is_known("web", &[]));
+        assert!(matches_any(
+            Path::new("a.css"),
+            b"",
+            &types(&["web"]),
+            &type_defs
+        ));
+        assert!(!matches_any(
+            Path::new("a.css"),
+            b"",
+            &types(&["rust"]),
+            &type_defs
+        ));
+        // Extends the built-in type
+        assert!(matches_any(
+            Path::new("a.ron"),
+            b"",
+            &types(&["rust"]),
+            &type_defs
+        ));
+        assert!(matches_any(
+            Path::new("a.rs"),
+            b"",
+            &types(&["rust"]),
+            &type_defs
+        ));
+
+        let described = describe(&type_defs);
+        assert!(described.contains(&String::from("rust: *.rs, *.ron")));
+        assert!(described.contains(&String::from("web: *.html, *.css")));
+        assert!(described.contains(&String::from("perl: *.pl, *.pm, #!perl")));
+    }
+
+    #[test]
+    fn test_matches_any() {
+        let types =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|s| s.to_string()).collect() };
+
+        assert!(matches_any(Path::new("a.py"), b"", &types(&["py"]), &[]));
+        assert!(matches_any(
+            Path::new("a.rs"),
+            b"",
+            &types(&["py", "rust"]),
+            &[]
+        ));
+        assert!(!matches_any(Path::new("a.rs"), b"", &types(&["py"]), &[]));
+        // Shebangs are only consulted without an extension
+        assert!(matches_any(
+            Path::new("run"),
+            b"#!/bin/sh\n",
+            &types(&["sh"]),
+            &[]
+        ));
+        assert!(!matches_any(
+            Path::new("run"),
+            b"#!/bin/sh\n",
+            &types(&["py"]),
+            &[]
+        ));
+        assert!(!matches_any(
+            Path::new("run.txt"),
+            b"#!/bin/sh\n",
+            &types(&["sh"]),
+            &[]
+        ));
+        assert!(!matches_any(
+            Path::new("run"),
+            b"echo hi\n",
+            &types(&["sh"]),
+            &[]
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+//! Asks git about the state of a repository, and about the user's git config.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+
+/// Lists the files under `path` that are tracked by git (i.e. in the index), or `path` itself if
+/// it is a tracked file. Untracked files are skipped even if they are not ignored.
+///
+/// Shells out to `git ls-files`, so git has to be installed.
+pub fn tracked_files(path: &Path) -> Result<Vec<PathBuf>> {
+    // git runs in the directory, so the files it lists are relative to it
+    let (dir, pathspec) = if path.is_dir() {
+        (path, Path::new("."))
+    } else {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        (dir, Path::new(path.file_name().unwrap_or(path.as_os_str())))
+    };
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["ls-files", "-z", "--"])
+        .arg(pathspec)
+        .output()
+        .context("could not run git")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("not a git repository") {
+            bail!("{} is not in a git repository", path.display());
+        }
+        bail!("git ls-files failed: {}", stderr.trim());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let base = if path.is_dir() { path } else { dir };
+    Ok(stdout
+        .split('\0')
+        .filter(|file| !file.is_empty())
+        .map(|file| base.join(file))
+        .collect())
+}
+
+/// Finds the root of the git repository that the directory `dir` is in, i.e. the closest of `dir`
+/// and its ancestors with a `.git` directory (or a `.git` file, for submodules and worktrees).
+///
+/// `dir` should be canonical, since `..` components are not resolved.
+pub fn repo_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").symlink_metadata().is_ok())
+}
+
+/// Finds the user's global ignore file, like git does: `core.excludesFile` in the global git
+/// config (or in `$GIT_CONFIG`, if set), or else `$XDG_CONFIG_HOME/git/ignore` (which defaults
+/// to `~/.config/git/ignore`). The file might not exist.
+///
+/// The config files are read directly rather than with `git config`, so git does not have to be
+/// installed. Includes are not followed.
+pub fn global_excludes_file() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+
+    // In increasing precedence, like git
+    let config_paths = match std::env::var_os("GIT_CONFIG") {
+        Some(config_path) => vec![PathBuf::from(config_path)],
+        None => [
+            config_home.as_ref().map(|dir| dir.join("git/config")),
+            home.as_ref().map(|home| home.join(".gitconfig")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+    };
+
+    config_paths
+        .iter()
+        .rev()
+        .filter_map(|config_path| std::fs::read_to_string(config_path).ok())
+        .find_map(|config| excludes_file(&config, home.as_deref()))
+        .or_else(|| config_home.map(|dir| dir.join("git/ignore")))
+}
+
+/// Finds the last `core.excludesFile` in a git config file, expanding a leading `~/`.
+fn excludes_file(config: &str, home: Option<&Path>) -> Option<PathBuf> {
+    let mut is_core = false;
+    let mut excludes_file = None;
+    for line in config.lines() {
+        let line = line.trim();
+        if let Some(section) = line.strip_prefix('[') {
+            // Section names are case insensitive, e.g. `[core]` or `[Core]`
+            let name = section.split(']').next().unwrap_or_default();
+            is_core = name.trim().eq_ignore_ascii_case("core");
+            continue;
+        }
+        if !is_core {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        // Keys are case insensitive too
+        if !key.trim().eq_ignore_ascii_case("excludesfile") {
+            continue;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        excludes_file = match (value.strip_prefix("~/"), home) {
+            (Some(relative_path), Some(home)) => Some(home.join(relative_path)),
+            _ => Some(PathBuf::from(value)),
+        };
+    }
+
+    excludes_file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn git(dir: &Path, args: &[&str]) -> bool {
+        Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    #[test]
+    fn test_repo_root() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        std::fs::create_dir_all(dir.join("repo/src/sub")).unwrap();
+        std::fs::create_dir_all(dir.join("repo/sub")).unwrap();
+        std::fs::write(dir.join("repo/sub/.git"), "gitdir: ../.git/modules/sub\n").unwrap();
+
+        let repo = dir.join("repo");
+        assert_eq!(repo_root(&repo), Some(repo.as_path()));
+        assert_eq!(repo_root(&repo.join("src/sub")), Some(repo.as_path()));
+        assert_eq!(
+            repo_root(&repo.join("sub")),
+            Some(repo.join("sub").as_path())
+        );
+    }
+
+    #[test]
+    fn test_tracked_files() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        if !git(dir, &["init", "-q"]) {
+            // git is not installed
+            return;
+        }
+        std::fs::write(dir.join("tracked.txt"), "a\n").unwrap();
+        std::fs::write(dir.join("src/tracked.rs"), "a\n").unwrap();
+        std::fs::write(dir.join("untracked.txt"), "a\n").unwrap();
+        assert!(git(dir, &["add", "-f", "tracked.txt", "src/tracked.rs"]));
+
+        let mut files = tracked_files(dir).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![dir.join("src/tracked.rs"), dir.join("tracked.txt")]
+        );
+
+        assert_eq!(
+            tracked_files(&dir.join("src")).unwrap(),
+            vec![dir.join("src/tracked.rs")]
+        );
+        assert_eq!(
+            tracked_files(&dir.join("tracked.txt")).unwrap(),
+            vec![dir.join("tracked.txt")]
+        );
+        assert!(
+            tracked_files(&dir.join("untracked.txt"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_excludes_file() {
+        let home = Path::new("/home/user");
+        let config = "[user]\n\tname = a\n\texcludesFile = /not/core\n[core]\n\tautocrlf = false\n\texcludesFile = ~/.gitignore_global\n";
+        assert_eq!(
+            excludes_file(config, Some(home)),
+            Some(PathBuf::from("/home/user/.gitignore_global"))
+        );
+
+        // The last one wins, and keys and sections are case insensitive
+        let config = "[core]\nexcludesfile = /a\n[CORE]\n  EXCLUDESFILE = \"/b c\"\n";
+        assert_eq!(
+            excludes_file(config, Some(home)),
+            Some(PathBuf::from("/b c"))
+        );
+
+        assert_eq!(excludes_file("[user]\nname = a\n", Some(home)), None);
+        assert_eq!(excludes_file("", None), None);
+    }
+
+    #[test]
+    fn test_tracked_files_not_a_repository() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        if !git(dir, &["--version"]) || git(dir, &["rev-parse", "--git-dir"]) {
+            // git is not installed, or the temp dir happens to be in a repository
+            return;
+        }
+
+        let err = tracked_files(dir).unwrap_err();
+        assert!(err.to_string().contains("is not in a git repository"));
+    }
+}
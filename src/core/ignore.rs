@@ -1,20 +1,30 @@
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
 use std::io::BufRead;
+use std::io::ErrorKind;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use anyhow::{Context, Result};
-use log::warn;
-use regex::RegexSet; // TODO: Rewrite this crate to not depend on this :)
+use log::{debug, warn};
+
+use super::file_types::{self, TypeDef};
+use super::regex_cache::RegexCache;
+use regex::{RegexSet, RegexSetBuilder}; // TODO: Rewrite this crate to not depend on this :)
 
 // Check why a file is ignored.
 // git check-ignore -v <FILE> [FILE...]
 
 const DIR_SEP: char = '/';
+/// The number of bytes at the start of a file that are checked to decide if it is text.
+pub const PROBE_SIZE: usize = 1024;
 
 /// Removes comment from a pattern.
 fn remove_comment(pattern: &str) -> &str {
     // Finds a comment from a pattern.
-    let Some(comment_index) = super::escaped_strings::find_char(&pattern, '#') else {
+    let Some(comment_index) = super::escaped_strings::find_char(pattern, '#') else {
         return pattern;
     };
 
@@ -55,7 +65,7 @@ fn convert_part(part: &str) -> Option<String> {
                 regex.push(c);
             }
             '*' => regex.push_str(r".*"),
-            '?' => regex.push_str(r"."),
+            '?' => regex.push('.'),
             '\\' => {
                 regex.push(c);
                 is_escaped = !is_escaped;
@@ -68,6 +78,28 @@ fn convert_part(part: &str) -> Option<String> {
     if is_escaped { None } else { Some(regex) }
 }
 
+/// Checks if a pattern, split into `parts` at each separator, is anchored to the directory of its
+/// ignore file instead of matching at any level below it.
+fn is_anchored(parts: &[String]) -> bool {
+    // Has separator at the beginning or middle (or both)
+    // => has a non-ending separator
+    // => has multiple separators or the only separator is not an ending separator
+    let has_multiple_separators = parts.len() >= 3;
+    let has_non_ending_separator = parts.len() >= 2 && !parts[parts.len() - 1].is_empty();
+
+    // 1. If there is a separator at the beginning or middle (or both) of the pattern, then the pattern is relative to the directory level of the particular .gitignore file itself. Otherwise the pattern may also match at any level below the .gitignore level.
+    // e.g. `dir/a.txt` matches `dir/a.txt` but not `dir2/dir/a.txt`.
+    // e.g. `dir/`      matches `dir/`      and     `dir2/dir/`.
+    // 2. leading `**/` overrides rule 1
+    (has_multiple_separators || has_non_ending_separator) && parts[0] != "**"
+}
+
+/// Checks if a pattern, split into `parts` at each separator, ends with a separator, so that it
+/// only matches directories.
+fn is_dir_only(parts: &[String]) -> bool {
+    parts.len() >= 2 && parts[parts.len() - 1].is_empty()
+}
+
 /// Converts pattern from gitignore syntax to regex syntax.
 /// Invalid patterns will return None.
 ///
@@ -76,12 +108,7 @@ fn convert_pattern(pattern: &str) -> Option<String> {
     let parts: Vec<String> = super::escaped_strings::split(pattern, DIR_SEP).collect();
 
     let mut regex = String::new();
-
-    // Has separator at the beginning or middle (or both)
-    // => has a non-ending separator
-    // => has multiple separators or the only separator is not an ending separator
-    let has_multiple_separators = parts.len() >= 3;
-    let has_non_ending_separator = parts.len() >= 2 && !parts[parts.len() - 1].is_empty();
+    let is_anchored = is_anchored(&parts);
 
     for (i, part) in parts.iter().enumerate() {
         let is_leading = i == 0;
@@ -89,11 +116,7 @@ fn convert_pattern(pattern: &str) -> Option<String> {
         let is_double_asterisk = *part == "**";
 
         if is_leading {
-            if (has_multiple_separators || has_non_ending_separator) && !is_double_asterisk {
-                // 1. If there is a separator at the beginning or middle (or both) of the pattern, then the pattern is relative to the directory level of the particular .gitignore file itself. Otherwise the pattern may also match at any level below the .gitignore level.
-                // e.g. `dir/a.txt` matches `dir/a.txt` but not `dir2/dir/a.txt`.
-                // e.g. `dir/`      matches `dir/`      and     `dir2/dir/`.
-                // 2. leading `**/` overrides rule 1
+            if is_anchored {
                 regex.push('^');
                 if part.is_empty() {
                     // Avoid adding a `/`
@@ -140,55 +163,192 @@ fn convert_pattern(pattern: &str) -> Option<String> {
     Some(regex)
 }
 
-struct GitIgnore {
+/// A pattern in gitignore syntax, as used by both ignore files and the globs of a walk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    /// The pattern (without its `!`) in regex syntax, see `convert_pattern`.
+    regex: String,
+    is_negated: bool,
+    is_dir_only: bool,
+    is_anchored: bool,
+}
+
+impl Glob {
+    /// Parses a pattern that was already cleaned (see `clean_pattern`). Returns None if it
+    /// matches nothing, i.e. if it is blank or invalid.
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let (is_negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let parts: Vec<String> = super::escaped_strings::split(pattern, DIR_SEP).collect();
+        Some(Self {
+            regex: convert_pattern(pattern)?,
+            is_negated,
+            is_dir_only: is_dir_only(&parts),
+            is_anchored: is_anchored(&parts),
+        })
+    }
+
+    /// Checks if the pattern starts with `!`, i.e. it re-includes the paths that it matches.
+    pub fn is_negated(&self) -> bool {
+        self.is_negated
+    }
+
+    /// Checks if the pattern ends with `/`, i.e. it only matches directories (and their contents).
+    pub fn is_dir_only(&self) -> bool {
+        self.is_dir_only
+    }
+
+    /// Checks if the pattern only matches relative to its root (e.g. the directory of its ignore
+    /// file), rather than at any level below it.
+    pub fn is_anchored(&self) -> bool {
+        self.is_anchored
+    }
+}
+
+/// Globs that are compiled together, so that a path is matched against all of them at once.
+struct GlobSet {
+    globs: Vec<Glob>,
+    set: RegexSet,
+}
+
+impl GlobSet {
+    /// Compiles `globs`, reusing the compiled sets in the regex cache, if any.
+    fn new(globs: Vec<Glob>, options: &WalkOptions) -> Result<Self> {
+        let patterns: Vec<String> = globs.iter().map(|glob| glob.regex.clone()).collect();
+        let set = match &options.regex_cache {
+            Some(cache) => cache.regex_set(&patterns, options.case_insensitive_globs),
+            None => RegexSetBuilder::new(patterns)
+                .case_insensitive(options.case_insensitive_globs)
+                .build(),
+        };
+
+        Ok(Self { globs, set: set? })
+    }
+
+    fn empty() -> Self {
+        Self {
+            globs: Vec::new(),
+            set: RegexSet::empty(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.globs.is_empty()
+    }
+
+    /// Checks if any glob matches `path`, which is relative to the root of the globs (see
+    /// `relative_path`).
+    fn is_match(&self, path: &str) -> bool {
+        self.set.is_match(path)
+    }
+
+    /// Finds the index of the last glob that matches `path`, which is the one that decides it,
+    /// like in git.
+    fn last_match(&self, path: &str) -> Option<usize> {
+        self.set.matches(path).iter().next_back()
+    }
+}
+
+/// Converts `path` to the string that patterns relative to `root_path` are matched against, with
+/// a trailing `/` for directories. A leading `./` is ignored, in both paths.
+fn relative_path<'a>(path: &'a Path, root_path: &Path, is_dir: bool) -> Cow<'a, str> {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    let root_path = root_path.strip_prefix("./").unwrap_or(root_path);
+    let path = path.strip_prefix(root_path).unwrap_or(path);
+
+    let mut path = path.to_string_lossy();
+    if is_dir {
+        path.to_mut().push('/');
+    }
+    path
+}
+
+/// A pattern in an ignore file, as written (without comments and trailing spaces).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// The 1-based line number of the pattern.
+    pub line_num: usize,
+    pub pattern: String,
+}
+
+impl Rule {
+    /// Checks if the rule is negated with a leading `!`, i.e. it un-ignores paths.
+    pub fn is_negated(&self) -> bool {
+        self.pattern.starts_with('!')
+    }
+}
+
+pub struct GitIgnore {
+    // The ignore file that the rules were read from, if any
+    source: PathBuf,
     root_path: PathBuf,
-    include_patterns: RegexSet,
-    exclude_patterns: RegexSet,
+    // The path of `root_path` relative to the directory of the ignore file, for the ignore files
+    // in the parent directories of a walk (see `parent_ignore_files`), which are outside of it
+    prefix: PathBuf,
+    // Negated globs are kept in order with the rest, since the last matching glob wins
+    globs: GlobSet,
+    // The rule of each glob in the set, in the same order
+    rules: Vec<Rule>,
 }
 
 impl GitIgnore {
     pub fn empty() -> Self {
         Self {
+            source: PathBuf::new(),
             root_path: PathBuf::new(),
-            include_patterns: RegexSet::empty(),
-            exclude_patterns: RegexSet::empty(),
+            prefix: PathBuf::new(),
+            globs: GlobSet::empty(),
+            rules: Vec::new(),
         }
     }
 
     pub fn from<R: BufRead>(ignore_path: PathBuf, reader: R) -> Result<Self> {
-        let mut include_patterns = Vec::new();
-        let mut exclude_patterns = Vec::new();
+        Self::from_reader(ignore_path, reader, &WalkOptions::default())
+    }
+
+    pub fn from_reader<R: BufRead>(
+        ignore_path: PathBuf,
+        reader: R,
+        options: &WalkOptions,
+    ) -> Result<Self> {
+        let mut globs = Vec::new();
+        let mut rules = Vec::new();
 
-        for pattern in reader.lines() {
-            let pattern = pattern?;
-            let pattern = clean_pattern(&pattern);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let pattern = clean_pattern(&line);
 
             // A blank line matches no files
             if pattern.is_empty() {
                 continue;
             }
 
-            if pattern.starts_with('!') {
-                let Some(pattern) = convert_pattern(&pattern[1..]) else {
-                    continue;
-                };
-                exclude_patterns.push(pattern);
-            } else {
-                let Some(pattern) = convert_pattern(&pattern) else {
-                    continue;
-                };
-                include_patterns.push(pattern);
-            }
+            let Some(glob) = Glob::parse(pattern) else {
+                continue;
+            };
+            globs.push(glob);
+            rules.push(Rule {
+                line_num: i + 1,
+                pattern: pattern.to_string(),
+            });
         }
 
         Ok(Self {
+            source: PathBuf::new(),
             root_path: ignore_path,
-            include_patterns: RegexSet::new(include_patterns)?,
-            exclude_patterns: RegexSet::new(exclude_patterns)?,
+            prefix: PathBuf::new(),
+            globs: GlobSet::new(globs, options)?,
+            rules,
         })
     }
 
-    pub fn new(ignore_path: &Path) -> Result<Self> {
+    pub fn new(ignore_path: &Path, options: &WalkOptions) -> Result<Self> {
         let file_name = ignore_path
             .file_name()
             .and_then(|s| s.to_str())
@@ -199,27 +359,49 @@ impl GitIgnore {
             return Ok(Self::empty());
         }
 
-        let f = std::fs::File::open(&ignore_path)
+        let f = std::fs::File::open(ignore_path)
             .with_context(|| format!("could not read file {:?}", &ignore_path))?;
         let reader = std::io::BufReader::new(f);
 
-        Self::from(
+        let gitignore = Self::from_reader(
             ignore_path
                 .parent()
-                .and_then(|p| Option::Some(p.to_path_buf()))
-                .unwrap_or(PathBuf::new()),
+                .map(|p| p.to_path_buf())
+                .unwrap_or_default(),
             reader,
-        )
+            options,
+        )?;
+        Ok(gitignore.with_source(ignore_path.to_path_buf()))
+    }
+
+    /// Records that the rules were read from the ignore file at `source`, see `source`.
+    fn with_source(self, source: PathBuf) -> Self {
+        Self { source, ..self }
+    }
+
+    /// The ignore file that the rules were read from, e.g. for `check_ignore`. Empty if they
+    /// were not read from a file.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    pub fn from_dir(dir_path: &Path, options: &WalkOptions) -> Result<Option<Self>> {
+        Self::from_dir_named(dir_path, ".gitignore", options)
     }
 
-    pub fn from_dir(dir_path: &Path) -> Result<Option<Self>> {
-        let gitignore_path = dir_path.join(".gitignore");
+    /// Reads the ignore file called `file_name` (`.gitignore` or `.ignore`) in `dir_path`, if any.
+    pub fn from_dir_named(
+        dir_path: &Path,
+        file_name: &str,
+        options: &WalkOptions,
+    ) -> Result<Option<Self>> {
+        let gitignore_path = dir_path.join(file_name);
 
         // Fetch the metadata once because it requires a syscall
         let metadata = gitignore_path.metadata()?;
 
         if metadata.is_file() {
-            let gitignore = GitIgnore::new(&gitignore_path)?;
+            let gitignore = GitIgnore::new(&gitignore_path, options)?;
 
             Ok(Some(gitignore))
         } else {
@@ -227,24 +409,110 @@ impl GitIgnore {
         }
     }
 
+    /// Matches the paths under `root_path` instead, which is at `prefix` relative to the
+    /// directory of the ignore file (e.g. `src/bin` for `src/bin` in the repository root).
+    fn with_root(self, root_path: PathBuf, prefix: PathBuf) -> Self {
+        Self {
+            root_path,
+            prefix,
+            ..self
+        }
+    }
+
     pub fn is_match(&self, path: &Path, is_dir: bool) -> bool {
-        let path = path.strip_prefix("./").unwrap_or(path);
-        let path = path.strip_prefix(&self.root_path).unwrap_or(path);
+        self.decide(path, is_dir).unwrap_or(false)
+    }
 
-        let mut path = path.to_string_lossy();
-        if is_dir {
-            if path == ".git" {
-                // We should always ignore .git directory!
-                return true;
-            }
-            path.to_mut().push('/');
+    /// The path that the patterns are matched against, see `relative_path`.
+    fn relative_path<'a>(&self, path: &'a Path, is_dir: bool) -> Cow<'a, str> {
+        let path = relative_path(path, &self.root_path, is_dir);
+        if self.prefix.as_os_str().is_empty() {
+            return path;
+        }
+
+        Cow::Owned(format!("{}{}{}", self.prefix.display(), DIR_SEP, path))
+    }
+
+    /// Decides if `path` is ignored (`Some(true)`) or re-included by a negated rule
+    /// (`Some(false)`), or returns None if no rule matches it, so that it is up to the ignore
+    /// files of the parent directories.
+    fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative_path = self.relative_path(path, is_dir);
+        if relative_path == ".git/" {
+            // We should always ignore .git directory!
+            return Some(true);
+        }
+
+        self.last_match(path, is_dir)
+            .map(|i| !self.globs.globs[i].is_negated())
+    }
+
+    /// Finds the rule that decides `is_match`, if any: the last rule that matches the path, like
+    /// in git. The `.git` directory is ignored without a rule.
+    pub fn explain(&self, path: &Path, is_dir: bool) -> Option<&Rule> {
+        self.last_match(path, is_dir).map(|i| &self.rules[i])
+    }
+
+    /// Finds the index of the rule that decides `is_match`, if any.
+    fn last_match(&self, path: &Path, is_dir: bool) -> Option<usize> {
+        self.globs.last_match(&self.relative_path(path, is_dir))
+    }
+}
+
+/// Reads newline separated globs from a file, skipping blank lines and comments.
+pub fn read_globs(globs_path: &Path) -> Result<Vec<String>> {
+    let f = std::fs::File::open(globs_path)
+        .with_context(|| format!("could not read glob file {:?}", globs_path))?;
+    let reader = std::io::BufReader::new(f);
+
+    let mut globs = Vec::new();
+    for glob in reader.lines() {
+        let glob = glob?;
+        let glob = clean_pattern(&glob);
+        if !glob.is_empty() {
+            globs.push(glob.to_string());
         }
+    }
+
+    Ok(globs)
+}
+
+/// Globs that select which files are walked, relative to the initial path.
+///
+/// Globs use the gitignore syntax. If there are any globs without a `!`, only files that match
+/// one of them are walked. Files and directories that match a glob with a `!` are never walked.
+struct Globs {
+    root_path: PathBuf,
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl Globs {
+    fn new(root_path: &Path, globs: &[String], options: &WalkOptions) -> Result<Self> {
+        // Like a blank line in an ignore file, an empty glob matches nothing
+        let (exclude, include): (Vec<Glob>, Vec<Glob>) = globs
+            .iter()
+            .filter_map(|glob| Glob::parse(glob))
+            .partition(Glob::is_negated);
 
-        if self.exclude_patterns.is_match(&path) {
-            return false;
+        let build = |globs| GlobSet::new(globs, options).context("invalid glob");
+
+        Ok(Self {
+            root_path: root_path.to_path_buf(),
+            include: build(include)?,
+            exclude: build(exclude)?,
+        })
+    }
+
+    /// Checks if `path` should be skipped.
+    fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let path = relative_path(path, &self.root_path, is_dir);
+        if is_dir {
+            // Include globs only apply to files, since a directory could contain matching files
+            return self.exclude.is_match(&path);
         }
 
-        self.include_patterns.is_match(&path)
+        self.exclude.is_match(&path) || (!self.include.is_empty() && !self.include.is_match(&path))
     }
 }
 
@@ -257,7 +525,7 @@ impl GitIgnoreStack {
         Self { stack: Vec::new() }
     }
 
-    fn push(&mut self, gitignore: GitIgnore) -> () {
+    fn push(&mut self, gitignore: GitIgnore) {
         self.stack.push(gitignore)
     }
 
@@ -269,117 +537,772 @@ impl GitIgnoreStack {
         self.stack.is_empty()
     }
 
+    /// Checks if `path` is ignored. Like in git, the last matching rule wins, and the rules of
+    /// deeper ignore files come after those of their parent directories, so a deeper file can
+    /// re-include a path with a negated rule (and vice versa).
     fn is_match(&self, path: &Path, is_dir: bool) -> bool {
-        for gitignore in self.stack.iter().rev() {
-            if gitignore.is_match(path, is_dir) {
-                return true;
-            }
+        self.stack
+            .iter()
+            .rev()
+            .find_map(|gitignore| gitignore.decide(path, is_dir))
+            .unwrap_or(false)
+    }
+
+    /// Finds the rule that decides `is_match`, if any, along with its ignore file: the last
+    /// matching rule of the deepest ignore file with one. The `.git` directory is ignored
+    /// without a rule.
+    fn explain(&self, path: &Path, is_dir: bool) -> Option<(&GitIgnore, &Rule)> {
+        let gitignore = self
+            .stack
+            .iter()
+            .rev()
+            .find(|gitignore| gitignore.decide(path, is_dir).is_some())?;
+        Some((gitignore, gitignore.explain(path, is_dir)?))
+    }
+}
+
+/// Reads the start of `path` into a re-usable `probe_buffer`, returning the number of bytes read.
+/// With `search_zip`, the decompressed contents of compressed files are probed instead.
+///
+/// At least `PROBE_SIZE` bytes are read (unless the file is shorter). If there is no newline in
+/// them, reading continues until the end of the first line, up to `max_line_length` bytes.
+fn probe_file(
+    probe_buffer: &mut Vec<u8>,
+    path: &Path,
+    search_zip: bool,
+    max_line_length: usize,
+) -> Option<usize> {
+    let file = std::fs::File::open(path).ok()?;
+    let reader: Box<dyn Read> = if search_zip {
+        super::compressed::reader(path, file)
+    } else {
+        Box::new(file)
+    };
+    let mut reader = reader.take(PROBE_SIZE.max(max_line_length) as u64);
+
+    probe_buffer.clear();
+    let mut chunk = [0u8; PROBE_SIZE];
+    loop {
+        let n = reader.read(&mut chunk).ok()?;
+        if n == 0 {
+            break;
+        }
+        probe_buffer.extend(&chunk[..n]);
+        if probe_buffer.len() >= PROBE_SIZE && chunk[..n].contains(&b'\n') {
+            break;
         }
+    }
+
+    Some(probe_buffer.len())
+}
+
+/// Checks if `sample`, from the start of a file, is valid text.
+///
+/// The sample can end in the middle of a multibyte char, which is still text.
+pub fn is_text(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return false;
+    }
 
-        false
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        // Only the last char is incomplete
+        Err(err) => err.error_len().is_none(),
     }
 }
 
-/// Checks if `path` is a valid text file.
-/// Uses a re-usable `probe_buffer`.
-fn is_text_file(probe_buffer: &mut [u8; 1024], path: &Path) -> bool {
-    let Ok(mut file) = std::fs::File::open(path) else {
+/// Which symlinks are followed by the walk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SymlinkMode {
+    /// Skips all symlinks, so that the walk is guaranteed to be a tree.
+    #[default]
+    Skip,
+    /// Follows symlinks to files, but not to directories, which cannot create cycles.
+    Files,
+    /// Follows all symlinks. Each directory is only walked once, which also breaks cycles.
+    All,
+}
+
+/// Whether git submodules are walked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SubmoduleMode {
+    /// Walks submodules like any other directory.
+    #[default]
+    Include,
+    /// Skips submodules, other than the initial path.
+    Exclude,
+}
+
+/// The number of paths that a walk skipped, for the reasons that are worth reporting.
+#[derive(Debug, Default)]
+pub struct SkipCounts {
+    /// Files that are not text, see `WalkOptions::binary`.
+    pub binary: AtomicUsize,
+    /// Files and directories that an ignore file ignores. The contents of an ignored directory
+    /// are not counted.
+    pub ignored: AtomicUsize,
+}
+
+pub struct WalkOptions {
+    pub max_depth: u32,
+    /// Matches gitignore patterns case insensitively, e.g. for case insensitive file systems.
+    pub case_insensitive_globs: bool,
+    /// Also collects directories (other than the initial path), with a trailing `/`.
+    pub include_dirs: bool,
+    /// Reuses compiled ignore patterns across walks.
+    pub regex_cache: Option<Arc<RegexCache>>,
+    /// Also walks hidden files and directories (those starting with `.`), other than `.git`.
+    pub hidden: bool,
+    /// Honors `.gitignore` and `.ignore` files.
+    pub ignore: bool,
+    /// Honors `.gitignore` files. `.ignore` files are still honored when this is disabled.
+    pub ignore_vcs: bool,
+    /// Honors the ignore files in the parent directories of the initial path, up to the root of
+    /// its git repository. See `parent_ignore_files`.
+    pub ignore_parent: bool,
+    /// Honors the `.git/info/exclude` file of the initial path's git repository.
+    pub ignore_exclude: bool,
+    /// Honors the user's global ignore file (see `git::global_excludes_file`), along with the
+    /// `.gitignore` files. Its patterns are relative to the root of the git repository, or else
+    /// to the initial path. Off by default, so that a walk does not depend on the user's config.
+    pub global_ignore: bool,
+    /// Selects the files to walk, like ripgrep's `-g`. See `Globs`.
+    pub globs: Vec<String>,
+    /// Also walks compressed files whose decompressed contents are text.
+    pub search_zip: bool,
+    /// Also walks binary files, i.e. files that are not text (see `is_text`).
+    pub binary: bool,
+    /// Only walks files of these types, if any. See `file_types`.
+    pub types: Vec<String>,
+    /// Skips files of these types, even if they are in `types`.
+    pub types_not: Vec<String>,
+    /// User-defined file types, which can add to the built-in ones, see `file_types::TypeDef`.
+    pub type_defs: Vec<TypeDef>,
+    /// Walks the entries of each directory in order of their names, instead of the order that
+    /// the file system returns them in, so that the walk is reproducible.
+    pub sorted: bool,
+    /// Reads up to this many bytes of a long first line to decide if a file is text, see
+    /// `probe_file`. Only the first 1024 bytes are read when this is smaller.
+    pub max_line_length: usize,
+    /// Skips files smaller than this many bytes.
+    pub min_filesize: Option<u64>,
+    /// Skips files larger than this many bytes.
+    pub max_filesize: Option<u64>,
+    pub symlinks: SymlinkMode,
+    /// Submodules are detected by their `.git` file, see `is_submodule`.
+    pub submodules: SubmoduleMode,
+    /// Prints each path that is skipped to stderr, along with why, e.g. `skipped a.bin (binary)`.
+    pub show_skipped: bool,
+    /// Counts the paths that are skipped, which can be shared between walks.
+    pub skip_counts: Option<Arc<SkipCounts>>,
+    /// Skips paths that are removed after they are found (e.g. by another process) instead of
+    /// failing the walk. They are only logged at debug level.
+    pub ignore_races: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: u32::MAX - 1,
+            case_insensitive_globs: false,
+            include_dirs: false,
+            regex_cache: None,
+            hidden: false,
+            ignore: true,
+            ignore_vcs: true,
+            ignore_parent: true,
+            ignore_exclude: true,
+            global_ignore: false,
+            globs: Vec::new(),
+            search_zip: false,
+            binary: false,
+            types: Vec::new(),
+            types_not: Vec::new(),
+            type_defs: Vec::new(),
+            sorted: false,
+            max_line_length: PROBE_SIZE,
+            min_filesize: None,
+            max_filesize: None,
+            symlinks: SymlinkMode::Skip,
+            submodules: SubmoduleMode::Include,
+            show_skipped: false,
+            skip_counts: None,
+            ignore_races: false,
+        }
+    }
+}
+
+struct Walker<'a> {
+    options: &'a WalkOptions,
+
+    /// The paths visited by the last task, which are yet to be yielded.
+    file_paths: VecDeque<PathBuf>,
+    probe_buffer: Vec<u8>,
+    gitignore_stack: GitIgnoreStack,
+    globs: Globs,
+    /// The canonical paths of the directories walked so far, with `SymlinkMode::All`.
+    visited_dirs: HashSet<PathBuf>,
+}
+
+/// Checks if `path` is hidden, i.e. its file name starts with a `.`.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+}
+
+/// Checks if the directory `dir_path` is a git submodule (or a linked worktree), i.e. it has a
+/// `.git` file that points to the actual git directory (`gitdir: ...`), instead of a `.git`
+/// directory.
+fn is_submodule(dir_path: &Path) -> bool {
+    let git_path = dir_path.join(".git");
+    if !git_path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_file())
+    {
         return false;
+    }
+
+    let mut prefix = [0; 7];
+    std::fs::File::open(git_path)
+        .and_then(|mut f| f.read_exact(&mut prefix))
+        .is_ok_and(|_| &prefix == b"gitdir:")
+}
+
+/// Reads the ignore files in `dir_path` that are enabled by `options`, in increasing precedence.
+fn ignore_files(dir_path: &Path, options: &WalkOptions) -> Vec<GitIgnore> {
+    if !options.ignore {
+        return Vec::new();
+    }
+
+    let mut file_names = Vec::new();
+    if options.ignore_vcs {
+        file_names.push(".gitignore");
+    }
+    file_names.push(".ignore");
+
+    file_names
+        .into_iter()
+        .filter_map(|file_name| {
+            GitIgnore::from_dir_named(dir_path, file_name, options).unwrap_or(None)
+        })
+        .collect()
+}
+
+/// Where the initial directory of a walk is in its git repository, for the ignore files outside of
+/// the walk, whose patterns are relative to the root of the repository.
+struct RepoDir {
+    /// The canonical path of the initial directory.
+    dir: PathBuf,
+    root: PathBuf,
+}
+
+impl RepoDir {
+    /// Returns None if `initial_path` is not a directory in a git repository. The initial path
+    /// is never ignored, so the ignore files outside of the walk would not apply to a file anyway.
+    fn find(initial_path: &Path) -> Option<Self> {
+        if !initial_path.is_dir() {
+            return None;
+        }
+
+        // The ancestors of a relative path like `.` or `..` are not its parent directories
+        let dir = std::fs::canonicalize(initial_path).ok()?;
+        let root = super::git::repo_root(&dir)?.to_path_buf();
+        Some(Self { dir, root })
+    }
+
+    /// Makes `gitignore`, which was read from `ancestor`, match the paths of the walk.
+    fn rebase(&self, gitignore: GitIgnore, initial_path: &Path, ancestor: &Path) -> GitIgnore {
+        let prefix = self.dir.strip_prefix(ancestor).unwrap_or(&self.dir);
+        gitignore.with_root(initial_path.to_path_buf(), prefix.to_path_buf())
+    }
+}
+
+/// Reads the ignore files outside of the walk that apply to it, in increasing precedence like in
+/// git: the user's global ignore file, the repository's `.git/info/exclude`, and then the ignore
+/// files of the parent directories. The walk's own ignore files take precedence over all of them.
+fn outer_ignore_files(initial_path: &Path, options: &WalkOptions) -> Vec<GitIgnore> {
+    if !options.ignore {
+        return Vec::new();
+    }
+
+    let repo = RepoDir::find(initial_path);
+    let mut gitignores: Vec<GitIgnore> = global_ignore_file(initial_path, repo.as_ref(), options)
+        .into_iter()
+        .collect();
+    if let Some(repo) = &repo {
+        gitignores.extend(repo_exclude_file(initial_path, repo, options));
+        gitignores.extend(parent_ignore_files(initial_path, repo, options));
+    }
+
+    gitignores
+}
+
+/// Reads the ignore files in the parent directories of `initial_path`, up to the root of its git
+/// repository, in increasing precedence. Outside of a repository, there are none, since the ignore
+/// files of unrelated directories (e.g. the home directory) should not apply.
+fn parent_ignore_files(
+    initial_path: &Path,
+    repo: &RepoDir,
+    options: &WalkOptions,
+) -> Vec<GitIgnore> {
+    if !options.ignore_parent {
+        return Vec::new();
+    }
+
+    let mut gitignores: Vec<GitIgnore> = Vec::new();
+    for parent in repo.dir.ancestors().skip(1) {
+        if !parent.starts_with(&repo.root) {
+            break;
+        }
+        // The closer parents take precedence, so they are pushed after the farther ones
+        let parent_gitignores = ignore_files(parent, options)
+            .into_iter()
+            .map(|gitignore| repo.rebase(gitignore, initial_path, parent));
+        gitignores.splice(0..0, parent_gitignores);
+    }
+
+    gitignores
+}
+
+/// Reads the repository's `.git/info/exclude`, if it is enabled by `options` and exists. Like a
+/// `.gitignore` file in the root of the repository, but not committed.
+fn repo_exclude_file(
+    initial_path: &Path,
+    repo: &RepoDir,
+    options: &WalkOptions,
+) -> Option<GitIgnore> {
+    if !options.ignore_vcs || !options.ignore_exclude {
+        return None;
+    }
+
+    let ignore_path = repo.root.join(".git/info/exclude");
+    let f = std::fs::File::open(&ignore_path).ok()?;
+    match GitIgnore::from_reader(repo.root.clone(), std::io::BufReader::new(f), options) {
+        Ok(gitignore) => {
+            Some(repo.rebase(gitignore.with_source(ignore_path), initial_path, &repo.root))
+        }
+        Err(err) => {
+            warn!("invalid ignore file {:?}: {}", ignore_path, err);
+            None
+        }
+    }
+}
+
+/// Reads the user's global ignore file, if it is enabled by `options` and exists. Its patterns
+/// are relative to the root of the repository, or else to the walk's root.
+fn global_ignore_file(
+    initial_path: &Path,
+    repo: Option<&RepoDir>,
+    options: &WalkOptions,
+) -> Option<GitIgnore> {
+    if !options.ignore_vcs || !options.global_ignore {
+        return None;
+    }
+
+    let ignore_path = super::git::global_excludes_file()?;
+    let f = std::fs::File::open(&ignore_path).ok()?;
+    let root_path = if initial_path.is_dir() {
+        initial_path
+    } else {
+        initial_path.parent().unwrap_or(Path::new(""))
     };
-    let Ok(n) = file.read(probe_buffer) else {
-        return false;
+    match GitIgnore::from_reader(root_path.to_path_buf(), std::io::BufReader::new(f), options) {
+        Ok(gitignore) => {
+            let gitignore = gitignore.with_source(ignore_path);
+            Some(match repo {
+                Some(repo) => repo.rebase(gitignore, initial_path, &repo.root),
+                None => gitignore,
+            })
+        }
+        Err(err) => {
+            warn!("invalid global ignore file {:?}: {}", ignore_path, err);
+            None
+        }
+    }
+}
+
+/// The rule that decides if a path is ignored, along with the ignore file that it is from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreMatch {
+    /// Like in git, relative to the root of the repository (e.g. `src/.gitignore` or
+    /// `.git/info/exclude`), except for the global ignore file. Outside of a repository, it is
+    /// next to the path.
+    pub source: PathBuf,
+    pub rule: Rule,
+}
+
+/// Finds the rule that decides if `path` is ignored, like `git check-ignore -v`, or returns None
+/// if no rule matches it. Every ignore file that a walk of its directory would honor is taken
+/// into account, including those in the parent directories and the global ignore file (see
+/// `outer_ignore_files`), as enabled by `options`.
+///
+/// The path does not have to exist, but its directory does. It is a directory if `is_dir`.
+pub fn check_ignore(path: &Path, is_dir: bool, options: &WalkOptions) -> Option<IgnoreMatch> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
     };
 
-    let sample = &probe_buffer[..n];
+    let mut stack = GitIgnoreStack::new();
+    for gitignore in outer_ignore_files(dir, options)
+        .into_iter()
+        .chain(ignore_files(dir, options))
+    {
+        stack.push(gitignore);
+    }
 
-    !sample.contains(&0) && std::str::from_utf8(sample).is_ok()
+    let repo = RepoDir::find(dir);
+    stack
+        .explain(path, is_dir)
+        .map(|(gitignore, rule)| IgnoreMatch {
+            source: relative_source(gitignore.source(), repo.as_ref()),
+            rule: rule.clone(),
+        })
 }
 
-struct Walker {
-    max_depth: u32,
+/// Makes `source`, the path of an ignore file, relative to the root of `repo` if it is in it, or
+/// else drops its leading `./`.
+fn relative_source(source: &Path, repo: Option<&RepoDir>) -> PathBuf {
+    // The ignore files outside of the walk are found through the canonical path
+    if let Some(repo) = repo
+        && let Ok(canonical) = std::fs::canonicalize(source)
+        && let Ok(relative) = canonical.strip_prefix(&repo.root)
+    {
+        return relative.to_path_buf();
+    }
 
-    file_paths: Vec<PathBuf>,
-    probe_buffer: [u8; 1024],
-    gitignore_stack: GitIgnoreStack,
+    source.strip_prefix(".").unwrap_or(source).to_path_buf()
 }
 
-/// Walks the path using DFS.
-fn walk_dfs(walker: &mut Walker, path: PathBuf, current_depth: u32) -> Result<()> {
-    if current_depth >= walker.max_depth {
-        return Ok(());
+/// Work that is left to do in the walk.
+enum Task {
+    /// Visits a path at some depth.
+    Visit(PathBuf, u32),
+    /// Pops a directory's ignore files off the stack, once all of its children are visited.
+    PopIgnores(usize),
+}
+
+/// Skips `path`, printing it to stderr along with the `reason` with `show_skipped`.
+fn skip(walker: &Walker, path: &Path, reason: &str) -> Result<()> {
+    if walker.options.show_skipped {
+        eprintln!("skipped {} ({})", path.display(), reason);
     }
 
-    // Fetch the metadata once because it requires a syscall
-    let metadata = path.symlink_metadata()?;
+    Ok(())
+}
 
-    if metadata.is_symlink() {
-        // Don't follow symlinks to guarantee that it is a tree
+/// Like `skip`, for a path that is ignored by an ignore file, along with the rule that ignored it,
+/// e.g. `skipped a.log (ignored by *.log)`.
+fn skip_ignored(walker: &Walker, path: &Path, is_dir: bool) -> Result<()> {
+    if let Some(counts) = &walker.options.skip_counts {
+        counts.ignored.fetch_add(1, Ordering::Relaxed);
+    }
+    if !walker.options.show_skipped {
         return Ok(());
-    } else if metadata.is_file() {
-        if walker.gitignore_stack.is_match(&path, false) {
+    }
+
+    match walker.gitignore_stack.explain(path, is_dir) {
+        Some((_, rule)) => skip(walker, path, &format!("ignored by {}", rule.pattern)),
+        None => skip(walker, path, "ignored"),
+    }
+}
+
+/// Checks if `err` is because `path` was removed after it was found, which is not an error with
+/// `WalkOptions::ignore_races`.
+fn is_removed(walker: &Walker, path: &Path, err: &std::io::Error) -> bool {
+    let is_removed = walker.options.ignore_races && err.kind() == ErrorKind::NotFound;
+    if is_removed {
+        debug!("{} was removed during the walk", path.display());
+    }
+    is_removed
+}
+
+/// Visits a single path, adding the tasks to visit its children (if any) onto `tasks`.
+fn visit(
+    walker: &mut Walker,
+    tasks: &mut Vec<Task>,
+    path: PathBuf,
+    current_depth: u32,
+) -> Result<()> {
+    if current_depth >= walker.options.max_depth {
+        return skip(walker, &path, "max depth");
+    }
+
+    // The initial path is always walked, even if it is hidden
+    if current_depth > 0 && is_hidden(&path) {
+        if path.file_name().is_some_and(|name| name == ".git") {
+            // Always skipped, so this is not worth mentioning
             return Ok(());
         }
+        if !walker.options.hidden {
+            return skip(walker, &path, "hidden");
+        }
+    }
 
-        if !is_text_file(&mut walker.probe_buffer, &path) {
-            return Ok(());
+    // Fetch the metadata once because it requires a syscall
+    let mut metadata = match path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(err) if is_removed(walker, &path, &err) => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if metadata.is_symlink() {
+        if walker.options.symlinks == SymlinkMode::Skip {
+            return skip(walker, &path, "symlink");
+        }
+        let Ok(target) = path.metadata() else {
+            return skip(walker, &path, "broken symlink");
+        };
+        let is_followed = match walker.options.symlinks {
+            SymlinkMode::Skip => false,
+            SymlinkMode::Files => target.is_file(),
+            SymlinkMode::All => target.is_file() || target.is_dir(),
+        };
+        if !is_followed {
+            return skip(walker, &path, "symlink");
+        }
+        metadata = target;
+    }
+
+    if metadata.is_file() {
+        // Neither ignore files nor globs apply to the initial path, since it was explicitly given
+        if current_depth > 0 && walker.gitignore_stack.is_match(&path, false) {
+            return skip_ignored(walker, &path, false);
+        }
+        if current_depth > 0 && walker.globs.is_excluded(&path, false) {
+            return skip(walker, &path, "glob");
+        }
+        // Checked before probing, since the size is already known
+        let size = metadata.len();
+        if walker.options.min_filesize.is_some_and(|min| size < min)
+            || walker.options.max_filesize.is_some_and(|max| size > max)
+        {
+            return skip(walker, &path, "size");
         }
-        walker.file_paths.push(path);
+
+        let Some(n) = probe_file(
+            &mut walker.probe_buffer,
+            &path,
+            walker.options.search_zip,
+            walker.options.max_line_length,
+        ) else {
+            return skip(walker, &path, "unreadable");
+        };
+        let sample = &walker.probe_buffer[..n];
+        if !walker.options.binary && !is_text(sample) {
+            if let Some(counts) = &walker.options.skip_counts {
+                counts.binary.fetch_add(1, Ordering::Relaxed);
+            }
+            return skip(walker, &path, "binary");
+        }
+        let type_defs = &walker.options.type_defs;
+        if current_depth > 0
+            && ((!walker.options.types.is_empty()
+                && !file_types::matches_any(&path, sample, &walker.options.types, type_defs))
+                || file_types::matches_any(&path, sample, &walker.options.types_not, type_defs))
+        {
+            return skip(walker, &path, "type");
+        }
+        walker.file_paths.push_back(path);
     } else if metadata.is_dir() {
         // gitignore cannot ignore its own directory, (it can only do stuff like `*` to ignore children)
         // so it is safe to do this before checking if it exists in current directory.
-        if walker.gitignore_stack.is_match(&path, true) {
-            return Ok(());
+        if current_depth > 0 && walker.gitignore_stack.is_match(&path, true) {
+            return skip_ignored(walker, &path, true);
+        }
+        if current_depth > 0 && walker.globs.is_excluded(&path, true) {
+            return skip(walker, &path, "glob");
+        }
+        if current_depth > 0
+            && walker.options.submodules == SubmoduleMode::Exclude
+            && is_submodule(&path)
+        {
+            return skip(walker, &path, "submodule");
         }
 
-        // If gitignore exists in this directory, add it to the stack
-        let gitignore = GitIgnore::from_dir(&path).unwrap_or(None);
-        let has_gitignore = gitignore.is_some();
-        if let Some(gitignore) = gitignore {
-            walker.gitignore_stack.push(gitignore);
+        if walker.options.symlinks == SymlinkMode::All {
+            // The same directory can be reached through symlinks, possibly from inside itself
+            let canonical_path = match std::fs::canonicalize(&path) {
+                Ok(canonical_path) => canonical_path,
+                Err(err) if is_removed(walker, &path, &err) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            if !walker.visited_dirs.insert(canonical_path) {
+                return skip(walker, &path, "already walked");
+            }
         }
 
-        for entry in std::fs::read_dir(path)? {
-            let entry = entry?;
-            let child = entry.path();
-            walk_dfs(walker, child, current_depth + 1)?;
+        // Read before anything else is done for the directory, in case it was removed
+        let entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(err) if is_removed(walker, &path, &err) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut children = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        if walker.options.sorted {
+            children.sort();
         }
 
-        // Clean it up from the stack
-        if has_gitignore {
-            let _ = walker.gitignore_stack.pop();
+        if walker.options.include_dirs && current_depth > 0 {
+            // Joining an empty path adds a trailing separator
+            walker.file_paths.push_back(path.join(""));
         }
+
+        // If ignore files exist in this directory, add them to the stack
+        let gitignores = ignore_files(&path, walker.options);
+        let num_gitignores = gitignores.len();
+        for gitignore in gitignores {
+            walker.gitignore_stack.push(gitignore);
+        }
+
+        // Tasks are done in LIFO order, so the ignore files are cleaned up from the stack after
+        // all of the children, and the children are pushed in reverse to be visited in order
+        if num_gitignores > 0 {
+            tasks.push(Task::PopIgnores(num_gitignores));
+        }
+        tasks.extend(
+            children
+                .into_iter()
+                .rev()
+                .map(|child| Task::Visit(child, current_depth + 1)),
+        );
     } else {
-        assert!(false, "path {:?} is not any of symlink, file, dir...", path);
+        unreachable!("path {:?} is not any of symlink, file, dir...", path);
     }
 
     Ok(())
 }
 
-// TODO: Make this an iterator
-// * not that easy because we cannot naturally do recursion in an iterator
-// * we chose to use recursion to simplify the management of `gitignore_stack`
+/// An iterator over the files in a file tree, see `walk_iter`.
+///
+/// The walk is a DFS that keeps its own stack of tasks rather than recursing, so that a very deep
+/// tree cannot overflow the stack, and so that each file can be yielded as soon as it is visited.
+pub struct Walk<'a> {
+    walker: Walker<'a>,
+    tasks: Vec<Task>,
+    /// The number of ignore files that were pushed before the walk, which are popped after it,
+    /// see `outer_ignore_files`.
+    num_outer_gitignores: usize,
+}
+
+impl Iterator for Walk<'_> {
+    type Item = Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(path) = self.walker.file_paths.pop_front() {
+                return Some(Ok(path));
+            }
+
+            let Some(task) = self.tasks.pop() else {
+                for _ in 0..std::mem::take(&mut self.num_outer_gitignores) {
+                    let _ = self.walker.gitignore_stack.pop();
+                }
+                assert!(
+                    self.walker.gitignore_stack.is_empty(),
+                    "the walk should have cleaned up all gitignores"
+                );
+                return None;
+            };
+            match task {
+                Task::Visit(path, current_depth) => {
+                    if let Err(err) = visit(&mut self.walker, &mut self.tasks, path, current_depth)
+                    {
+                        // Nothing else is walked after an error
+                        self.tasks.clear();
+                        self.walker.gitignore_stack = GitIgnoreStack::new();
+                        self.num_outer_gitignores = 0;
+                        return Some(Err(err));
+                    }
+                }
+                Task::PopIgnores(num_gitignores) => {
+                    for _ in 0..num_gitignores {
+                        let _ = self.walker.gitignore_stack.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Walks the file tree rooted at `initial_path` (up to `max_depth`), yielding each file as soon
+/// as it is visited, so that the files can be used while the rest of the tree is walked.
+pub fn walk_iter(initial_path: PathBuf, options: &WalkOptions) -> Result<Walk<'_>> {
+    let mut walker = Walker {
+        options,
+        file_paths: VecDeque::new(),
+        probe_buffer: Vec::with_capacity(PROBE_SIZE),
+        gitignore_stack: GitIgnoreStack::new(),
+        globs: Globs::new(&initial_path, &options.globs, options)?,
+        visited_dirs: HashSet::new(),
+    };
+
+    // At the bottom of the stack, so that the walk's own ignore files take precedence
+    let outer_gitignores = outer_ignore_files(&initial_path, options);
+    let num_outer_gitignores = outer_gitignores.len();
+    for gitignore in outer_gitignores {
+        walker.gitignore_stack.push(gitignore);
+    }
+
+    Ok(Walk {
+        walker,
+        tasks: vec![Task::Visit(initial_path, 0)],
+        num_outer_gitignores,
+    })
+}
+
 /// Walks the file tree rooted at `initial_path` (up to `max_depth`), collecting all files into the result.
-pub fn walk(initial_path: PathBuf, max_depth: u32) -> Result<Vec<PathBuf>> {
+pub fn walk(initial_path: PathBuf, options: &WalkOptions) -> Result<Vec<PathBuf>> {
+    walk_iter(initial_path, options)?.collect()
+}
+
+/// Filters `file_paths`, which were listed some other way than walking `initial_path` (e.g. by
+/// git), like a walk of `initial_path` would: hidden paths, globs, file types, sizes and binary
+/// files are skipped as enabled by `options`. The ignore files are not read, since the paths were
+/// listed on purpose. Paths that are missing or not files (e.g. a git submodule) are skipped.
+pub fn filter_listed(
+    initial_path: &Path,
+    file_paths: Vec<PathBuf>,
+    options: &WalkOptions,
+) -> Result<Vec<PathBuf>> {
     let mut walker = Walker {
-        max_depth,
-        file_paths: Vec::new(),
-        probe_buffer: [0u8; 1024],
+        options,
+        file_paths: VecDeque::new(),
+        probe_buffer: Vec::with_capacity(PROBE_SIZE),
         gitignore_stack: GitIgnoreStack::new(),
+        globs: Globs::new(initial_path, &options.globs, options)?,
+        visited_dirs: HashSet::new(),
     };
 
-    walk_dfs(&mut walker, initial_path, 0)?;
+    for path in file_paths {
+        let relative_path = path.strip_prefix(initial_path).unwrap_or(&path);
+        // The files themselves are checked by `visit`, but a walk would not enter their parents
+        if !options.hidden && relative_path.ancestors().skip(1).any(is_hidden) {
+            skip(&walker, &path, "hidden")?;
+            continue;
+        }
 
-    assert!(
-        walker.gitignore_stack.is_empty(),
-        "walk_dfs should have cleaned up all gitignores"
-    );
+        let depth = relative_path.components().count() as u32;
+        if path.symlink_metadata().is_err() {
+            skip(&walker, &path, "missing")?;
+        } else if path.is_dir() {
+            skip(&walker, &path, "not a file")?;
+        } else {
+            // A file has no children, so there are no tasks to do after it
+            visit(&mut walker, &mut Vec::new(), path, depth)?;
+        }
+    }
 
-    Ok(walker.file_paths)
+    Ok(walker.file_paths.into())
 }
 
 #[cfg(test)]
+// Some of the tests borrow the paths that they match, which is harmless
+#[allow(clippy::needless_borrow)]
 mod tests {
     use super::*;
+    use crate::test_util::TempDir;
 
     #[test]
     fn test_remove_comment() {
@@ -442,6 +1365,33 @@ mod tests {
         assert_eq!(convert_pattern(r"dir/abc\"), None);
     }
 
+    #[test]
+    fn test_glob() {
+        let flags = |pattern: &str| {
+            Glob::parse(pattern)
+                .map(|glob| (glob.is_negated(), glob.is_dir_only(), glob.is_anchored()))
+        };
+
+        assert_eq!(flags("*.log"), Some((false, false, false)));
+        assert_eq!(flags("!*.log"), Some((true, false, false)));
+        assert_eq!(flags("build/"), Some((false, true, false)));
+        assert_eq!(flags("/build/"), Some((false, true, true)));
+        assert_eq!(flags("src/*.rs"), Some((false, false, true)));
+        // A leading `**/` matches at any level
+        assert_eq!(flags("**/gen/"), Some((false, true, false)));
+        assert_eq!(flags(r"dir\/"), Some((false, false, false)));
+
+        // Blank and invalid patterns match nothing
+        assert_eq!(flags(""), None);
+        assert_eq!(flags("!"), None);
+        assert_eq!(flags(r"abc\"), None);
+
+        assert_eq!(
+            Glob::parse("!/a.txt").map(|glob| glob.regex),
+            convert_pattern("/a.txt")
+        );
+    }
+
     #[test]
     fn test_basic_file_ignore() {
         // Unanchored: should match anywhere
@@ -579,4 +1529,856 @@ mod tests {
         assert!(!ignore.is_match(&Path::new(r"file.txt"), false));
         assert!(!ignore.is_match(&Path::new(r"file2.txt"), false));
     }
+
+    #[test]
+    fn test_explain() {
+        let gitignore = "# logs\n*.log\n!keep.log\n\nbuild/\n/build/cache/\n";
+        let gitignore = GitIgnore::from(PathBuf::new(), gitignore.as_bytes()).unwrap();
+        let explain = |path: &str, is_dir: bool| {
+            gitignore
+                .explain(Path::new(path), is_dir)
+                .map(|rule| (rule.line_num, rule.pattern.as_str(), rule.is_negated()))
+        };
+
+        assert_eq!(explain("a.log", false), Some((2, "*.log", false)));
+        assert_eq!(explain("keep.log", false), Some((3, "!keep.log", true)));
+        assert_eq!(explain("build", true), Some((5, "build/", false)));
+        // The last matching rule is reported
+        assert_eq!(
+            explain("build/cache", true),
+            Some((6, "/build/cache/", false))
+        );
+        assert_eq!(explain("build", false), None);
+        assert_eq!(explain("a.txt", false), None);
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let relative = |path: &str, root_path: &str, is_dir| {
+            relative_path(Path::new(path), Path::new(root_path), is_dir).into_owned()
+        };
+        assert_eq!(relative("src/a.rs", "src", false), "a.rs");
+        assert_eq!(relative("src/sub", "src", true), "sub/");
+        assert_eq!(relative("a.rs", "", false), "a.rs");
+        // A leading `./` in either path does not matter
+        assert_eq!(relative("./src/a.rs", "./src", false), "a.rs");
+        assert_eq!(relative("./src/a.rs", "src", false), "a.rs");
+        assert_eq!(relative("./a.rs", "", false), "a.rs");
+        assert_eq!(relative("./a.rs", ".", false), "a.rs");
+        // Paths outside of the root are kept as is
+        assert_eq!(relative("lib/a.rs", "src", false), "lib/a.rs");
+    }
+
+    #[test]
+    fn test_last_match_wins() {
+        let gitignore = "*.log\n!keep*.log\nkeep-not.log\n";
+        let gitignore = GitIgnore::from(PathBuf::new(), gitignore.as_bytes()).unwrap();
+        assert!(gitignore.is_match(Path::new("a.log"), false));
+        assert!(!gitignore.is_match(Path::new("keep.log"), false));
+        // A later rule re-ignores what a negated rule re-included
+        assert!(gitignore.is_match(Path::new("keep-not.log"), false));
+        assert!(!gitignore.is_match(Path::new("a.txt"), false));
+    }
+
+    #[test]
+    fn test_stack_explain() {
+        let mut stack = GitIgnoreStack::new();
+        stack.push(GitIgnore::from(PathBuf::new(), "*.log\n!keep.log\n".as_bytes()).unwrap());
+        let explain = |stack: &GitIgnoreStack, path: &str| {
+            stack
+                .explain(Path::new(path), false)
+                .map(|(_, rule)| (rule.line_num, rule.pattern.clone()))
+        };
+        assert_eq!(explain(&stack, "a.log"), Some((1, String::from("*.log"))));
+        assert_eq!(
+            explain(&stack, "keep.log"),
+            Some((2, String::from("!keep.log")))
+        );
+        assert_eq!(explain(&stack, "a.txt"), None);
+        assert!(stack.explain(Path::new(".git"), true).is_none());
+        assert!(stack.is_match(Path::new(".git"), true));
+
+        // The deeper ignore file decides
+        stack.push(GitIgnore::from(PathBuf::from("child"), "!*.log\n".as_bytes()).unwrap());
+        assert_eq!(
+            explain(&stack, "child/a.log"),
+            Some((1, String::from("!*.log")))
+        );
+        assert!(!stack.is_match(Path::new("child/a.log"), false));
+    }
+
+    #[test]
+    fn test_check_ignore() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join(".git/info")).unwrap();
+        std::fs::create_dir_all(dir.join("src/gen")).unwrap();
+        std::fs::write(dir.join(".git/info/exclude"), "*.tmp\n").unwrap();
+        std::fs::write(dir.join(".gitignore"), "# Logs\n*.log\n/src/gen/\n").unwrap();
+        std::fs::write(dir.join("src/.ignore"), "!keep.log\n").unwrap();
+
+        let check = |path: &str, is_dir: bool| {
+            check_ignore(&dir.join(path), is_dir, &WalkOptions::default())
+                .map(|found| (found.source, found.rule.line_num, found.rule.pattern))
+        };
+        // Each source is relative to the root of the repository
+        assert_eq!(
+            check("a.log", false),
+            Some((PathBuf::from(".gitignore"), 2, String::from("*.log")))
+        );
+        // From the parent directory
+        assert_eq!(
+            check("src/a.log", false),
+            Some((PathBuf::from(".gitignore"), 2, String::from("*.log")))
+        );
+        assert_eq!(
+            check("src/keep.log", false),
+            Some((PathBuf::from("src/.ignore"), 1, String::from("!keep.log")))
+        );
+        assert_eq!(
+            check("src/gen", true),
+            Some((PathBuf::from(".gitignore"), 3, String::from("/src/gen/")))
+        );
+        assert_eq!(
+            check("src/a.tmp", false),
+            Some((PathBuf::from(".git/info/exclude"), 1, String::from("*.tmp")))
+        );
+        assert_eq!(check("src/a.rs", false), None);
+    }
+
+    #[test]
+    fn test_walk_nested_negation() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("child/grandchild")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.log\n!important.log\n").unwrap();
+        // Re-includes a file that the parent ignored
+        std::fs::write(dir.join("child/.gitignore"), "!keep.log\n").unwrap();
+        // Ignores a file that the parent re-included
+        std::fs::write(dir.join("child/grandchild/.gitignore"), "important.log\n").unwrap();
+        for path in [
+            "a.log",
+            "keep.log",
+            "important.log",
+            "child/a.log",
+            "child/keep.log",
+            "child/important.log",
+            "child/grandchild/a.log",
+            "child/grandchild/keep.log",
+            "child/grandchild/important.log",
+        ] {
+            std::fs::write(dir.join(path), "foo\n").unwrap();
+        }
+
+        let options = WalkOptions {
+            hidden: true,
+            ..Default::default()
+        };
+        let mut file_paths = walk(dir.to_path_buf(), &options).unwrap();
+        file_paths.sort();
+        let expected: Vec<PathBuf> = [
+            ".gitignore",
+            "child/.gitignore",
+            "child/grandchild/.gitignore",
+            "child/grandchild/keep.log",
+            "child/important.log",
+            "child/keep.log",
+            "important.log",
+        ]
+        .iter()
+        .map(|path| dir.join(path))
+        .collect();
+        assert_eq!(file_paths, expected);
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let gitignore_content = b"*.LOG";
+
+        let ignore = GitIgnore::from_reader(
+            PathBuf::new(),
+            &gitignore_content[..],
+            &WalkOptions::default(),
+        )
+        .unwrap();
+        assert!(!ignore.is_match(Path::new("error.log"), false));
+        assert!(ignore.is_match(Path::new("error.LOG"), false));
+
+        let options = WalkOptions {
+            case_insensitive_globs: true,
+            ..Default::default()
+        };
+        let ignore =
+            GitIgnore::from_reader(PathBuf::new(), &gitignore_content[..], &options).unwrap();
+        assert!(ignore.is_match(Path::new("error.log"), false));
+        assert!(ignore.is_match(Path::new("error.LOG"), false));
+        assert!(ignore.is_match(Path::new("build/Error.Log"), false));
+    }
+
+    #[test]
+    fn test_is_text_file_bom() {
+        let dir = TempDir::new();
+        let path = dir.path().join("bom.txt");
+        std::fs::write(&path, b"\xEF\xBB\xBFhello\n").unwrap();
+
+        let mut probe_buffer = Vec::new();
+        let n = probe_file(&mut probe_buffer, &path, false, PROBE_SIZE).unwrap();
+        assert!(is_text(&probe_buffer[..n]));
+    }
+
+    #[test]
+    fn test_is_text_file_long_line() {
+        let dir = TempDir::new();
+        let path = dir.path().join("long-line.json");
+        // 3 byte chars, so the first 1024 bytes end in the middle of one
+        let line = "日".repeat(2000);
+        std::fs::write(&path, format!("{}\n\0", line)).unwrap();
+
+        let mut probe_buffer = Vec::new();
+        let n = probe_file(&mut probe_buffer, &path, false, PROBE_SIZE).unwrap();
+        assert_eq!(n, PROBE_SIZE);
+        assert!(is_text(&probe_buffer[..n]));
+
+        // Reads up to the end of the first line, but not beyond the maximum
+        let n = probe_file(&mut probe_buffer, &path, false, 4000).unwrap();
+        assert_eq!(n, 4000);
+        assert!(is_text(&probe_buffer[..n]));
+        let n = probe_file(&mut probe_buffer, &path, false, 10_000).unwrap();
+        assert!(n > line.len() && n < 10_000);
+        assert!(!is_text(&probe_buffer[..n]));
+
+        assert!(!is_text(b"abc\xFFdef"));
+    }
+
+    #[test]
+    fn test_walk_include_dirs() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        std::fs::create_dir_all(dir.join("ignored")).unwrap();
+        std::fs::create_dir_all(dir.join("sub/nested")).unwrap();
+        std::fs::write(dir.join("sub/a.txt"), "a").unwrap();
+        std::fs::write(dir.join(".gitignore"), "ignored/").unwrap();
+
+        let walk_sorted = |options: &WalkOptions| {
+            let mut file_paths = walk(dir.to_path_buf(), options).unwrap();
+            file_paths.sort();
+            file_paths
+        };
+
+        assert_eq!(
+            walk_sorted(&WalkOptions::default()),
+            vec![dir.join("sub/a.txt")]
+        );
+        assert_eq!(
+            walk_sorted(&WalkOptions {
+                include_dirs: true,
+                ..Default::default()
+            }),
+            vec![
+                dir.join("empty/"),
+                dir.join("sub/"),
+                dir.join("sub/a.txt"),
+                dir.join("sub/nested/"),
+            ]
+        );
+        // Respects the max depth
+        assert_eq!(
+            walk_sorted(&WalkOptions {
+                include_dirs: true,
+                max_depth: 2,
+                ..Default::default()
+            }),
+            vec![dir.join("empty/"), dir.join("sub/")]
+        );
+    }
+
+    #[test]
+    fn test_walk_toggles() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join(".hidden")).unwrap();
+        std::fs::write(dir.join(".git/config"), "a").unwrap();
+        std::fs::write(dir.join(".hidden/a.txt"), "a").unwrap();
+        std::fs::write(dir.join(".env"), "a").unwrap();
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        std::fs::write(dir.join("vcs.txt"), "a").unwrap();
+        std::fs::write(dir.join("local.txt"), "a").unwrap();
+        std::fs::write(dir.join(".gitignore"), "vcs.txt").unwrap();
+        std::fs::write(dir.join(".ignore"), "local.txt").unwrap();
+
+        let walk_sorted = |options: WalkOptions| {
+            let mut file_paths = walk(dir.to_path_buf(), &options).unwrap();
+            file_paths.sort();
+            file_paths
+        };
+
+        // Skips hidden files and honors both ignore files by default
+        assert_eq!(walk_sorted(WalkOptions::default()), vec![dir.join("a.txt")]);
+        assert_eq!(
+            walk_sorted(WalkOptions {
+                hidden: true,
+                ..Default::default()
+            }),
+            vec![
+                dir.join(".env"),
+                dir.join(".gitignore"),
+                dir.join(".hidden/a.txt"),
+                dir.join(".ignore"),
+                dir.join("a.txt"),
+            ]
+        );
+        assert_eq!(
+            walk_sorted(WalkOptions {
+                ignore: false,
+                ..Default::default()
+            }),
+            vec![
+                dir.join("a.txt"),
+                dir.join("local.txt"),
+                dir.join("vcs.txt")
+            ]
+        );
+        assert_eq!(
+            walk_sorted(WalkOptions {
+                ignore_vcs: false,
+                ..Default::default()
+            }),
+            vec![dir.join("a.txt"), dir.join("vcs.txt")]
+        );
+        // `.git` is never walked
+        assert_eq!(
+            walk_sorted(WalkOptions {
+                hidden: true,
+                ignore: false,
+                ..Default::default()
+            }),
+            vec![
+                dir.join(".env"),
+                dir.join(".gitignore"),
+                dir.join(".hidden/a.txt"),
+                dir.join(".ignore"),
+                dir.join("a.txt"),
+                dir.join("local.txt"),
+                dir.join("vcs.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_glob_file() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target/debug")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "a").unwrap();
+        std::fs::write(dir.join("src/notes.txt"), "a").unwrap();
+        std::fs::write(dir.join("build.rs"), "a").unwrap();
+        std::fs::write(dir.join("target/debug/out.rs"), "a").unwrap();
+
+        let globs_path = dir.join("rust.globs");
+        std::fs::write(
+            &globs_path,
+            "# Rust sources\n*.rs\n\n!target/  # build output\n",
+        )
+        .unwrap();
+
+        let globs = read_globs(&globs_path).unwrap();
+        assert_eq!(globs, vec!["*.rs", "!target/"]);
+
+        let mut file_paths = walk(
+            dir.to_path_buf(),
+            &WalkOptions {
+                globs,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        file_paths.sort();
+        assert_eq!(
+            file_paths,
+            vec![dir.join("build.rs"), dir.join("src/main.rs")]
+        );
+
+        // Empty globs are skipped, instead of only matching empty paths
+        let walk_globs = |globs: Vec<String>| {
+            let options = WalkOptions {
+                globs,
+                sorted: true,
+                ..Default::default()
+            };
+            walk(dir.to_path_buf(), &options).unwrap()
+        };
+        assert_eq!(
+            walk_globs(vec![String::new(), String::from("!")]),
+            walk_globs(Vec::new())
+        );
+
+        assert!(read_globs(&dir.join("missing.globs")).is_err());
+    }
+
+    #[test]
+    fn test_walk_types() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::write(dir.join("build.sh"), "make\n").unwrap();
+        std::fs::write(dir.join("deploy"), "#!/usr/bin/env bash\nmake deploy\n").unwrap();
+        std::fs::write(dir.join("main.py"), "print()\n").unwrap();
+        std::fs::write(dir.join("notes"), "make\n").unwrap();
+
+        let walk_sorted = |types: &[&str]| {
+            let options = WalkOptions {
+                types: types.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            };
+            let mut file_paths = walk(dir.to_path_buf(), &options).unwrap();
+            file_paths.sort();
+            file_paths
+        };
+
+        assert_eq!(
+            walk_sorted(&["sh"]),
+            vec![dir.join("build.sh"), dir.join("deploy")]
+        );
+        assert_eq!(walk_sorted(&["py"]), vec![dir.join("main.py")]);
+
+        let options = WalkOptions {
+            types_not: vec![String::from("sh"), String::from("notes")],
+            type_defs: vec!["notes:txt,md".parse().unwrap()],
+            ..Default::default()
+        };
+        std::fs::write(dir.join("todo.md"), "make\n").unwrap();
+        let mut file_paths = walk(dir.to_path_buf(), &options).unwrap();
+        file_paths.sort();
+        assert_eq!(file_paths, vec![dir.join("main.py"), dir.join("notes")]);
+    }
+
+    #[test]
+    fn test_walk_parent_ignore_files() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        let repo = dir.join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(repo.join("src/gen")).unwrap();
+        // Outside of the repository, so never honored
+        std::fs::write(dir.join(".gitignore"), "*.rs\n").unwrap();
+        std::fs::write(repo.join(".gitignore"), "*.log\n/src/gen/\n").unwrap();
+        std::fs::write(repo.join("src/.ignore"), "!keep.log\n").unwrap();
+        for name in ["a.rs", "a.log", "keep.log", "gen/b.rs"] {
+            std::fs::write(repo.join("src").join(name), "a").unwrap();
+        }
+
+        let walk_sorted = |path: PathBuf, options: &WalkOptions| {
+            let mut file_paths = walk(path, options).unwrap();
+            file_paths.sort();
+            file_paths
+        };
+        let src = repo.join("src");
+        assert_eq!(
+            walk_sorted(src.clone(), &WalkOptions::default()),
+            vec![src.join("a.rs"), src.join("keep.log")]
+        );
+        // Like in git, the files in an ignored directory are ignored, even if it is walked itself
+        assert!(walk_sorted(src.join("gen"), &WalkOptions::default()).is_empty());
+        let options = WalkOptions {
+            ignore_parent: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            walk_sorted(src.clone(), &options),
+            vec![
+                src.join("a.log"),
+                src.join("a.rs"),
+                src.join("gen/b.rs"),
+                src.join("keep.log"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_repo_exclude_file() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join(".git/info")).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join(".git/info/exclude"),
+            "*.tmp\n/src/gen.rs\n!*.log\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(dir.join("src/.gitignore"), "!keep.tmp\n").unwrap();
+        for name in ["a.rs", "gen.rs", "a.tmp", "keep.tmp", "a.log"] {
+            std::fs::write(dir.join("src").join(name), "a").unwrap();
+        }
+
+        let src = dir.join("src");
+        let walk_sorted = |options: &WalkOptions| {
+            let mut file_paths = walk(src.clone(), options).unwrap();
+            file_paths.sort();
+            file_paths
+        };
+        // The .gitignore files take precedence over it
+        assert_eq!(
+            walk_sorted(&WalkOptions::default()),
+            vec![src.join("a.rs"), src.join("keep.tmp")]
+        );
+        let options = WalkOptions {
+            ignore_exclude: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            walk_sorted(&options),
+            vec![
+                src.join("a.rs"),
+                src.join("a.tmp"),
+                src.join("gen.rs"),
+                src.join("keep.tmp"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_iter() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("a/b/c.txt"), "c").unwrap();
+        std::fs::write(dir.join("a/d.txt"), "d").unwrap();
+        std::fs::write(dir.join("e.txt"), "e").unwrap();
+
+        let options = WalkOptions {
+            sorted: true,
+            ..Default::default()
+        };
+        // Each file is yielded as soon as it is visited, in the same order as `walk`
+        let mut files = walk_iter(dir.to_path_buf(), &options).unwrap();
+        assert_eq!(files.next().unwrap().unwrap(), dir.join("a/b/c.txt"));
+        assert_eq!(
+            files.collect::<Result<Vec<_>>>().unwrap(),
+            vec![dir.join("a/d.txt"), dir.join("e.txt")]
+        );
+        assert_eq!(
+            walk(dir.to_path_buf(), &options).unwrap(),
+            vec![
+                dir.join("a/b/c.txt"),
+                dir.join("a/d.txt"),
+                dir.join("e.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_ignore_races() {
+        let dir = TempDir::new();
+
+        let walk_removing = |ignore_races| -> Result<Vec<PathBuf>> {
+            dir.write("a.txt", "a");
+            dir.write("b.txt", "b");
+            dir.write("sub/c.txt", "c");
+            let options = WalkOptions {
+                sorted: true,
+                ignore_races,
+                ..Default::default()
+            };
+
+            // The rest of the directory is found along with a.txt, but it is not visited yet
+            let mut files = walk_iter(dir.path().to_path_buf(), &options)?;
+            let mut file_paths = vec![files.next().unwrap()?];
+            std::fs::remove_file(dir.path().join("b.txt")).unwrap();
+            std::fs::remove_dir_all(dir.path().join("sub")).unwrap();
+            for file_path in files {
+                file_paths.push(file_path?);
+            }
+            Ok(file_paths)
+        };
+
+        assert_eq!(walk_removing(true).unwrap(), vec![dir.path().join("a.txt")]);
+        assert!(walk_removing(false).is_err());
+    }
+
+    #[test]
+    fn test_walk_skip_counts() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+        std::fs::write(dir.join("target/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("b.log"), "b").unwrap();
+        std::fs::write(dir.join("c.bin"), b"\0c").unwrap();
+        std::fs::write(dir.join("d.txt"), "d").unwrap();
+
+        let counts = Arc::new(SkipCounts::default());
+        let options = WalkOptions {
+            skip_counts: Some(Arc::clone(&counts)),
+            ..Default::default()
+        };
+        assert_eq!(
+            walk(dir.to_path_buf(), &options).unwrap(),
+            vec![dir.join("d.txt")]
+        );
+        // `target/a.txt` is not counted, only `target/`
+        assert_eq!(counts.ignored.load(Ordering::Relaxed), 2);
+        assert_eq!(counts.binary.load(Ordering::Relaxed), 1);
+
+        // The counts add up across walks
+        walk(dir.to_path_buf(), &options).unwrap();
+        assert_eq!(counts.ignored.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_walk_sorted() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        let mut expected = Vec::new();
+        for name in ["b", "a/z", "c", "a/b", "A"] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "a").unwrap();
+            expected.push(path);
+        }
+        expected.sort();
+
+        let options = WalkOptions {
+            sorted: true,
+            ..Default::default()
+        };
+        assert_eq!(walk(dir.to_path_buf(), &options).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_walk_deep_tree() {
+        const DEPTH: usize = 1500;
+
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        let mut deepest = dir.to_path_buf();
+        for _ in 0..DEPTH {
+            deepest.push("d");
+        }
+        std::fs::create_dir_all(&deepest).unwrap();
+        std::fs::write(deepest.join("a.txt"), "a").unwrap();
+        std::fs::write(dir.join("d/.gitignore"), "b.txt").unwrap();
+        std::fs::write(deepest.join("b.txt"), "b").unwrap();
+
+        // The walk should not need more stack for deeper trees
+        let handle = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn({
+                let dir = dir.to_path_buf();
+                move || walk(dir, &WalkOptions::default()).unwrap()
+            })
+            .unwrap();
+        assert_eq!(handle.join().unwrap(), vec![deepest.join("a.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("root/sub")).unwrap();
+        std::fs::create_dir_all(dir.join("outside")).unwrap();
+        std::fs::write(dir.join("root/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("outside/b.txt"), "b").unwrap();
+        symlink(dir.join("outside/b.txt"), dir.join("root/file-link.txt")).unwrap();
+        symlink(dir.join("outside"), dir.join("root/dir-link")).unwrap();
+        symlink(dir.join("missing.txt"), dir.join("root/broken-link.txt")).unwrap();
+        // A cycle back to the root
+        symlink(dir.join("root"), dir.join("root/sub/cycle")).unwrap();
+
+        let walk_symlinks = |symlinks: SymlinkMode| {
+            let options = WalkOptions {
+                symlinks,
+                sorted: true,
+                ..Default::default()
+            };
+            walk(dir.join("root"), &options).unwrap()
+        };
+
+        assert_eq!(
+            walk_symlinks(SymlinkMode::Skip),
+            vec![dir.join("root/a.txt")]
+        );
+        assert_eq!(
+            walk_symlinks(SymlinkMode::Files),
+            vec![dir.join("root/a.txt"), dir.join("root/file-link.txt")]
+        );
+        // Each directory is only walked once, so the cycle does not walk the root again
+        assert_eq!(
+            walk_symlinks(SymlinkMode::All),
+            vec![
+                dir.join("root/a.txt"),
+                dir.join("root/dir-link/b.txt"),
+                dir.join("root/file-link.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_filesize() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        for size in [1, 10, 100, 1000] {
+            std::fs::write(dir.join(format!("{}.txt", size)), "a".repeat(size)).unwrap();
+        }
+
+        let walk_sizes = |min_filesize, max_filesize| {
+            let options = WalkOptions {
+                min_filesize,
+                max_filesize,
+                sorted: true,
+                ..Default::default()
+            };
+            let mut sizes: Vec<usize> = walk(dir.to_path_buf(), &options)
+                .unwrap()
+                .iter()
+                .map(|path| path.file_stem().unwrap().to_str().unwrap().parse().unwrap())
+                .collect();
+            sizes.sort();
+            sizes
+        };
+
+        assert_eq!(walk_sizes(None, None), vec![1, 10, 100, 1000]);
+        // Both bounds are inclusive
+        assert_eq!(walk_sizes(Some(10), None), vec![10, 100, 1000]);
+        assert_eq!(walk_sizes(None, Some(100)), vec![1, 10, 100]);
+        assert_eq!(walk_sizes(Some(10), Some(100)), vec![10, 100]);
+        assert_eq!(walk_sizes(Some(11), Some(99)), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_walk_submodules() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        std::fs::create_dir_all(dir.join("repo/sub")).unwrap();
+        std::fs::create_dir_all(dir.join("repo/not-sub")).unwrap();
+        std::fs::write(dir.join("repo/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("repo/sub/.git"), "gitdir: ../.git/modules/sub\n").unwrap();
+        std::fs::write(dir.join("repo/sub/b.txt"), "b").unwrap();
+        // Only a `.git` file that points to a git directory is a submodule
+        std::fs::write(dir.join("repo/not-sub/.git"), "not a submodule\n").unwrap();
+        std::fs::write(dir.join("repo/not-sub/c.txt"), "c").unwrap();
+
+        let walk_submodules = |path: &Path, submodules: SubmoduleMode| {
+            let options = WalkOptions {
+                submodules,
+                hidden: true,
+                sorted: true,
+                ..Default::default()
+            };
+            walk(path.to_path_buf(), &options).unwrap()
+        };
+
+        // The `.git` file is always skipped, like the `.git` directory
+        assert_eq!(
+            walk_submodules(&dir.join("repo"), SubmoduleMode::Include),
+            vec![
+                dir.join("repo/a.txt"),
+                dir.join("repo/not-sub/c.txt"),
+                dir.join("repo/sub/b.txt"),
+            ]
+        );
+        assert_eq!(
+            walk_submodules(&dir.join("repo"), SubmoduleMode::Exclude),
+            vec![dir.join("repo/a.txt"), dir.join("repo/not-sub/c.txt")]
+        );
+        // Unless it is the initial path
+        assert_eq!(
+            walk_submodules(&dir.join("repo/sub"), SubmoduleMode::Exclude),
+            vec![dir.join("repo/sub/b.txt")]
+        );
+    }
+
+    #[test]
+    fn test_filter_listed() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join(".github")).unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        std::fs::write(dir.join("b.rs"), "b").unwrap();
+        std::fs::write(dir.join("c.bin"), b"c\0").unwrap();
+        std::fs::write(dir.join(".github/d.txt"), "d").unwrap();
+        std::fs::write(dir.join(".gitignore"), "a.txt\n").unwrap();
+        let listed = [
+            "a.txt",
+            "b.rs",
+            "c.bin",
+            ".github/d.txt",
+            "sub",
+            "deleted.txt",
+        ]
+        .iter()
+        .map(|path| dir.join(path))
+        .collect::<Vec<_>>();
+
+        let filter = |options: &WalkOptions| filter_listed(&dir, listed.clone(), options).unwrap();
+
+        // The ignore files do not apply, but missing paths and directories are skipped
+        assert_eq!(
+            filter(&WalkOptions::default()),
+            vec![dir.join("a.txt"), dir.join("b.rs")]
+        );
+        assert_eq!(
+            filter(&WalkOptions {
+                hidden: true,
+                binary: true,
+                ..Default::default()
+            }),
+            vec![
+                dir.join("a.txt"),
+                dir.join("b.rs"),
+                dir.join("c.bin"),
+                dir.join(".github/d.txt"),
+            ]
+        );
+        assert_eq!(
+            filter(&WalkOptions {
+                globs: vec![String::from("*.rs")],
+                ..Default::default()
+            }),
+            vec![dir.join("b.rs")]
+        );
+        assert_eq!(
+            filter(&WalkOptions {
+                max_depth: 1,
+                ..Default::default()
+            }),
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[test]
+    fn test_is_hidden() {
+        assert!(is_hidden(Path::new(".env")));
+        assert!(is_hidden(Path::new("dir/.hidden")));
+        assert!(!is_hidden(Path::new("dir.d/file")));
+        assert!(!is_hidden(Path::new(".")));
+        assert!(!is_hidden(Path::new("..")));
+    }
+
+    #[test]
+    fn test_regex_cache() {
+        let cache = Arc::new(RegexCache::new(8));
+        let options = WalkOptions {
+            regex_cache: Some(Arc::clone(&cache)),
+            ..Default::default()
+        };
+        let gitignore_content = b"*.log\n!keep.log";
+
+        for _ in 0..3 {
+            let ignore =
+                GitIgnore::from_reader(PathBuf::new(), &gitignore_content[..], &options).unwrap();
+            assert!(ignore.is_match(Path::new("error.log"), false));
+            assert!(!ignore.is_match(Path::new("keep.log"), false));
+        }
+
+        // The set of patterns was only compiled once
+        assert_eq!(cache.num_compiles(), 1);
+    }
 }
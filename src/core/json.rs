@@ -0,0 +1,41 @@
+//! Just enough JSON to write the output of a search, without pulling in a serializer.
+
+/// Appends `s` to `buf` as a JSON string, in quotes.
+///
+/// Quotes, backslashes and control chars are escaped. Other chars (including non-ASCII ones) are
+/// written as is, since JSON is UTF-8.
+pub fn push_string(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if c.is_control() => buf.push_str(&format!("\\u{:04x}", c as u32)),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_string() {
+        let string = |s: &str| {
+            let mut buf = String::new();
+            push_string(&mut buf, s);
+            buf
+        };
+
+        assert_eq!(string("abc"), r#""abc""#);
+        assert_eq!(string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(string("a\tb\r\n"), r#""a\tb\r\n""#);
+        assert_eq!(string("\x1b[0m\x7f"), r#""\u001b[0m\u007f""#);
+        assert_eq!(string("日本 ✓"), "\"日本 ✓\"");
+    }
+}
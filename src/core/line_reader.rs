@@ -0,0 +1,195 @@
+//! Line-by-line reading that keeps track of line numbers and byte offsets.
+
+use std::io::BufRead;
+
+use anyhow::{Context, Result, bail};
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// What to do when a line is not valid UTF-8.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Fails the read.
+    #[default]
+    Error,
+    /// Replaces invalid sequences with U+FFFD.
+    Lossy,
+}
+
+/// Wraps a reader and yields its lines one at a time.
+///
+/// Each line comes with its (1-indexed) line number and the byte offset of the start of the line.
+/// Line terminators (`\n` and `\r\n`) are stripped, and a final line without a terminator is
+/// still yielded. A UTF-8 byte order mark at the start of the input is stripped too, so the
+/// first line's byte offset is that of the text after it.
+pub struct LineReader<R: BufRead> {
+    reader: R,
+    invalid_utf8: InvalidUtf8,
+
+    line_number: usize,
+    byte_offset: usize,
+    buf: Vec<u8>,
+    line: String,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            invalid_utf8: InvalidUtf8::default(),
+            line_number: 0,
+            byte_offset: 0,
+            buf: Vec::new(),
+            line: String::new(),
+        }
+    }
+
+    pub fn invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> Self {
+        self.invalid_utf8 = invalid_utf8;
+        self
+    }
+
+    /// Reads the next line, returning `(line_number, byte_offset, line)`.
+    /// Returns None at the end of the input.
+    ///
+    /// This cannot be an `Iterator` because the returned line borrows from the internal buffer.
+    pub fn next_line(&mut self) -> Result<Option<(usize, usize, &str)>> {
+        self.buf.clear();
+        let n = self
+            .reader
+            .read_until(b'\n', &mut self.buf)
+            .context("could not read line")?;
+        if n == 0 {
+            return Ok(None);
+        }
+
+        let mut byte_offset = self.byte_offset;
+        self.byte_offset += n;
+        self.line_number += 1;
+
+        let mut bytes = &self.buf[..];
+        if self.line_number == 1
+            && let Some(stripped) = bytes.strip_prefix(UTF8_BOM)
+        {
+            bytes = stripped;
+            byte_offset += UTF8_BOM.len();
+        }
+        if let Some(stripped) = bytes.strip_suffix(b"\n") {
+            bytes = stripped.strip_suffix(b"\r").unwrap_or(stripped);
+        }
+
+        self.line.clear();
+        match std::str::from_utf8(bytes) {
+            Ok(s) => self.line.push_str(s),
+            Err(_) if self.invalid_utf8 == InvalidUtf8::Lossy => {
+                self.line.push_str(&String::from_utf8_lossy(bytes))
+            }
+            Err(err) => bail!("line {} is not valid UTF-8: {}", self.line_number, err),
+        }
+
+        Ok(Some((self.line_number, byte_offset, &self.line)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(input: &[u8], invalid_utf8: InvalidUtf8) -> Result<Vec<(usize, usize, String)>> {
+        let mut reader = LineReader::new(input).invalid_utf8(invalid_utf8);
+        let mut lines = Vec::new();
+        while let Some((line_number, byte_offset, line)) = reader.next_line()? {
+            lines.push((line_number, byte_offset, line.to_string()));
+        }
+        Ok(lines)
+    }
+
+    fn lines(input: &[u8]) -> Vec<(usize, usize, String)> {
+        read_all(input, InvalidUtf8::Error).unwrap()
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(lines(b""), vec![]);
+    }
+
+    #[test]
+    fn test_trailing_newline() {
+        assert_eq!(
+            lines(b"abc\ndef\n"),
+            vec![(1, 0, "abc".to_string()), (2, 4, "def".to_string())]
+        );
+        // A lone newline is a single blank line
+        assert_eq!(lines(b"\n"), vec![(1, 0, "".to_string())]);
+    }
+
+    #[test]
+    fn test_no_trailing_newline() {
+        assert_eq!(
+            lines(b"abc\ndef"),
+            vec![(1, 0, "abc".to_string()), (2, 4, "def".to_string())]
+        );
+        assert_eq!(lines(b"abc"), vec![(1, 0, "abc".to_string())]);
+    }
+
+    #[test]
+    fn test_crlf() {
+        assert_eq!(
+            lines(b"abc\r\ndef\r\nghi"),
+            vec![
+                (1, 0, "abc".to_string()),
+                (2, 5, "def".to_string()),
+                (3, 10, "ghi".to_string()),
+            ]
+        );
+        // A carriage return without a newline is part of the line
+        assert_eq!(lines(b"abc\rdef"), vec![(1, 0, "abc\rdef".to_string())]);
+        assert_eq!(lines(b"abc\r"), vec![(1, 0, "abc\r".to_string())]);
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        assert_eq!(
+            lines(b"\n\nabc\n\n"),
+            vec![
+                (1, 0, "".to_string()),
+                (2, 1, "".to_string()),
+                (3, 2, "abc".to_string()),
+                (4, 6, "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multibyte_offsets() {
+        assert_eq!(
+            lines("🦀\n게\n".as_bytes()),
+            vec![(1, 0, "🦀".to_string()), (2, 5, "게".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_bom() {
+        assert_eq!(
+            lines(b"\xEF\xBB\xBFabc\ndef\n"),
+            vec![(1, 3, "abc".to_string()), (2, 7, "def".to_string())]
+        );
+        assert_eq!(lines(b"\xEF\xBB\xBF"), vec![(1, 3, "".to_string())]);
+        // Only a leading BOM is stripped
+        assert_eq!(
+            lines(b"abc\n\xEF\xBB\xBFdef"),
+            vec![(1, 0, "abc".to_string()), (2, 4, "\u{FEFF}def".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let input = b"abc\n\xffdef\n";
+
+        assert!(read_all(input, InvalidUtf8::Error).is_err());
+        assert_eq!(
+            read_all(input, InvalidUtf8::Lossy).unwrap(),
+            vec![(1, 0, "abc".to_string()), (2, 4, "\u{FFFD}def".to_string())]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Ordering of rotated log files, e.g. `app.log`, `app.log.1` and `app.log.2.gz`.
+
+use std::path::{Path, PathBuf};
+
+/// The order to search rotated log files in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RotationOrder {
+    /// The live log first, then `.1`, `.2` and so on.
+    #[default]
+    NewestFirst,
+    /// The oldest rotation first, ending with the live log.
+    OldestFirst,
+}
+
+/// Splits a log file name into the name of the live log and its rotation number.
+///
+/// The live log has rotation number 0, and a trailing `.gz` is ignored.
+/// For example, `app.log.2.gz` is rotation 2 of `app.log`.
+fn rotation(file_name: &str) -> (&str, usize) {
+    let name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+
+    if let Some((live_name, suffix)) = name.rsplit_once('.')
+        && !live_name.is_empty()
+        && !suffix.is_empty()
+        && suffix.bytes().all(|b| b.is_ascii_digit())
+        && let Ok(number) = suffix.parse()
+    {
+        return (live_name, number);
+    }
+
+    (name, 0)
+}
+
+/// Sorts `file_paths` so that the rotations of each log are together, in `order`.
+///
+/// Files are grouped by directory and live log name, so other files are sorted by name.
+pub fn sort_rotated(file_paths: &mut [PathBuf], order: RotationOrder) {
+    let key = |path: &Path| {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (live_name, number) = rotation(&file_name);
+        (
+            (path.parent().map(Path::to_path_buf), live_name.to_string()),
+            number,
+        )
+    };
+
+    file_paths.sort_by(|a, b| {
+        let (a_log, a_number) = key(a);
+        let (b_log, b_number) = key(b);
+        a_log.cmp(&b_log).then_with(|| match order {
+            RotationOrder::NewestFirst => a_number.cmp(&b_number),
+            RotationOrder::OldestFirst => b_number.cmp(&a_number),
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        assert_eq!(rotation("app.log"), ("app.log", 0));
+        assert_eq!(rotation("app.log.1"), ("app.log", 1));
+        assert_eq!(rotation("app.log.12.gz"), ("app.log", 12));
+        assert_eq!(rotation("app.log.gz"), ("app.log", 0));
+        // Not rotations
+        assert_eq!(rotation("app.log.+1"), ("app.log.+1", 0));
+        assert_eq!(rotation(".1"), (".1", 0));
+        assert_eq!(rotation("app.log."), ("app.log.", 0));
+    }
+
+    #[test]
+    fn test_sort_rotated() {
+        let paths = |names: &[&str]| -> Vec<PathBuf> {
+            names
+                .iter()
+                .map(|name| PathBuf::from("logs").join(name))
+                .collect()
+        };
+        let unsorted = paths(&[
+            "app.log.10",
+            "db.log.1",
+            "app.log.2.gz",
+            "app.log",
+            "db.log",
+            "app.log.1",
+        ]);
+
+        let mut file_paths = unsorted.clone();
+        sort_rotated(&mut file_paths, RotationOrder::NewestFirst);
+        assert_eq!(
+            file_paths,
+            paths(&[
+                "app.log",
+                "app.log.1",
+                "app.log.2.gz",
+                "app.log.10",
+                "db.log",
+                "db.log.1",
+            ])
+        );
+
+        let mut file_paths = unsorted;
+        sort_rotated(&mut file_paths, RotationOrder::OldestFirst);
+        assert_eq!(
+            file_paths,
+            paths(&[
+                "app.log.10",
+                "app.log.2.gz",
+                "app.log.1",
+                "app.log",
+                "db.log.1",
+                "db.log",
+            ])
+        );
+    }
+}
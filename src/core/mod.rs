@@ -1,4 +1,13 @@
+pub mod color;
+pub mod compressed;
 pub mod escaped_strings;
+pub mod file_types;
+pub mod git;
 pub mod ignore;
+pub mod json;
+pub mod line_reader;
+pub mod log_rotation;
+pub mod pager;
+pub mod regex_cache;
 pub mod threads;
 pub mod writer;
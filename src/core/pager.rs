@@ -0,0 +1,89 @@
+//! Paging output through `$PAGER`.
+
+use std::io::{ErrorKind, IsTerminal, Result, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+const DEFAULT_PAGER: &str = "less";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PagerChoice {
+    /// Pages only if stdout is a terminal.
+    Auto,
+    Always,
+    #[default]
+    Never,
+}
+
+impl PagerChoice {
+    /// Checks if output should be paged.
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            PagerChoice::Auto => std::io::stdout().is_terminal(),
+            PagerChoice::Always => true,
+            PagerChoice::Never => false,
+        }
+    }
+}
+
+/// A pager process that output is written to.
+///
+/// If the pager exits early (e.g. the user quits `less`), the rest of the output is discarded
+/// instead of being an error. Dropping the pager waits for it to exit.
+pub struct Pager {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Pager {
+    /// Spawns `$PAGER`, or `less` if it is not set.
+    ///
+    /// Unless `$LESS` is already set, `less` is told to pass color codes through (`-R`).
+    pub fn spawn() -> Result<Self> {
+        let pager = std::env::var("PAGER").unwrap_or_default();
+        let mut words = pager.split_whitespace();
+        let program = words.next().unwrap_or(DEFAULT_PAGER);
+
+        let mut command = Command::new(program);
+        command.args(words).stdin(Stdio::piped());
+        if std::env::var_os("LESS").is_none() {
+            command.env("LESS", "R");
+        }
+
+        let mut child = command.spawn()?;
+        let stdin = child.stdin.take();
+
+        Ok(Self { child, stdin })
+    }
+}
+
+/// Treats the pager exiting early as success, discarding the output.
+fn ignore_broken_pipe<T>(result: Result<T>, discarded: T) -> Result<T> {
+    match result {
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => Ok(discarded),
+        result => result,
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match &mut self.stdin {
+            Some(stdin) => ignore_broken_pipe(stdin.write(buf), buf.len()),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.stdin {
+            Some(stdin) => ignore_broken_pipe(stdin.flush(), ()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        // Closes the pager's stdin, so that it knows that there is no more output
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,219 @@
+//! A cache of compiled regexes, for embedders that search repeatedly with the same patterns.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Regex {
+        pattern: String,
+        case_insensitive: bool,
+    },
+    RegexSet {
+        patterns: Vec<String>,
+        case_insensitive: bool,
+    },
+}
+
+#[derive(Clone)]
+enum Compiled {
+    Regex(Regex),
+    RegexSet(RegexSet),
+}
+
+struct Entries {
+    /// Incremented on every access, to find the least recently used entry.
+    tick: u64,
+    map: HashMap<CacheKey, (u64, Compiled)>,
+}
+
+/// A thread-safe, bounded LRU cache of compiled `Regex` and `RegexSet`s.
+///
+/// Compiled regexes are reference counted internally, so handing out clones is cheap.
+pub struct RegexCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    num_compiles: AtomicUsize,
+}
+
+impl RegexCache {
+    /// Creates a cache that holds up to `capacity` compiled regexes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries {
+                tick: 0,
+                map: HashMap::new(),
+            }),
+            num_compiles: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of times a regex was actually compiled (i.e. cache misses).
+    pub fn num_compiles(&self) -> usize {
+        self.num_compiles.load(Ordering::Relaxed)
+    }
+
+    pub fn regex(&self, pattern: &str, case_insensitive: bool) -> Result<Regex, regex::Error> {
+        let key = CacheKey::Regex {
+            pattern: pattern.to_string(),
+            case_insensitive,
+        };
+        let compiled = self.get_or_compile(key, || {
+            RegexBuilder::new(pattern)
+                .case_insensitive(case_insensitive)
+                .build()
+                .map(Compiled::Regex)
+        })?;
+
+        match compiled {
+            Compiled::Regex(regex) => Ok(regex),
+            Compiled::RegexSet(_) => unreachable!("regex keys always map to a Regex"),
+        }
+    }
+
+    pub fn regex_set(
+        &self,
+        patterns: &[String],
+        case_insensitive: bool,
+    ) -> Result<RegexSet, regex::Error> {
+        let key = CacheKey::RegexSet {
+            patterns: patterns.to_vec(),
+            case_insensitive,
+        };
+        let compiled = self.get_or_compile(key, || {
+            RegexSetBuilder::new(patterns)
+                .case_insensitive(case_insensitive)
+                .build()
+                .map(Compiled::RegexSet)
+        })?;
+
+        match compiled {
+            Compiled::RegexSet(regex_set) => Ok(regex_set),
+            Compiled::Regex(_) => unreachable!("regex set keys always map to a RegexSet"),
+        }
+    }
+
+    fn get_or_compile<F>(&self, key: CacheKey, compile: F) -> Result<Compiled, regex::Error>
+    where
+        F: FnOnce() -> Result<Compiled, regex::Error>,
+    {
+        {
+            let mut entries = self.lock();
+            entries.tick += 1;
+            let tick = entries.tick;
+            if let Some((last_used, compiled)) = entries.map.get_mut(&key) {
+                *last_used = tick;
+                return Ok(compiled.clone());
+            }
+        }
+
+        // Compile without holding the lock, so other threads are not blocked by a slow compile
+        let compiled = compile()?;
+        self.num_compiles.fetch_add(1, Ordering::Relaxed);
+
+        if self.capacity == 0 {
+            return Ok(compiled);
+        }
+
+        let mut entries = self.lock();
+        if entries.map.len() >= self.capacity && !entries.map.contains_key(&key) {
+            let least_recently_used = entries
+                .map
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recently_used) = least_recently_used {
+                entries.map.remove(&least_recently_used);
+            }
+        }
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.map.insert(key, (tick, compiled.clone()));
+
+        Ok(compiled)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        // The entries are always left in a valid state, so ignore poisoning
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_hit() {
+        let cache = RegexCache::new(4);
+
+        let regex = cache.regex("fo+", false).unwrap();
+        assert!(regex.is_match("foo"));
+        assert_eq!(cache.num_compiles(), 1);
+
+        // Same pattern and flags
+        let regex = cache.regex("fo+", false).unwrap();
+        assert!(regex.is_match("foo"));
+        assert_eq!(cache.num_compiles(), 1);
+
+        // Different flags
+        let regex = cache.regex("fo+", true).unwrap();
+        assert!(regex.is_match("FOO"));
+        assert_eq!(cache.num_compiles(), 2);
+
+        // Regex sets are cached separately
+        let patterns = vec![String::from("fo+")];
+        cache.regex_set(&patterns, false).unwrap();
+        cache.regex_set(&patterns, false).unwrap();
+        assert_eq!(cache.num_compiles(), 3);
+
+        // Invalid patterns are not cached
+        assert!(cache.regex("(", false).is_err());
+        assert!(cache.regex("(", false).is_err());
+        assert_eq!(cache.num_compiles(), 3);
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let cache = RegexCache::new(2);
+
+        cache.regex("a", false).unwrap();
+        cache.regex("b", false).unwrap();
+        // `a` is now more recently used than `b`
+        cache.regex("a", false).unwrap();
+        assert_eq!(cache.num_compiles(), 2);
+
+        // Evicts `b`
+        cache.regex("c", false).unwrap();
+        assert_eq!(cache.num_compiles(), 3);
+        cache.regex("a", false).unwrap();
+        assert_eq!(cache.num_compiles(), 3);
+        cache.regex("b", false).unwrap();
+        assert_eq!(cache.num_compiles(), 4);
+    }
+
+    #[test]
+    fn test_cache_threads() {
+        let cache = std::sync::Arc::new(RegexCache::new(4));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = std::sync::Arc::clone(&cache);
+                std::thread::spawn(move || cache.regex("fo+", false).unwrap().is_match("foo"))
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+
+        // Threads may race to compile the same pattern, but it is cached afterwards
+        let num_compiles = cache.num_compiles();
+        assert!((1..=8).contains(&num_compiles));
+        cache.regex("fo+", false).unwrap();
+        assert_eq!(cache.num_compiles(), num_compiles);
+    }
+}
@@ -1,3 +1,4 @@
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use crossbeam::channel::{Sender, bounded};
@@ -10,9 +11,38 @@ pub trait Function: FnOnce() + Send + 'static {}
 impl<T: FnOnce() + Send + 'static> Function for T {}
 type FunctionBox = Box<dyn Function>;
 
+/// A task that panicked, with the panic's message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskError {
+    pub message: String,
+}
+
+impl std::fmt::Display for TaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a task panicked: {}", self.message)
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+impl TaskError {
+    fn from_panic(payload: Box<dyn std::any::Any + Send>) -> Self {
+        // `panic!` with a literal gives a &str, and with a format string gives a String
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            String::from("unknown panic")
+        };
+        Self { message }
+    }
+}
+
 pub struct ThreadPool {
     tx: Sender<FunctionBox>,
     handles: Vec<thread::JoinHandle<()>>,
+    errors: Arc<Mutex<Vec<TaskError>>>,
 }
 
 /// A pool of threads for executing functions.
@@ -24,7 +54,7 @@ pub struct ThreadPool {
 impl ThreadPool {
     /// Creates a new ThreadPool with `num_threads`.
     pub fn new(num_threads: usize) -> Self {
-        if num_threads <= 0 {
+        if num_threads == 0 {
             panic!(
                 "ThreadPool expects a positive num_threads, but {} was provided",
                 num_threads
@@ -34,18 +64,23 @@ impl ThreadPool {
         let (tx, rx) = bounded::<FunctionBox>(num_threads * CHANNEL_MULITPLIER);
 
         let mut handles = Vec::new();
+        let errors = Arc::new(Mutex::new(Vec::new()));
 
         for _ in 0..num_threads {
             let rx = rx.clone();
+            let errors = Arc::clone(&errors);
 
             let handle = thread::spawn(move || {
                 while let Ok(function) = rx.recv() {
-                    // TODO: Have some way to handle errors...?
+                    // A panicking task should not take the worker (and its other tasks) down
                     let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
                         function();
                     }));
-                    if let Err(_) = result {
-                        eprintln!("Worker thread caught a panic in a task!");
+                    if let Err(payload) = result {
+                        errors
+                            .lock()
+                            .unwrap_or_else(|err| err.into_inner())
+                            .push(TaskError::from_panic(payload));
                     }
                 }
             });
@@ -53,8 +88,9 @@ impl ThreadPool {
         }
 
         Self {
-            tx: tx,
-            handles: handles,
+            tx,
+            handles,
+            errors,
         }
     }
 
@@ -71,20 +107,32 @@ impl ThreadPool {
     /// Executes a function.
     ///
     /// This blocks until one of the threads actually start executing the function.
-    pub fn execute<F: Function>(&self, function: F) -> () {
+    pub fn execute<F: Function>(&self, function: F) {
         self.tx.send(Box::new(function)).unwrap();
     }
 
-    /// Waits for all threads to complete.
+    /// Waits for all threads to complete, returning the errors of the tasks that panicked.
+    /// The other tasks still ran to completion.
     ///
     /// The ThreadPool will no longer be usable.
-    pub fn wait(self) {
+    pub fn wait(self) -> Result<(), Vec<TaskError>> {
         drop(self.tx); // Close the channel
 
+        let mut errors = Vec::new();
         for handle in self.handles {
-            // TODO: Handle this properly
-            // https://doc.rust-lang.org/std/thread/struct.JoinHandle.html#method.join
-            handle.join().unwrap();
+            // Tasks cannot panic the worker, so this is only for completeness
+            if let Err(payload) = handle.join() {
+                errors.push(TaskError::from_panic(payload));
+            }
+        }
+
+        errors.extend(std::mem::take(
+            &mut *self.errors.lock().unwrap_or_else(|err| err.into_inner()),
+        ));
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
 }
@@ -120,6 +168,37 @@ mod tests {
             sleep(Duration::from_secs(2));
         });
 
-        thread_pool.wait();
+        thread_pool.wait().unwrap();
+    }
+
+    #[test]
+    fn test_thread_pool_errors() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let thread_pool = ThreadPool::new(2);
+        let num_done = Arc::new(AtomicUsize::new(0));
+        for i in 0..10 {
+            let num_done = Arc::clone(&num_done);
+            thread_pool.execute(move || {
+                if i % 4 == 0 {
+                    panic!("task {} failed", i);
+                }
+                num_done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+
+        let mut errors = thread_pool.wait().unwrap_err();
+        errors.sort_by(|a, b| a.message.cmp(&b.message));
+        let messages: Vec<_> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["task 0 failed", "task 4 failed", "task 8 failed"]
+        );
+        // The other tasks were not affected
+        assert_eq!(num_done.load(Ordering::Relaxed), 7);
+
+        let thread_pool = ThreadPool::new(2);
+        thread_pool.execute(|| {});
+        assert_eq!(thread_pool.wait(), Ok(()));
     }
 }
@@ -1,50 +1,521 @@
+use std::collections::BTreeMap;
 use std::io::{Result, Stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 const BUF_SIZE: usize = 8192;
 
-pub struct SynchronizedWriter {
-    writer: Stdout,
+/// A writer that can be cloned and shared between threads.
+///
+/// Each `write_all` holds the lock for the whole call, so it is never interleaved with writes
+/// from other threads.
+#[derive(Clone)]
+pub struct SharedWriter {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    has_section: Arc<AtomicBool>,
+}
+
+impl SharedWriter {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            has_section: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Writes `section` in one go, preceded by `separator` if this or any of its clones already
+    /// wrote a section. This way, separators only go between sections, never before or after them.
+    pub fn write_section(&mut self, separator: &[u8], section: &[u8]) -> Result<()> {
+        let mut writer = self.lock();
+        // Checked while holding the lock, so that the first section is the first one written
+        if !self.has_section.swap(true, Ordering::Relaxed) {
+            return writer.write_all(section);
+        }
+
+        let mut buf = Vec::with_capacity(separator.len() + section.len());
+        buf.extend(separator);
+        buf.extend(section);
+        writer.write_all(&buf)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Box<dyn Write + Send>> {
+        // A panic while writing cannot leave the writer in an invalid state, so ignore poisoning
+        self.writer.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.lock().flush()
+    }
+}
+
+/// Writes the output of each task in the order that the tasks were started, as soon as the task
+/// and every task before it are done, e.g. so that files searched in parallel are printed in
+/// order. The output of a task is kept in memory until then.
+#[derive(Clone)]
+pub struct OrderedWriter {
+    state: Arc<Mutex<OrderedState>>,
+}
+
+struct OrderedState {
+    writer: SharedWriter,
+    separator: &'static [u8],
+    num_tasks: usize,
+    /// The task whose output is written next.
+    next_task: usize,
+    /// The output of the tasks that are done, but have to wait for an earlier task.
+    done: BTreeMap<usize, Vec<u8>>,
+    /// The first error from writing the output of a task.
+    error: Option<std::io::Error>,
+}
+
+impl OrderedWriter {
+    /// The output of each task is written with `SharedWriter::write_section`, so `separator`
+    /// only goes between the tasks with output.
+    pub fn new(writer: SharedWriter, separator: &'static [u8]) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(OrderedState {
+                writer,
+                separator,
+                num_tasks: 0,
+                next_task: 0,
+                done: BTreeMap::new(),
+                error: None,
+            })),
+        }
+    }
+
+    /// Starts a task, returning the writer for its output. The task is done once the writer and
+    /// all of its clones are dropped.
+    pub fn task(&self) -> SharedWriter {
+        let mut state = self.lock();
+        let index = state.num_tasks;
+        state.num_tasks += 1;
+
+        SharedWriter::new(TaskWriter {
+            index,
+            buf: Vec::new(),
+            state: Arc::clone(&self.state),
+        })
+    }
+
+    /// Returns the first error from writing the output of a task, if any. Later output is
+    /// dropped after an error.
+    pub fn finish(&self) -> Result<()> {
+        match self.lock().error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, OrderedState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl OrderedState {
+    /// Writes the output of task `index`, along with that of any later tasks that were waiting
+    /// for it.
+    fn done(&mut self, index: usize, buf: Vec<u8>) {
+        self.done.insert(index, buf);
+        while let Some(buf) = self.done.remove(&self.next_task) {
+            self.next_task += 1;
+            if buf.is_empty() || self.error.is_some() {
+                continue;
+            }
+            if let Err(err) = self.writer.write_section(self.separator, &buf) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+/// The output of a single task of an `OrderedWriter`, which is handed back to it when dropped.
+struct TaskWriter {
+    index: usize,
+    buf: Vec<u8>,
+    state: Arc<Mutex<OrderedState>>,
+}
+
+impl Write for TaskWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.buf.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TaskWriter {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.buf);
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .done(self.index, buf);
+    }
+}
+
+/// Buffers everything written to it and writes it out in one go, prefixed by a header.
+///
+/// The header and the buffer are written with a single `write_all` call, and `Stdout` holds its
+/// lock for the whole call, so output from different writers never interleaves.
+/// As long as callers write whole lines at a time, a line (along with any color codes in it) is
+/// never split across flushes either.
+pub struct SynchronizedWriter<W: Write = Stdout> {
+    writer: W,
     header: String,
     buf: Vec<u8>,
+    max_buf: Option<usize>,
+}
+
+impl<W: Write> SynchronizedWriter<W> {
+    pub fn new(writer: W, header: String) -> Self {
+        Self {
+            writer,
+            header,
+            buf: Vec::with_capacity(BUF_SIZE),
+            max_buf: None,
+        }
+    }
+
+    /// Flushes early once the buffer reaches `max_buf` bytes, to bound memory usage.
+    ///
+    /// Output from other writers can then come between the flushes, so each flush starts with
+    /// the header again, and the lines can still be told apart.
+    pub fn max_buffer(mut self, max_buf: Option<usize>) -> Self {
+        self.max_buf = max_buf;
+        self
+    }
+}
+
+impl<W: Write> Write for SynchronizedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.buf.is_empty() {
+            // header will only be printed if something was actually written
+            self.buf.extend(self.header.as_bytes());
+            self.buf.push(b'\n');
+        }
+
+        self.buf.extend(buf);
+        if self
+            .max_buf
+            .is_some_and(|max_buf| self.buf.len() >= max_buf)
+        {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        self.writer.write_all(&self.buf)?;
+        self.writer.flush()?;
+
+        self.buf.clear();
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for SynchronizedWriter<W> {
+    fn drop(&mut self) {
+        // TODO: Do we need to handle this failing?
+        let _ = self.flush();
+    }
+}
+
+/// Like `SynchronizedWriter`, but writes the buffer out as a Markdown section: a `###` heading
+/// followed by the buffer in a fenced code block. Sections are separated by a blank line.
+///
+/// The fence is longer than any run of backticks in the buffer, so lines cannot close it early.
+pub struct MarkdownWriter {
+    writer: SharedWriter,
+    heading: String,
+    buf: Vec<u8>,
+    max_buf: Option<usize>,
 }
 
-impl SynchronizedWriter {
-    pub fn new(writer: Stdout, header: String) -> Self {
+impl MarkdownWriter {
+    pub fn new(writer: SharedWriter, heading: String) -> Self {
         Self {
-            writer: writer,
-            header: header,
+            writer,
+            heading,
             buf: Vec::with_capacity(BUF_SIZE),
+            max_buf: None,
         }
     }
+
+    /// Like `SynchronizedWriter::max_buffer`: each early flush is a section of its own, with
+    /// the same heading.
+    pub fn max_buffer(mut self, max_buf: Option<usize>) -> Self {
+        self.max_buf = max_buf;
+        self
+    }
 }
 
-impl Write for SynchronizedWriter {
+/// Returns the length of the longest run of backticks in `buf`.
+fn longest_backtick_run(buf: &[u8]) -> usize {
+    buf.split(|&b| b != b'`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0)
+}
+
+impl Write for MarkdownWriter {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         self.buf.extend(buf);
+        if self
+            .max_buf
+            .is_some_and(|max_buf| self.buf.len() >= max_buf)
+        {
+            self.flush()?;
+        }
         Ok(buf.len())
     }
 
     fn flush(&mut self) -> Result<()> {
-        if self.buf.len() == 0 {
+        if self.buf.is_empty() {
             return Ok(());
         }
 
-        let mut writer = self.writer.lock();
+        let fence = "`".repeat(3.max(longest_backtick_run(&self.buf) + 1));
 
-        writer.write_fmt(format_args!("{}\n", self.header))?;
-        writer.write_all(&self.buf)?;
+        let mut section = format!("### {}\n\n{}\n", self.heading, fence).into_bytes();
+        section.extend(&self.buf);
+        if !self.buf.ends_with(b"\n") {
+            section.push(b'\n');
+        }
+        section.extend(format!("{}\n", fence).as_bytes());
 
-        let result = writer.flush()?;
+        self.writer.write_section(b"\n", &section)?;
+        self.writer.flush()?;
 
         self.buf.clear();
 
-        Ok(result)
+        Ok(())
     }
 }
 
-impl Drop for SynchronizedWriter {
+impl Drop for MarkdownWriter {
     fn drop(&mut self) {
         // TODO: Do we need to handle this failing?
         let _ = self.flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::color::{ColorChoice, RESET};
+    use crate::grep::matcher::{MatchOptions, PatternSet, find_matches};
+
+    /// Records each `write` call as a separate block.
+    #[derive(Clone, Default)]
+    struct MockWriter {
+        blocks: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl Write for MockWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.blocks.lock().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_shared_writer() {
+        let mock = MockWriter::default();
+        let shared = SharedWriter::new(mock.clone());
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let mut shared = shared.clone();
+                std::thread::spawn(move || {
+                    let line = format!("{}\n", i).repeat(100);
+                    shared.write_all(line.as_bytes()).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Each block was written in one go
+        let blocks = mock.blocks.lock().unwrap();
+        assert_eq!(blocks.len(), 4);
+        for block in blocks.iter() {
+            assert!(block.iter().all(|&b| b == block[0] || b == b'\n'));
+        }
+    }
+
+    #[test]
+    fn test_header_only_if_written() {
+        let mock = MockWriter::default();
+        drop(SynchronizedWriter::new(
+            mock.clone(),
+            String::from("a.txt:"),
+        ));
+        assert!(mock.blocks.lock().unwrap().is_empty());
+
+        let mut writer = SynchronizedWriter::new(mock.clone(), String::from("a.txt:"));
+        writer.write_all(b"1: abc\n").unwrap();
+        writer.write_all(b"2: def\n").unwrap();
+        drop(writer);
+        assert_eq!(
+            *mock.blocks.lock().unwrap(),
+            vec![b"a.txt:\n1: abc\n2: def\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_max_buffer() {
+        let mock = MockWriter::default();
+        let input = "foo\n".repeat(5);
+        let options = MatchOptions {
+            show_line_numbers: true,
+            ..Default::default()
+        };
+        let patterns = PatternSet::new(&[String::from("foo")], &options).unwrap();
+
+        find_matches(
+            input.as_bytes(),
+            SynchronizedWriter::new(mock.clone(), String::from("a.txt:")).max_buffer(Some(20)),
+            None,
+            &patterns,
+            &options,
+        )
+        .unwrap();
+
+        // Whole lines are flushed as soon as the buffer is full, each with the header
+        assert_eq!(
+            *mock.blocks.lock().unwrap(),
+            vec![
+                b"a.txt:\n1: foo\n2: foo\n".to_vec(),
+                b"a.txt:\n3: foo\n4: foo\n".to_vec(),
+                b"a.txt:\n5: foo\n".to_vec(),
+            ]
+        );
+
+        let mock = MockWriter::default();
+        let mut writer = MarkdownWriter::new(SharedWriter::new(mock.clone()), String::from("a.md"))
+            .max_buffer(Some(7));
+        writer.write_all(b"1: abc\n").unwrap();
+        writer.write_all(b"2: def\n").unwrap();
+        drop(writer);
+        assert_eq!(
+            *mock.blocks.lock().unwrap(),
+            vec![
+                b"### a.md\n\n```\n1: abc\n```\n".to_vec(),
+                b"\n### a.md\n\n```\n2: def\n```\n".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_color_codes_are_not_split() {
+        let mock = MockWriter::default();
+        let input = b"foo bar foo\nbaz\nfoofoo\n";
+        let options = MatchOptions {
+            color: ColorChoice::Always,
+            ..Default::default()
+        };
+        let patterns = PatternSet::new(&[String::from("foo")], &options).unwrap();
+
+        find_matches(
+            &input[..],
+            SynchronizedWriter::new(mock.clone(), String::from("a.txt:")),
+            None,
+            &patterns,
+            &options,
+        )
+        .unwrap();
+
+        let blocks = mock.blocks.lock().unwrap();
+        assert_eq!(blocks.len(), 1);
+        for block in blocks.iter() {
+            let block = String::from_utf8(block.clone()).unwrap();
+            // Every line has balanced color codes
+            for line in block.lines() {
+                assert_eq!(
+                    line.matches("\x1b[").count(),
+                    2 * line.matches(RESET).count()
+                );
+            }
+            assert_eq!(block.matches(RESET).count(), 4);
+        }
+    }
+
+    #[test]
+    fn test_ordered_writer() {
+        let mock = MockWriter::default();
+        let ordered = OrderedWriter::new(SharedWriter::new(mock.clone()), b"--\n");
+        let mut first = ordered.task();
+        let second = ordered.task();
+        let mut third = ordered.task();
+        first.write_all(b"1\n").unwrap();
+        third.write_all(b"3\n").unwrap();
+
+        // Waits for the tasks before it
+        drop(third);
+        drop(second);
+        assert!(mock.blocks.lock().unwrap().is_empty());
+
+        // Only done once every clone is dropped
+        let clone = first.clone();
+        drop(first);
+        assert!(mock.blocks.lock().unwrap().is_empty());
+        drop(clone);
+
+        // The task without output is not separated
+        assert_eq!(
+            *mock.blocks.lock().unwrap(),
+            vec![b"1\n".to_vec(), b"--\n3\n".to_vec()]
+        );
+        ordered.finish().unwrap();
+    }
+
+    #[test]
+    fn test_markdown_writer() {
+        let mock = MockWriter::default();
+        let shared = SharedWriter::new(mock.clone());
+        drop(MarkdownWriter::new(shared.clone(), String::from("a.md")));
+        assert!(mock.blocks.lock().unwrap().is_empty());
+
+        let mut writer = MarkdownWriter::new(shared.clone(), String::from("a.md"));
+        writer.write_all(b"1: abc\n").unwrap();
+        drop(writer);
+
+        let mut writer = MarkdownWriter::new(shared.clone(), String::from("b.md"));
+        writer.write_all(b"3: use ```rust\n").unwrap();
+        writer.write_all(b"4: ````").unwrap();
+        drop(writer);
+
+        assert_eq!(
+            *mock.blocks.lock().unwrap(),
+            vec![
+                b"### a.md\n\n```\n1: abc\n```\n".to_vec(),
+                // Only separated from the previous section, without a trailing blank line
+                b"\n### b.md\n\n`````\n3: use ```rust\n4: ````\n`````\n".to_vec(),
+            ]
+        );
+    }
+}
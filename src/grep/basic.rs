@@ -0,0 +1,137 @@
+//! Translation of POSIX basic regular expressions (BRE), as used by grep, to the regex crate's
+//! syntax.
+//!
+//! The supported subset is:
+//! * `(`, `)`, `{`, `}`, `+`, `?` and `|` are literal, and special when escaped with a backslash
+//!   (the latter three are GNU extensions)
+//! * `*` is literal at the start of the pattern, or right after `\(`, `\|` or a leading `^`
+//! * Bracket expressions (e.g. `[a-z]`, `[]abc]`, `[[:digit:]]`), where backslashes are literal
+//! * Any other escape (e.g. `\.` or `\w`) is passed through as is
+//!
+//! Back-references (e.g. `\1`) are not supported by the regex crate, so they fail to compile.
+
+/// Translates a basic regular expression to the regex crate's syntax.
+pub fn translate(pattern: &str) -> String {
+    let mut regex = String::with_capacity(pattern.len());
+
+    let mut chars = pattern.chars().peekable();
+    // Whether a `*` here would have nothing to repeat, which makes it literal
+    let mut at_start = true;
+
+    while let Some(c) = chars.next() {
+        let was_at_start = at_start;
+        at_start = false;
+
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | '|')) => {
+                    regex.push(c);
+                    at_start = true;
+                }
+                Some(c @ (')' | '{' | '}' | '+' | '?')) => regex.push(c),
+                Some(c) => {
+                    regex.push('\\');
+                    regex.push(c);
+                }
+                // A trailing backslash is invalid, so let the regex crate report it
+                None => regex.push('\\'),
+            },
+            '(' | ')' | '{' | '}' | '+' | '?' | '|' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            '*' if was_at_start => regex.push_str(r"\*"),
+            '^' if was_at_start => {
+                regex.push(c);
+                at_start = true;
+            }
+            '[' => translate_bracket(&mut chars, &mut regex),
+            _ => regex.push(c),
+        }
+    }
+
+    regex
+}
+
+/// Translates a bracket expression, after its opening `[`.
+fn translate_bracket(chars: &mut std::iter::Peekable<std::str::Chars>, regex: &mut String) {
+    regex.push('[');
+    if chars.next_if_eq(&'^').is_some() {
+        regex.push('^');
+    }
+    // A `]` at the start is literal
+    if chars.next_if_eq(&']').is_some() {
+        regex.push_str(r"\]");
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            ']' => {
+                regex.push(']');
+                return;
+            }
+            '[' if chars.peek() == Some(&':') => {
+                // Character classes like `[:digit:]` are copied as is
+                regex.push('[');
+                for c in chars.by_ref() {
+                    regex.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            // These are special in the regex crate's classes, but literal in BRE
+            '\\' | '[' | '&' | '~' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            _ => regex.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        // Escaped metacharacters are special
+        assert_eq!(translate(r"a\{2\}"), "a{2}");
+        assert_eq!(translate(r"\(ab\)\+c\?"), "(ab)+c?");
+        assert_eq!(translate(r"foo\|bar"), "foo|bar");
+        // Unescaped metacharacters are literal
+        assert_eq!(translate("a{2}"), r"a\{2\}");
+        assert_eq!(translate("(ab)+c?"), r"\(ab\)\+c\?");
+        assert_eq!(translate("foo|bar"), r"foo\|bar");
+        // Other syntax is the same
+        assert_eq!(translate(r"^a.*b\.c$"), r"^a.*b\.c$");
+        assert_eq!(translate(r"\w\+"), r"\w+");
+    }
+
+    #[test]
+    fn test_translate_literal_star() {
+        assert_eq!(translate("*a"), r"\*a");
+        assert_eq!(translate("^*a"), r"^\*a");
+        assert_eq!(translate(r"\(*a\)"), r"(\*a)");
+        assert_eq!(translate(r"a\|*b"), r"a|\*b");
+        assert_eq!(translate("a*"), "a*");
+    }
+
+    #[test]
+    fn test_translate_bracket() {
+        assert_eq!(translate("[a-z]+"), r"[a-z]\+");
+        assert_eq!(translate("[]a]"), r"[\]a]");
+        assert_eq!(translate("[^]a]"), r"[^\]a]");
+        assert_eq!(translate(r"[\n]"), r"[\\n]");
+        assert_eq!(translate("[[:digit:]x]"), "[[:digit:]x]");
+        assert_eq!(translate("[(){}]"), "[(){}]");
+    }
+
+    #[test]
+    fn test_translated_regex() {
+        let regex = regex::Regex::new(&translate(r"a\{2\}(b)")).unwrap();
+        assert!(regex.is_match("aa(b)"));
+        assert!(!regex.is_match("aab"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Masks comments and string literals in source code, so that searches only match the code.
+//!
+//! This is a small tokenizer rather than a parser, so it gets some edge cases wrong (e.g. raw
+//! strings, or heredocs in shell). Strings cannot span lines, but block comments can.
+
+/// A language whose comments and strings can be masked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Lang {
+    /// C-style languages: `//` and `/* */` comments, `"..."` strings and `'.'` chars.
+    C,
+    /// Shell: `#` comments and `'...'` and `"..."` strings.
+    Shell,
+}
+
+/// Masks the comments and strings in each line of an input, in order.
+pub struct CodeMasker {
+    lang: Lang,
+    in_block_comment: bool,
+}
+
+/// What the tokenizer is in the middle of.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Code,
+    BlockComment,
+    /// A string (or char) literal that ends with this quote.
+    String(char),
+}
+
+impl CodeMasker {
+    pub fn new(lang: Lang) -> Self {
+        Self {
+            lang,
+            in_block_comment: false,
+        }
+    }
+
+    /// Replaces every comment and string literal (along with its quotes) in `line` with spaces.
+    ///
+    /// Each char is replaced by as many spaces as it has bytes, so byte offsets in the masked
+    /// line are the same as in `line`.
+    pub fn mask(&mut self, line: &str) -> String {
+        let mut masked = String::with_capacity(line.len());
+        let mut state = if self.in_block_comment {
+            State::BlockComment
+        } else {
+            State::Code
+        };
+
+        let mut chars = line.chars().peekable();
+        let mut prev = None;
+        while let Some(c) = chars.next() {
+            let next = chars.peek().copied();
+            match state {
+                State::Code => match (self.lang, c, next) {
+                    (Lang::C, '/', Some('/')) => {
+                        push_masked(&mut masked, c);
+                        chars.by_ref().for_each(|c| push_masked(&mut masked, c));
+                    }
+                    (Lang::C, '/', Some('*')) => {
+                        push_masked(&mut masked, c);
+                        push_masked(&mut masked, chars.next().unwrap_or(' '));
+                        state = State::BlockComment;
+                    }
+                    (Lang::Shell, '#', _) if prev.is_none_or(char::is_whitespace) => {
+                        push_masked(&mut masked, c);
+                        chars.by_ref().for_each(|c| push_masked(&mut masked, c));
+                    }
+                    (Lang::C, '"' | '\'', _) | (Lang::Shell, '"' | '\'', _) => {
+                        push_masked(&mut masked, c);
+                        state = State::String(c);
+                    }
+                    _ => masked.push(c),
+                },
+                State::BlockComment => {
+                    push_masked(&mut masked, c);
+                    if c == '*' && next == Some('/') {
+                        push_masked(&mut masked, chars.next().unwrap_or(' '));
+                        state = State::Code;
+                    }
+                }
+                State::String(quote) => {
+                    push_masked(&mut masked, c);
+                    // Single quoted strings in shell have no escapes
+                    if c == '\\' && !(self.lang == Lang::Shell && quote == '\'') {
+                        if let Some(escaped) = chars.next() {
+                            push_masked(&mut masked, escaped);
+                        }
+                    } else if c == quote {
+                        state = State::Code;
+                    }
+                }
+            }
+            prev = Some(c);
+        }
+
+        self.in_block_comment = state == State::BlockComment;
+        masked
+    }
+}
+
+/// Appends as many spaces as `c` has bytes.
+fn push_masked(masked: &mut String, c: char) {
+    for _ in 0..c.len_utf8() {
+        masked.push(' ');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_c() {
+        let mut masker = CodeMasker::new(Lang::C);
+
+        assert_eq!(
+            masker.mask(r#"foo("foo \" foo", 'f'); // foo"#),
+            r#"foo(            ,    );       "#
+        );
+        assert_eq!(masker.mask("a /* foo */ b"), "a           b");
+        // Block comments span lines
+        assert_eq!(masker.mask("a /* foo"), "a       ");
+        assert_eq!(masker.mask("foo"), "   ");
+        assert_eq!(masker.mask("foo */ foo"), "       foo");
+        // Byte offsets are kept
+        assert_eq!(masker.mask("\"日\" x"), "      x");
+    }
+
+    #[test]
+    fn test_mask_shell() {
+        let mut masker = CodeMasker::new(Lang::Shell);
+
+        assert_eq!(masker.mask("echo foo # foo"), "echo foo      ");
+        assert_eq!(masker.mask("# foo"), "     ");
+        // Not a comment in the middle of a word
+        assert_eq!(masker.mask("echo a#foo"), "echo a#foo");
+        assert_eq!(
+            masker.mask(r#"foo 'a\' "b \" foo" foo"#),
+            r#"foo                 foo"#
+        );
+        // Comment markers from other languages are code
+        assert_eq!(masker.mask("foo // bar"), "foo // bar");
+    }
+}
@@ -0,0 +1,99 @@
+//! Decodes lines of encoded data, so that searches can match the data instead of its encoding.
+
+/// An encoding that lines can be decoded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Standard or URL-safe base64, with optional padding.
+    Base64,
+    /// Hex digits of either case, two per byte.
+    Hex,
+}
+
+impl Encoding {
+    /// Decodes `line`, ignoring whitespace around it, into (lossy) UTF-8 text.
+    /// Returns `None` if the line is empty or is not cleanly encoded.
+    pub fn decode(&self, line: &str) -> Option<String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        let bytes = match self {
+            Encoding::Base64 => decode_base64(line)?,
+            Encoding::Hex => decode_hex(line)?,
+        };
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    // Each char has 6 bits, so a single char left over cannot make a byte
+    if text.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut num_bits = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        num_bits += 6;
+        if num_bits >= 8 {
+            num_bits -= 8;
+            bytes.push((buffer >> num_bits) as u8);
+            buffer &= (1 << num_bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` also accepts a sign
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_base64() {
+        let decode = |line| Encoding::Base64.decode(line);
+        assert_eq!(decode("aGVsbG8gd29ybGQ="), Some("hello world".to_string()));
+        assert_eq!(decode("aGVsbG8gd29ybGQ"), Some("hello world".to_string()));
+        assert_eq!(decode("  Zm9v\t"), Some("foo".to_string()));
+        assert_eq!(decode("Zm9vYg=="), Some("foob".to_string()));
+        // URL-safe alphabet
+        assert_eq!(decode("-_8"), decode("+/8"));
+
+        assert_eq!(decode(""), None);
+        assert_eq!(decode("not base64!"), None);
+        assert_eq!(decode("Zm9vY"), None);
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        let decode = |line| Encoding::Hex.decode(line);
+        assert_eq!(decode("68656c6C6f"), Some("hello".to_string()));
+        assert_eq!(decode(" 666f6f "), Some("foo".to_string()));
+
+        assert_eq!(decode(""), None);
+        assert_eq!(decode("666"), None);
+        assert_eq!(decode("zz"), None);
+        assert_eq!(decode("+1"), None);
+    }
+}
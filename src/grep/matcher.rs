@@ -1,64 +1,2218 @@
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
 use std::io::BufRead;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-use anyhow::{Context, Result};
-use regex::RegexBuilder;
+use anyhow::{Context, Result, bail};
+use memchr::memmem::Finder;
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
 
+use crate::core::color::{self, ColorChoice};
+use crate::core::json;
+use crate::core::line_reader::{InvalidUtf8, LineReader};
+use crate::core::regex_cache::RegexCache;
+use crate::grep::code::{CodeMasker, Lang};
+use crate::grep::decode::Encoding;
+use crate::grep::replace::Template;
+
+/// Which of the matching lines in each input are selected.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineSelection {
+    #[default]
+    All,
+    /// Only the first matching line, which stops reading the input early.
+    First,
+    /// Only the last matching line, which is only written at the end of the input.
+    Last,
+}
+
+/// How the case of letters is matched.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CaseMode {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Insensitive, unless the pattern has an uppercase letter, like ripgrep's `--smart-case`.
+    /// See `has_uppercase`.
+    Smart,
+}
+
+impl CaseMode {
+    /// Whether `pattern` is matched case insensitively.
+    pub fn is_insensitive(self, pattern: &str) -> bool {
+        match self {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !has_uppercase(pattern),
+        }
+    }
+}
+
+/// Checks if `pattern` has an uppercase letter that it matches literally, including in a
+/// bracket class like `[A-Z]`. The letters of escapes (`\W`, `\p{Lu}`, `\x4F`), inline flags
+/// (`(?U)`) and group names (`(?P<Name>...)`) are not matched literally, so they do not count.
+fn has_uppercase(pattern: &str) -> bool {
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                // Any other escape is a class (`\W`), an assertion (`\B`) or punctuation, none of
+                // which is an uppercase literal. These are classes and code points, with or
+                // without braces, e.g. `\pL`, `\x{4F}`
+                if let Some(c @ ('p' | 'P' | 'x' | 'u' | 'U')) = chars.next() {
+                    if chars.next_if_eq(&'{').is_some() {
+                        chars.find(|&c| c == '}');
+                    } else {
+                        let len = match c {
+                            'x' => 2,
+                            'u' => 4,
+                            'U' => 8,
+                            _ => 1,
+                        };
+                        for _ in 0..len {
+                            chars.next_if(char::is_ascii_alphanumeric);
+                        }
+                    }
+                }
+            }
+            '(' if chars.next_if_eq(&'?').is_some() => {
+                // Skips the flags or the group name, up to the start of the group
+                chars.find(|&c| c == ':' || c == ')' || c == '>');
+            }
+            c if c.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// How inputs that are binary are searched. An input is binary if its start is not text, see
+/// `ignore::is_text`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BinaryFiles {
+    /// Searches them until the first matching line, but only writes `Binary file matches`.
+    Binary,
+    /// Searches them as if they were text, replacing invalid UTF-8 with U+FFFD.
+    Text,
+    /// Assumes that they do not match, without searching them.
+    WithoutMatch,
+}
+
+/// A capture group in a pattern, either by its index or its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureGroup {
+    Index(usize),
+    Name(String),
+}
+
+impl std::str::FromStr for CaptureGroup {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => CaptureGroup::Index(index),
+            Err(_) => CaptureGroup::Name(s.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for CaptureGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureGroup::Index(index) => write!(f, "{}", index),
+            CaptureGroup::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct MatchOptions {
     pub show_line_numbers: bool,
-    pub case_insensitive: bool,
+    pub case: CaseMode,
+    /// Stops looking for more matches on a line after this many matches.
+    /// The line still counts as a single matching line.
+    pub max_matches_per_line: Option<usize>,
+    pub color: ColorChoice,
+    /// Treats the pattern as a literal string instead of a regex.
+    pub fixed_strings: bool,
+    /// Only matches whole words.
+    pub word_regexp: bool,
+    /// Only matches whole lines.
+    pub line_regexp: bool,
+    /// Only counts the matching lines, without writing them.
+    pub count_only: bool,
+    /// Counts identical matching lines once.
+    /// The distinct lines are kept in memory, so this fails after `max_distinct` distinct lines.
+    pub distinct: bool,
+    pub max_distinct: Option<usize>,
+    /// Lines that also match this pattern are not considered matches.
+    /// It is built with the same options as the search pattern.
+    pub not_pattern: Option<String>,
+    /// Selects the lines that are not matches instead, i.e. the lines that do not match the
+    /// pattern or that also match `not_pattern`. They have no submatches to highlight.
+    pub invert_match: bool,
+    /// Replaces every match with this template, see `replace` for the supported syntax.
+    pub replace: Option<String>,
+    /// Reuses compiled patterns across searches.
+    pub regex_cache: Option<Arc<RegexCache>>,
+    pub select: LineSelection,
+    /// Shows context around each match based on how far it is from the neighbouring matches,
+    /// up to this many lines. See `write_auto_context` for the details.
+    /// The whole input is kept in memory.
+    pub context_auto: Option<usize>,
+    /// Writes these capture groups of each match, joined by `capture_separator`, instead of the
+    /// whole line. Matches where any of the groups did not participate are not written.
+    pub captures: Vec<CaptureGroup>,
+    pub capture_separator: String,
+    /// Writes each match on its own line, instead of the whole line.
+    /// Empty matches (e.g. of an empty pattern) are not written, but the line still matches.
+    pub only_matching: bool,
+    /// Only considers the nth (1-based) match on each line, after `max_matches_per_line`.
+    /// Lines with fewer matches are not considered matches.
+    pub nth_match: Option<usize>,
+    /// Adds the number of bytes read from each input to this, even if the search fails partway.
+    pub bytes_searched: Option<Arc<AtomicUsize>>,
+    /// Adds the number of matches (rather than matching lines) in each input to this.
+    pub matches_found: Option<Arc<AtomicUsize>>,
+    /// Skips output lines that were already written, possibly by another search.
+    /// Only the text of the line is compared, without its path or line number.
+    pub unique_output: Option<Arc<SeenLines>>,
+    /// Stops after this many matching lines.
+    pub max_count: Option<usize>,
+    /// Writes a notice at the end if there were more matching lines than `max_count`.
+    /// To stay cheap, this only looks for one more match, so the notice does not say how many.
+    pub show_truncation: bool,
+    /// Writes this many lines before and after each matching line.
+    /// Groups of lines that are not contiguous are separated by `--`.
+    pub before_context: usize,
+    pub after_context: usize,
+    /// Writes the whole paragraph around each matching line, where paragraphs are separated by
+    /// blank lines. Blank lines never match. Paragraphs are separated by `--`.
+    pub paragraph: bool,
+    /// Writes the line numbers of the matching lines at the end, collapsed into ranges (see
+    /// `format_ranges`), prefixed by the path if there is one. Usually used with `count_only`.
+    pub ranges: bool,
+    /// Writes each output line as a JSON object (JSON Lines), with its path (or null), line number,
+    /// the byte offset of the line in the input, its text and the byte offsets of the matches in
+    /// the line, e.g. `{"path":"a.txt","line_number":1,"byte_offset":0,"text":"a foo",
+    /// "submatches":[{"start":2,"end":5,"text":"foo"}]}`. Colors are not written.
+    ///
+    /// There are no submatches when they cannot be located in the text, e.g. with `captures`,
+    /// `replace` or `decode`.
+    pub json: bool,
+    /// Ignores matches in the comments and string literals of this language. See `code`.
+    pub code_only: Option<Lang>,
+    /// Searches each line decoded from this encoding, but writes the original line. Lines that
+    /// do not decode cleanly never match. Matches are not highlighted, since they are in the
+    /// decoded line.
+    pub decode: Option<Encoding>,
+    /// Checks if each input is binary before searching it. If not set, inputs are always searched
+    /// as text, and the search fails at the first line that is not valid UTF-8.
+    ///
+    /// With `BinaryFiles::Binary`, a binary input counts as one matching line (unless with
+    /// `count_only`), and nothing is written for it with `json`.
+    pub binary_files: Option<BinaryFiles>,
 }
 
-impl Default for MatchOptions {
-    fn default() -> Self {
+/// The output lines that were written so far, which can be shared between searches.
+///
+/// The lines are kept in memory, so inserting fails after `max_lines` lines.
+pub struct SeenLines {
+    lines: Mutex<HashSet<String>>,
+    max_lines: Option<usize>,
+}
+
+impl SeenLines {
+    pub fn new(max_lines: Option<usize>) -> Self {
         Self {
-            show_line_numbers: false,
-            case_insensitive: false,
+            lines: Mutex::new(HashSet::new()),
+            max_lines,
         }
     }
+
+    /// Inserts `line`, returning whether it is new.
+    fn insert(&self, line: &str) -> Result<bool> {
+        let mut lines = self.lines.lock().unwrap_or_else(|err| err.into_inner());
+        if lines.contains(line) {
+            return Ok(false);
+        }
+        if let Some(max_lines) = self.max_lines
+            && lines.len() >= max_lines
+        {
+            bail!("found more than {} unique output lines", max_lines);
+        }
+
+        lines.insert(line.to_string());
+        Ok(true)
+    }
 }
 
-pub fn find_matches<R: BufRead, W: std::io::Write>(
+/// The pattern that is compiled for `pattern`, taking into account the options that affect the
+/// pattern, along with whether it is matched case insensitively.
+fn expand_pattern(pattern: &str, options: &MatchOptions) -> (String, bool) {
+    let case_insensitive = if options.fixed_strings {
+        // Escaped first, so that every letter counts, even in something like `\W`
+        options.case.is_insensitive(&regex::escape(pattern))
+    } else {
+        options.case.is_insensitive(pattern)
+    };
+    let mut pattern = if options.fixed_strings {
+        regex::escape(pattern)
+    } else {
+        pattern.to_string()
+    };
+
+    if options.line_regexp {
+        pattern = format!("^(?:{})$", pattern);
+    } else if options.word_regexp {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+
+    (pattern, case_insensitive)
+}
+
+/// Builds the regex for `pattern`, taking into account the options that affect the pattern.
+pub fn build_regex(pattern: &str, options: &MatchOptions) -> Result<Regex> {
+    let (pattern, case_insensitive) = expand_pattern(pattern, options);
+    compile_regex(&pattern, case_insensitive, options)
+}
+
+/// Compiles an already expanded `pattern` (see `expand_pattern`), with the cache of `options` if
+/// there is one.
+fn compile_regex(pattern: &str, case_insensitive: bool, options: &MatchOptions) -> Result<Regex> {
+    let regex = match &options.regex_cache {
+        Some(cache) => cache.regex(pattern, case_insensitive),
+        None => RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build(),
+    };
+    regex.context("invalid search pattern")
+}
+
+/// A single match within a line.
+///
+/// Offsets are relative to the start of the line.
+/// Both byte and char offsets are kept because editors disagree on which one they want.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submatch {
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub char_start: usize,
+    pub char_end: usize,
+    pub text: String,
+}
+
+/// Finds all (non-overlapping) matches of `regex` in `line`, up to `limit` matches.
+pub fn find_submatches(regex: &Regex, line: &str, limit: Option<usize>) -> Vec<Submatch> {
+    let matches = regex.find_iter(line).map(|m| (m.start(), m.end()));
+    submatches_at(line, matches, limit)
+}
+
+/// The submatches of `line` at the byte ranges of `matches`, which are in order and do not
+/// overlap, up to `limit` matches.
+fn submatches_at(
+    line: &str,
+    matches: impl Iterator<Item = (usize, usize)>,
+    limit: Option<usize>,
+) -> Vec<Submatch> {
+    let mut submatches = Vec::new();
+
+    // Matches are returned in order, so we only have to count the chars between matches once
+    let mut char_indices = line.char_indices().peekable();
+    let mut char_offset = 0;
+
+    let mut count_chars_until = |byte_offset: usize| -> usize {
+        while char_indices
+            .next_if(|(idx, _)| *idx < byte_offset)
+            .is_some()
+        {
+            char_offset += 1;
+        }
+        char_offset
+    };
+
+    for (start, end) in matches.take(limit.unwrap_or(usize::MAX)) {
+        let char_start = count_chars_until(start);
+        let char_end = count_chars_until(end);
+
+        submatches.push(Submatch {
+            byte_start: start,
+            byte_end: end,
+            char_start,
+            char_end,
+            text: line[start..end].to_string(),
+        });
+    }
+
+    submatches
+}
+
+/// Finds the matches of `patterns` in `line` that select it, so that the line matches if there
+/// are any (before `invert_match`). Like `find_submatches`, along with the options that change
+/// which lines match: `code_only` (masked by `masker`), `decode`, `nth_match` and `not_pattern`,
+/// which is matched against the same (decoded) line.
+///
+/// With `decode`, the matches are in the decoded line, so they do not line up with `line`.
+pub fn select_submatches(
+    patterns: &PatternSet,
+    masker: Option<&mut CodeMasker>,
+    line: &str,
+    options: &MatchOptions,
+) -> Vec<Submatch> {
+    // Masking keeps the byte offsets, so the matches are also at the same place in the line
+    let masked = masker.map(|masker| masker.mask(line));
+    let searched = masked.as_deref().unwrap_or(line);
+    let decoded = options.decode.map(|encoding| encoding.decode(searched));
+    let searched = match &decoded {
+        // Lines that do not decode never match
+        Some(None) => return Vec::new(),
+        Some(Some(decoded)) => decoded.as_str(),
+        None => searched,
+    };
+
+    // Both patterns are matched against the same line
+    if (patterns.not_regex.as_ref()).is_some_and(|not_regex| not_regex.is_match(searched)) {
+        return Vec::new();
+    }
+    let submatches = patterns.find_submatches(searched, options.max_matches_per_line);
+    match options.nth_match {
+        // Lines with fewer matches do not match at all
+        Some(nth) => submatches.into_iter().nth(nth - 1).into_iter().collect(),
+        None => submatches,
+    }
+}
+
+/// Checks that each of `groups` is a capture group in `regex`.
+fn check_capture_groups(regex: &Regex, groups: &[CaptureGroup]) -> Result<()> {
+    for group in groups {
+        let exists = match group {
+            CaptureGroup::Index(index) => *index < regex.captures_len(),
+            CaptureGroup::Name(name) => regex.capture_names().flatten().any(|n| n == name),
+        };
+        if !exists {
+            bail!("the pattern has no capture group {}", group);
+        }
+    }
+
+    Ok(())
+}
+
+/// Extracts `groups` from each match of `regex` in `line` (up to `limit` matches, or only the
+/// `nth` one), joined by `separator`. Matches where any of the groups did not participate are
+/// skipped.
+pub fn extract_captures(
+    regex: &Regex,
+    line: &str,
+    groups: &[CaptureGroup],
+    separator: &str,
+    limit: Option<usize>,
+    nth: Option<usize>,
+) -> Vec<String> {
+    regex
+        .captures_iter(line)
+        .take(limit.unwrap_or(usize::MAX))
+        .enumerate()
+        .filter(|(idx, _)| nth.is_none_or(|nth| idx + 1 == nth))
+        .filter_map(|(_, captures)| {
+            let fields = groups
+                .iter()
+                .map(|group| match group {
+                    CaptureGroup::Index(index) => captures.get(*index),
+                    CaptureGroup::Name(name) => captures.name(name),
+                })
+                .map(|m| m.map(|m| m.as_str()))
+                .collect::<Option<Vec<_>>>()?;
+            Some(fields.join(separator))
+        })
+        .collect()
+}
+
+/// Combines `patterns` into a single pattern that matches if any of them match.
+pub fn any_of(patterns: &[String]) -> String {
+    if let [pattern] = patterns {
+        return pattern.clone();
+    }
+
+    patterns
+        .iter()
+        .map(|pattern| format!("(?:{})", pattern))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// The patterns of a search, compiled once so that the searches of every input can share them,
+/// even across threads. A line matches if any of the patterns match.
+///
+/// The patterns are compiled with the options of the search, so they should only be used with
+/// those options.
+pub struct PatternSet {
+    /// Each pattern on its own, to tell which of them match, see `contains_all_patterns`.
+    set: RegexSet,
+    /// Any of the patterns at once (see `any_of`), to find where they match.
+    regex: Regex,
+    /// Finds where the pattern matches instead of `regex`, when it is a single fixed string that
+    /// is matched as is (see `literal_finder`).
+    literal: Option<Finder<'static>>,
+    /// The compiled `MatchOptions::not_pattern`.
+    not_regex: Option<Regex>,
+    /// The parsed `MatchOptions::replace`.
+    template: Option<Template>,
+}
+
+impl PatternSet {
+    /// Compiles `patterns`, along with the `not_pattern` and `replace` of `options`. Fails if any
+    /// of them is invalid, or if a capture group of `captures` is not in the patterns.
+    ///
+    /// With `CaseMode::Smart`, every pattern is matched case sensitively if any of them has an
+    /// uppercase letter.
+    pub fn new(patterns: &[String], options: &MatchOptions) -> Result<Self> {
+        // Expanded one at a time, since fixed strings have to be escaped before they are combined
+        let (expanded, is_insensitive): (Vec<String>, Vec<bool>) = patterns
+            .iter()
+            .map(|pattern| expand_pattern(pattern, options))
+            .unzip();
+        let case_insensitive = is_insensitive.iter().all(|&is_insensitive| is_insensitive);
+        let set = match &options.regex_cache {
+            Some(cache) => cache.regex_set(&expanded, case_insensitive),
+            None => RegexSetBuilder::new(&expanded)
+                .case_insensitive(case_insensitive)
+                .build(),
+        };
+        let set = set.context("invalid search pattern")?;
+
+        let regex = compile_regex(&any_of(&expanded), case_insensitive, options)?;
+        check_capture_groups(&regex, &options.captures)?;
+        let literal = literal_finder(patterns, case_insensitive, options);
+        let not_regex = match &options.not_pattern {
+            Some(not_pattern) => Some(build_regex(not_pattern, options)?),
+            None => None,
+        };
+        let template = match &options.replace {
+            Some(replace) => Some(Template::parse(replace)?),
+            None => None,
+        };
+
+        Ok(Self {
+            set,
+            regex,
+            literal,
+            not_regex,
+            template,
+        })
+    }
+
+    /// The regex that matches any of the patterns.
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Finds all (non-overlapping) matches of the patterns in `line`, like `find_submatches`.
+    pub fn find_submatches(&self, line: &str, limit: Option<usize>) -> Vec<Submatch> {
+        match &self.literal {
+            Some(finder) => {
+                let len = finder.needle().len();
+                let matches = finder
+                    .find_iter(line.as_bytes())
+                    .map(|start| (start, start + len));
+                submatches_at(line, matches, limit)
+            }
+            None => find_submatches(&self.regex, line, limit),
+        }
+    }
+}
+
+/// A substring search (memmem) for `patterns` if they are a single fixed string that matches
+/// exactly where it occurs, which is much faster than a regex. Case insensitive matching, whole
+/// words and whole lines still need the regex, as does an empty string, which matches everywhere.
+fn literal_finder(
+    patterns: &[String],
+    case_insensitive: bool,
+    options: &MatchOptions,
+) -> Option<Finder<'static>> {
+    match patterns {
+        [pattern]
+            if options.fixed_strings
+                && !case_insensitive
+                && !options.word_regexp
+                && !options.line_regexp
+                && !pattern.is_empty() =>
+        {
+            Some(Finder::new(pattern).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Checks if every one of `patterns` matches somewhere in the input (not necessarily on the same
+/// line).
+///
+/// Stops reading as soon as all patterns have been found.
+pub fn contains_all_patterns<R: BufRead>(reader: R, patterns: &PatternSet) -> Result<bool> {
+    let pattern_set = &patterns.set;
+    let mut is_found = vec![false; pattern_set.len()];
+    let mut num_found = 0;
+
+    let mut lines = LineReader::new(reader);
+    while let Some((_, _, line)) = lines.next_line()? {
+        for idx in pattern_set.matches(line).iter() {
+            if !is_found[idx] {
+                is_found[idx] = true;
+                num_found += 1;
+            }
+        }
+
+        if num_found == pattern_set.len() {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Highlights each submatch in `line`.
+fn highlight(line: &str, submatches: &[Submatch]) -> String {
+    let mut highlighted = String::with_capacity(line.len());
+
+    let mut last_end = 0;
+    for submatch in submatches {
+        // Empty matches have nothing to highlight
+        if submatch.text.is_empty() {
+            continue;
+        }
+
+        highlighted.push_str(&line[last_end..submatch.byte_start]);
+        color::paint(&mut highlighted, color::MATCH, &submatch.text);
+        last_end = submatch.byte_end;
+    }
+    highlighted.push_str(&line[last_end..]);
+
+    highlighted
+}
+
+/// The lines of the current paragraph, with `MatchOptions::paragraph`.
+#[derive(Default)]
+struct Paragraph {
+    output: String,
+    has_match: bool,
+    has_written: bool,
+}
+
+impl Paragraph {
+    /// Writes the paragraph if any of its lines matched, then starts the next one.
+    fn end<W: std::io::Write>(&mut self, writer: &mut W) -> Result<()> {
+        if self.has_match {
+            if self.has_written {
+                self.output.insert_str(0, "--\n");
+            }
+            writer.write_all(self.output.as_bytes())?;
+            self.has_written = true;
+        }
+        self.output.clear();
+        self.has_match = false;
+        Ok(())
+    }
+}
+
+/// Collapses `line_nums` (in increasing order) into ranges of consecutive line numbers, e.g.
+/// `10-14,20,33-35`. Ranges are separated by commas, and a range of one line is just the line.
+pub fn format_ranges(line_nums: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &line_num in line_nums {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line_num => *end = line_num,
+            _ => ranges.push((line_num, line_num)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Formats the line number before a line, followed by `separator` (`:` for matches and `-`
+/// for context) and a space.
+fn line_number_prefix(line_num: usize, separator: char, use_color: bool) -> String {
+    let mut prefix = String::new();
+    if use_color {
+        color::paint(&mut prefix, color::LINE_NUMBER, &line_num.to_string());
+    } else {
+        prefix.push_str(&line_num.to_string());
+    }
+    prefix.push(separator);
+    prefix.push(' ');
+    prefix
+}
+
+/// Formats a line that is written as context for a match.
+fn context_line(line_num: usize, message: &str, options: &MatchOptions, use_color: bool) -> String {
+    if options.show_line_numbers {
+        format!(
+            "{}{}\n",
+            line_number_prefix(line_num, '-', use_color),
+            message
+        )
+    } else {
+        format!("{}\n", message)
+    }
+}
+
+/// A line that is buffered until the end of the input.
+struct BufferedLine {
+    output: String,
+    is_match: bool,
+}
+
+/// Writes each match along with a variable amount of context.
+///
+/// Sparse matches get more context than dense ones: the context before a match is half the number
+/// of lines since the previous match (or the start of the input), clamped to `[1, max_context]`.
+/// Similarly, the context after a match is based on the number of lines until the next match (or
+/// the end of the input).
+/// Groups of lines that are not contiguous are separated by `--`.
+fn write_auto_context<W: std::io::Write>(
+    writer: &mut W,
+    lines: &[BufferedLine],
+    max_context: usize,
+) -> Result<()> {
+    let max_context = max_context.max(1);
+    let match_indices: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.is_match)
+        .map(|(idx, _)| idx)
+        .collect();
+
+    // Ranges of lines to print, [start, end)
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (i, &idx) in match_indices.iter().enumerate() {
+        let gap_before = match i {
+            0 => idx,
+            _ => idx - match_indices[i - 1] - 1,
+        };
+        let gap_after = match match_indices.get(i + 1) {
+            Some(&next_idx) => next_idx - idx - 1,
+            None => lines.len() - idx - 1,
+        };
+
+        let start = idx.saturating_sub((gap_before / 2).clamp(1, max_context));
+        let end = (idx + 1 + (gap_after / 2).clamp(1, max_context)).min(lines.len());
+
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    for (i, (start, end)) in ranges.into_iter().enumerate() {
+        let mut output = String::new();
+        if i > 0 {
+            output.push_str("--\n");
+        }
+        for line in &lines[start..end] {
+            output.push_str(&line.output);
+        }
+        writer.write_all(output.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Counts the bytes that are consumed from a reader. They are added to `counter` once it is
+/// dropped, rather than on every line, since other searches are adding to it at the same time.
+struct CountingReader<'a, R: BufRead> {
     reader: R,
+    count: usize,
+    counter: Option<&'a AtomicUsize>,
+}
+
+impl<R: BufRead> std::io::Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt;
+        self.reader.consume(amt)
+    }
+}
+
+impl<R: BufRead> Drop for CountingReader<'_, R> {
+    fn drop(&mut self) {
+        if let Some(counter) = self.counter {
+            counter.fetch_add(self.count, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writes every (selected) line that matches any of `patterns` to `writer`.
+/// Returns the number of (selected) matching lines.
+///
+/// `path` is the path of the input, if it has one. `patterns` are compiled with `options`, see
+/// `PatternSet::new`.
+pub fn find_matches<R: BufRead, W: std::io::Write>(
+    mut reader: R,
     mut writer: W,
-    pattern: &str,
-    options: MatchOptions,
-) -> Result<()> {
-    let pattern_regex = RegexBuilder::new(pattern)
-        .case_insensitive(options.case_insensitive)
-        .build()
-        .context("invalid search pattern")?;
-
-    for (line_num, line) in reader.lines().enumerate() {
-        let message = line.with_context(|| format!("could not read line"))?;
-        if pattern_regex.is_match(&message) {
-            if options.show_line_numbers {
-                writeln!(writer, "{}: {}", line_num + 1, message)?;
+    path: Option<&Path>,
+    patterns: &PatternSet,
+    options: &MatchOptions,
+) -> Result<usize> {
+    let use_color = options.color.is_enabled();
+
+    // The start of the input is already buffered, so checking it does not read anything extra.
+    // Only as much as the walk probes is checked, so that both agree on which files are binary
+    let is_binary = options.binary_files.is_some() && {
+        let buf = reader.fill_buf().context("could not read input")?;
+        !crate::core::ignore::is_text(&buf[..buf.len().min(crate::core::ignore::PROBE_SIZE)])
+    };
+    if is_binary && options.binary_files == Some(BinaryFiles::WithoutMatch) {
+        return Ok(0);
+    }
+    // Only whether it matches is written, so there is no need to look further
+    let is_summarized =
+        is_binary && options.binary_files == Some(BinaryFiles::Binary) && !options.count_only;
+    let count_only = options.count_only || is_summarized;
+    let select = if is_summarized {
+        LineSelection::First
+    } else {
+        options.select
+    };
+
+    let mut num_matches = 0;
+    let mut distinct_lines = HashSet::new();
+    let mut last_output = None;
+    let mut buffered_lines = Vec::new();
+    let mut is_truncated = false;
+    // The context lines before the next match, and how many lines after the last match are left
+    let mut before_lines: VecDeque<String> = VecDeque::new();
+    let mut after_remaining = 0;
+    let mut last_written: Option<usize> = None;
+    let has_context = options.before_context > 0 || options.after_context > 0;
+    // Set when no more matches are needed, but there might be context left to write
+    let mut is_done = false;
+    let mut paragraph = Paragraph::default();
+    let mut match_line_nums = Vec::new();
+    let mut masker = options.code_only.map(CodeMasker::new);
+    let is_buffering = options.context_auto.is_some() && !count_only;
+
+    let reader = CountingReader {
+        reader,
+        count: 0,
+        counter: options.bytes_searched.as_deref(),
+    };
+    let mut lines = LineReader::new(reader).invalid_utf8(if is_binary {
+        InvalidUtf8::Lossy
+    } else {
+        InvalidUtf8::Error
+    });
+    while let Some((line_num, byte_offset, message)) = lines.next_line()? {
+        if options.paragraph && message.trim().is_empty() {
+            if is_done {
+                break;
+            }
+            paragraph.end(&mut writer)?;
+            continue;
+        }
+        if is_done {
+            // Only the context after the last match is left
+            if options.paragraph {
+                paragraph
+                    .output
+                    .push_str(&context_line(line_num, message, options, use_color));
+                continue;
+            }
+            if after_remaining == 0 {
+                break;
+            }
+            writer.write_all(context_line(line_num, message, options, use_color).as_bytes())?;
+            after_remaining -= 1;
+            continue;
+        }
+
+        // Lines that do not match can still be written as context
+        let mut submatches = select_submatches(patterns, masker.as_mut(), message, options);
+        let is_match = !submatches.is_empty();
+        if is_match == options.invert_match {
+            if is_buffering {
+                buffered_lines.push(BufferedLine {
+                    output: context_line(line_num, message, options, use_color),
+                    is_match: false,
+                });
+            } else if options.paragraph {
+                paragraph
+                    .output
+                    .push_str(&context_line(line_num, message, options, use_color));
+            } else if after_remaining > 0 {
+                writer.write_all(context_line(line_num, message, options, use_color).as_bytes())?;
+                after_remaining -= 1;
+                last_written = Some(line_num);
+            } else if options.before_context > 0 {
+                if before_lines.len() >= options.before_context {
+                    before_lines.pop_front();
+                }
+                before_lines.push_back(context_line(line_num, message, options, use_color));
+            }
+            continue;
+        }
+
+        if options.invert_match {
+            // Whatever matched in the line is not what selected it
+            submatches.clear();
+        }
+
+        if options
+            .max_count
+            .is_some_and(|max_count| num_matches >= max_count)
+        {
+            // This is one more match than the maximum
+            is_truncated = true;
+            break;
+        }
+
+        if let Some(matches_found) = &options.matches_found {
+            matches_found.fetch_add(submatches.len(), Ordering::Relaxed);
+        }
+        if options.distinct {
+            if !distinct_lines.contains(message) {
+                if let Some(max_distinct) = options.max_distinct
+                    && distinct_lines.len() >= max_distinct
+                {
+                    bail!("found more than {} distinct matching lines", max_distinct);
+                }
+                distinct_lines.insert(message.to_string());
+            }
+            num_matches = distinct_lines.len();
+        } else {
+            num_matches += 1;
+        }
+        if options.ranges {
+            match_line_nums.push(line_num);
+        }
+
+        if !count_only {
+            // Each line is written in one call so that it is never split up
+            let mut output = String::new();
+            let prefix = if options.show_line_numbers {
+                line_number_prefix(line_num, ':', use_color)
             } else {
-                writeln!(writer, "{}", message)?;
+                String::new()
+            };
+            // Each output line, as its text, how it is displayed and the matches in it
+            let rows: Vec<(String, String, Cow<[Submatch]>)> = if !options.captures.is_empty() {
+                // Each extracted match is written on its own line
+                extract_captures(
+                    &patterns.regex,
+                    message,
+                    &options.captures,
+                    &options.capture_separator,
+                    options.max_matches_per_line,
+                    options.nth_match,
+                )
+                .into_iter()
+                .map(|extracted| (extracted.clone(), extracted, Cow::Borrowed(&[][..])))
+                .collect()
+            } else if options.only_matching {
+                // Each match is written on its own line
+                submatches
+                    .iter()
+                    .filter(|submatch| !submatch.text.is_empty())
+                    .map(|submatch| {
+                        let mut display = String::new();
+                        if use_color {
+                            color::paint(&mut display, color::MATCH, &submatch.text);
+                        } else {
+                            display.push_str(&submatch.text);
+                        }
+                        // The match is the whole text, so its offsets are relative to the match
+                        let whole = Submatch {
+                            byte_start: 0,
+                            byte_end: submatch.text.len(),
+                            char_start: 0,
+                            char_end: submatch.char_end - submatch.char_start,
+                            text: submatch.text.clone(),
+                        };
+                        (submatch.text.clone(), display, Cow::Owned(vec![whole]))
+                    })
+                    .collect()
+            } else if let Some(template) = &patterns.template {
+                let limit = options.max_matches_per_line.unwrap_or(0);
+                let replaced = patterns
+                    .regex
+                    .replacen(message, limit, |captures: &regex::Captures| {
+                        template.expand(captures, path, line_num)
+                    })
+                    .into_owned();
+                vec![(replaced.clone(), replaced, Cow::Borrowed(&[][..]))]
+            } else if options.decode.is_some() {
+                // The matches are in the decoded line, so they do not line up with the line
+                vec![(
+                    message.to_string(),
+                    message.to_string(),
+                    Cow::Borrowed(&[][..]),
+                )]
+            } else if use_color {
+                vec![(
+                    message.to_string(),
+                    highlight(message, &submatches),
+                    Cow::Borrowed(&submatches[..]),
+                )]
+            } else {
+                vec![(
+                    message.to_string(),
+                    message.to_string(),
+                    Cow::Borrowed(&submatches[..]),
+                )]
+            };
+
+            for (text, display, row_submatches) in rows {
+                if let Some(seen_lines) = &options.unique_output
+                    && !seen_lines.insert(&text)?
+                {
+                    continue;
+                }
+                if options.json {
+                    output.push_str("{\"path\":");
+                    match path {
+                        Some(path) => json::push_string(&mut output, &path.to_string_lossy()),
+                        None => output.push_str("null"),
+                    }
+                    output.push_str(&format!(
+                        ",\"line_number\":{},\"byte_offset\":{},\"text\":",
+                        line_num, byte_offset
+                    ));
+                    json::push_string(&mut output, &text);
+                    output.push_str(",\"submatches\":[");
+                    for (i, submatch) in row_submatches.iter().enumerate() {
+                        if i > 0 {
+                            output.push(',');
+                        }
+                        output.push_str(&format!(
+                            "{{\"start\":{},\"end\":{},\"text\":",
+                            submatch.byte_start, submatch.byte_end
+                        ));
+                        json::push_string(&mut output, &submatch.text);
+                        output.push('}');
+                    }
+                    output.push_str("]}\n");
+                    continue;
+                }
+                output.push_str(&prefix);
+                output.push_str(&display);
+                output.push('\n');
             }
+
+            if is_buffering {
+                buffered_lines.push(BufferedLine {
+                    output,
+                    is_match: true,
+                });
+            } else if select == LineSelection::Last {
+                last_output = Some(output);
+            } else if options.paragraph {
+                paragraph.output.push_str(&output);
+                paragraph.has_match |= !output.is_empty();
+            } else if !output.is_empty() {
+                let mut group = String::new();
+                let first_line_num = line_num - before_lines.len();
+                if has_context
+                    && last_written.is_some_and(|last_written| first_line_num > last_written + 1)
+                {
+                    group.push_str("--\n");
+                }
+                group.extend(before_lines.drain(..));
+                group.push_str(&output);
+                writer.write_all(group.as_bytes())?;
+                after_remaining = options.after_context;
+                last_written = Some(line_num);
+            }
+        }
+
+        if select == LineSelection::First {
+            break;
+        }
+        if !options.show_truncation
+            && options
+                .max_count
+                .is_some_and(|max_count| num_matches >= max_count)
+        {
+            // Stops early, since there is no need to know if there are more matches
+            is_done = true;
         }
     }
 
-    Ok(())
+    paragraph.end(&mut writer)?;
+    if let Some(output) = last_output
+        && !output.is_empty()
+    {
+        writer.write_all(output.as_bytes())?;
+    }
+    if let Some(max_context) = options.context_auto
+        && is_buffering
+    {
+        write_auto_context(&mut writer, &buffered_lines, max_context)?;
+    }
+    if options.ranges && !match_line_nums.is_empty() {
+        let ranges = format_ranges(&match_line_nums);
+        let line = match path {
+            Some(path) => format!("{}:{}\n", path.display(), ranges),
+            None => format!("{}\n", ranges),
+        };
+        writer.write_all(line.as_bytes())?;
+    }
+    if is_summarized && num_matches > 0 && !options.json {
+        writer.write_all(b"Binary file matches\n")?;
+    }
+    if is_truncated && options.show_truncation && !count_only {
+        let notice = match path {
+            Some(path) => format!("... (more matches in {})\n", path.display()),
+            None => String::from("... (more matches)\n"),
+        };
+        writer.write_all(notice.as_bytes())?;
+    }
+    if select != LineSelection::All {
+        num_matches = num_matches.min(1);
+    }
+
+    Ok(num_matches)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Like `find_matches`, for a single pattern that is compiled with `options`.
+    fn find_pattern<R: BufRead, W: std::io::Write>(
+        reader: R,
+        writer: W,
+        path: Option<&Path>,
+        pattern: &str,
+        options: &MatchOptions,
+    ) -> Result<usize> {
+        let patterns = PatternSet::new(&[pattern.to_string()], options)?;
+        find_matches(reader, writer, path, &patterns, options)
+    }
+
     #[test]
     fn test_find_matches() {
         let input = b"lorem ipsum\ndolor sit amet\nquick brown fox";
         let mut result = Vec::new();
 
-        find_matches(
+        let num_matches = find_pattern(
             &input[..],
             &mut result,
-            &"dolor".to_string(),
-            MatchOptions::default(),
+            None,
+            "dolor",
+            &MatchOptions::default(),
         )
         .unwrap();
 
         assert_eq!(result, b"dolor sit amet\n");
+        assert_eq!(num_matches, 1);
+    }
+
+    #[test]
+    fn test_find_matches_bom() {
+        let input = b"\xEF\xBB\xBFfoo bar\nfoo baz\n";
+        let mut result = Vec::new();
+
+        let options = MatchOptions {
+            show_line_numbers: true,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "^foo", &options).unwrap();
+
+        assert_eq!(result, b"1: foo bar\n2: foo baz\n");
+        assert_eq!(num_matches, 2);
+    }
+
+    #[test]
+    fn test_extract_captures() {
+        let regex = Regex::new(r"id=(\d+)(?: user=(?<user>\w+))?").unwrap();
+        let line = "id=1 user=alice id=2 id=3 user=bob";
+
+        assert_eq!(
+            extract_captures(&regex, line, &[CaptureGroup::Index(1)], ",", None, None),
+            vec!["1", "2", "3"]
+        );
+        // Matches without the user are skipped
+        assert_eq!(
+            extract_captures(
+                &regex,
+                line,
+                &[
+                    CaptureGroup::Index(1),
+                    CaptureGroup::Name(String::from("user"))
+                ],
+                ",",
+                None,
+                None
+            ),
+            vec!["1,alice", "3,bob"]
+        );
+        assert_eq!(
+            extract_captures(&regex, line, &[CaptureGroup::Index(1)], ",", Some(2), None),
+            vec!["1", "2"]
+        );
+        assert_eq!(
+            extract_captures(&regex, line, &[CaptureGroup::Index(1)], ",", None, Some(2)),
+            vec!["2"]
+        );
+    }
+
+    #[test]
+    fn test_find_matches_captures() {
+        let input = b"GET /a 200\nPOST /b 500\nnothing\n";
+        let mut result = Vec::new();
+
+        let options = MatchOptions {
+            show_line_numbers: true,
+            captures: vec!["method".parse().unwrap(), "2".parse().unwrap()],
+            capture_separator: String::from("\t"),
+            ..Default::default()
+        };
+        let num_matches = find_pattern(
+            &input[..],
+            &mut result,
+            None,
+            r"(?<method>[A-Z]+) (/\w+)",
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(result, b"1: GET\t/a\n2: POST\t/b\n");
+        assert_eq!(num_matches, 2);
+
+        // Unknown capture groups are an error
+        let options = MatchOptions {
+            captures: vec!["3".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(find_pattern(&input[..], Vec::new(), None, r"(\w+)", &options).is_err());
+    }
+
+    #[test]
+    fn test_find_matches_only_matching() {
+        let input = b"a1 b22 c333\nno digits\nx4 y55\nz6\n";
+
+        let run = |options: &MatchOptions| {
+            let mut result = Vec::new();
+            let num_matches = find_pattern(&input[..], &mut result, None, r"\d+", options).unwrap();
+            (String::from_utf8(result).unwrap(), num_matches)
+        };
+
+        let options = MatchOptions {
+            show_line_numbers: true,
+            only_matching: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&options),
+            (String::from("1: 1\n1: 22\n1: 333\n3: 4\n3: 55\n4: 6\n"), 3)
+        );
+
+        // Only the second match, skipping lines with fewer matches
+        let options = MatchOptions {
+            show_line_numbers: true,
+            only_matching: true,
+            nth_match: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(run(&options), (String::from("1: 22\n3: 55\n"), 2));
+
+        // Matches after the maximum are never considered
+        let options = MatchOptions {
+            only_matching: true,
+            nth_match: Some(3),
+            max_matches_per_line: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(run(&options), (String::new(), 0));
+    }
+
+    #[test]
+    fn test_find_matches_bytes_searched() {
+        let bytes_searched = Arc::new(AtomicUsize::new(0));
+        let options = MatchOptions {
+            bytes_searched: Some(Arc::clone(&bytes_searched)),
+            ..Default::default()
+        };
+
+        find_pattern(&b"foo\nbar\n"[..], Vec::new(), None, "foo", &options).unwrap();
+        assert_eq!(bytes_searched.load(Ordering::Relaxed), 8);
+
+        // Bytes read before an error still count
+        let result = find_pattern(&b"foo\n\xff\nbar\n"[..], Vec::new(), None, "foo", &options);
+        assert!(result.is_err());
+        assert_eq!(bytes_searched.load(Ordering::Relaxed), 8 + 6);
+    }
+
+    #[test]
+    fn test_find_matches_matches_found() {
+        let matches_found = Arc::new(AtomicUsize::new(0));
+        let options = MatchOptions {
+            matches_found: Some(Arc::clone(&matches_found)),
+            max_count: Some(2),
+            ..Default::default()
+        };
+
+        let num_matches = find_pattern(
+            &b"foo foo
+bar
+foo
+foo
+"[..],
+            Vec::new(),
+            None,
+            "foo",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(num_matches, 2);
+        // Only the matches on the lines that count
+        assert_eq!(matches_found.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_find_matches_unique_output() {
+        let seen_lines = Arc::new(SeenLines::new(Some(3)));
+        let options = MatchOptions {
+            show_line_numbers: true,
+            unique_output: Some(Arc::clone(&seen_lines)),
+            ..Default::default()
+        };
+
+        let mut result = Vec::new();
+        let num_matches = find_pattern(
+            &b"foo 1\nfoo 2\nfoo 1\n"[..],
+            &mut result,
+            None,
+            "foo",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(result, b"1: foo 1\n2: foo 2\n");
+        // Skipped lines still match
+        assert_eq!(num_matches, 3);
+
+        // Lines written by earlier searches are skipped, regardless of their line numbers
+        let mut result = Vec::new();
+        find_pattern(&b"foo 2\nfoo 3\n"[..], &mut result, None, "foo", &options).unwrap();
+        assert_eq!(result, b"2: foo 3\n");
+
+        // Fails after too many unique lines
+        assert!(find_pattern(&b"foo 4\n"[..], Vec::new(), None, "foo", &options).is_err());
+    }
+
+    #[test]
+    fn test_find_matches_max_count() {
+        let input = b"foo 1\nbar\nfoo 2\nfoo 3\n";
+
+        let run = |max_count: usize, show_truncation: bool| {
+            let mut result = Vec::new();
+            let options = MatchOptions {
+                max_count: Some(max_count),
+                show_truncation,
+                ..Default::default()
+            };
+            let num_matches = find_pattern(
+                &input[..],
+                &mut result,
+                Some(Path::new("a.txt")),
+                "foo",
+                &options,
+            )
+            .unwrap();
+            (String::from_utf8(result).unwrap(), num_matches)
+        };
+
+        assert_eq!(run(2, false), (String::from("foo 1\nfoo 2\n"), 2));
+        assert_eq!(
+            run(2, true),
+            (
+                String::from("foo 1\nfoo 2\n... (more matches in a.txt)\n"),
+                2
+            )
+        );
+        // No notice if there are no more matches
+        assert_eq!(run(3, true), (String::from("foo 1\nfoo 2\nfoo 3\n"), 3));
+        assert_eq!(
+            run(0, true),
+            (String::from("... (more matches in a.txt)\n"), 0)
+        );
+    }
+
+    #[test]
+    fn test_find_matches_context() {
+        let input = b"a\nfoo 1\nb\nc\nfoo 2\nd\ne\nf\ng\nfoo 3\n";
+
+        let run = |before_context: usize, after_context: usize| {
+            let mut result = Vec::new();
+            let options = MatchOptions {
+                show_line_numbers: true,
+                before_context,
+                after_context,
+                ..Default::default()
+            };
+            find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+            String::from_utf8(result).unwrap()
+        };
+
+        assert_eq!(run(0, 0), "2: foo 1\n5: foo 2\n10: foo 3\n");
+        assert_eq!(
+            run(1, 0),
+            "1- a\n2: foo 1\n--\n4- c\n5: foo 2\n--\n9- g\n10: foo 3\n"
+        );
+        assert_eq!(
+            run(0, 2),
+            "2: foo 1\n3- b\n4- c\n5: foo 2\n6- d\n7- e\n--\n10: foo 3\n"
+        );
+        // Overlapping context is only written once
+        assert_eq!(
+            run(2, 2),
+            "1- a\n2: foo 1\n3- b\n4- c\n5: foo 2\n6- d\n7- e\n8- f\n9- g\n10: foo 3\n"
+        );
+    }
+
+    #[test]
+    fn test_find_matches_context_invert_match() {
+        let input = b"foo 1\na\nfoo 2\nfoo 3\nfoo 4\nb\n";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            invert_match: true,
+            before_context: 1,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+
+        // The matching lines are the context of the selected lines
+        assert_eq!(num_matches, 2);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            "1- foo 1\n2: a\n--\n5- foo 4\n6: b\n"
+        );
+    }
+
+    #[test]
+    fn test_find_matches_color() {
+        let input = b"a\nfoo 1\nb\n";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            color: ColorChoice::Always,
+            before_context: 1,
+            ..Default::default()
+        };
+        find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+
+        // The line numbers of both matches and context are colored, but not their separators
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!(
+                "{n}1{r}- a\n{n}2{r}: {m}foo{r} 1\n",
+                n = color::LINE_NUMBER,
+                m = color::MATCH,
+                r = color::RESET,
+            )
+        );
+    }
+
+    #[test]
+    fn test_find_matches_context_max_count() {
+        let input = b"foo 1\nfoo 2\nb\nfoo 3\n";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            after_context: 2,
+            max_count: Some(1),
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+
+        // The context after the last match is still written
+        assert_eq!(num_matches, 1);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            "1: foo 1\n2- foo 2\n3- b\n"
+        );
+    }
+
+    #[test]
+    fn test_find_matches_paragraph() {
+        let input = b"foo 1\na\n\nb\nc\n\n\nd\nfoo 2\nfoo 3\n\ne\nfoo 4";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            paragraph: true,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+
+        // Paragraphs at the start and the end, and one with multiple matches
+        assert_eq!(num_matches, 4);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            "1: foo 1\n2- a\n--\n8- d\n9: foo 2\n10: foo 3\n--\n12- e\n13: foo 4\n"
+        );
+    }
+
+    #[test]
+    fn test_format_ranges() {
+        assert_eq!(format_ranges(&[]), "");
+        assert_eq!(format_ranges(&[7]), "7");
+        assert_eq!(
+            format_ranges(&[10, 11, 12, 13, 14, 20, 33, 34, 35]),
+            "10-14,20,33-35"
+        );
+        assert_eq!(format_ranges(&[1, 3, 4]), "1,3-4");
+    }
+
+    #[test]
+    fn test_find_matches_count_only() {
+        let input = b"foo\nbar\nfoo foo\nFOO\n";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            count_only: true,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(
+            &input[..],
+            &mut result,
+            Some(Path::new("a.txt")),
+            "foo",
+            &options,
+        )
+        .unwrap();
+
+        // Matching lines are counted, not matches, and nothing is written
+        assert_eq!(num_matches, 2);
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_find_matches_ranges() {
+        let input = b"foo\nfoo\nbar\nfoo\nbar\nbar\nfoo\nfoo\nfoo\n";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            count_only: true,
+            ranges: true,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(
+            &input[..],
+            &mut result,
+            Some(Path::new("a.txt")),
+            "foo",
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(num_matches, 6);
+        assert_eq!(String::from_utf8(result).unwrap(), "a.txt:1-2,4,7-9\n");
+    }
+
+    #[test]
+    fn test_find_matches_json() {
+        let input = "a: \"foo\"\nbar\nfoo\tfoo 日本\n".as_bytes();
+        let run = |path: Option<&Path>, only_matching: bool| {
+            let mut result = Vec::new();
+            let options = MatchOptions {
+                json: true,
+                only_matching,
+                // Colors are never written
+                color: ColorChoice::Always,
+                ..Default::default()
+            };
+            find_pattern(input, &mut result, path, "foo", &options).unwrap();
+            String::from_utf8(result).unwrap()
+        };
+
+        // Offsets are in bytes, so 日本 does not change them
+        assert_eq!(
+            run(Some(Path::new("dir/a:b.txt")), false),
+            concat!(
+                r#"{"path":"dir/a:b.txt","line_number":1,"byte_offset":0,"text":"a: \"foo\"","#,
+                r#""submatches":[{"start":4,"end":7,"text":"foo"}]}"#,
+                "\n",
+                r#"{"path":"dir/a:b.txt","line_number":3,"byte_offset":13,"text":"foo\tfoo 日本","#,
+                r#""submatches":[{"start":0,"end":3,"text":"foo"},{"start":4,"end":7,"text":"foo"}]}"#,
+                "\n",
+            )
+        );
+        // Each match is its own object, whose submatch is all of its text
+        assert_eq!(
+            run(None, true),
+            concat!(
+                r#"{"path":null,"line_number":1,"byte_offset":0,"text":"foo","#,
+                r#""submatches":[{"start":0,"end":3,"text":"foo"}]}"#,
+                "\n",
+                r#"{"path":null,"line_number":3,"byte_offset":13,"text":"foo","#,
+                r#""submatches":[{"start":0,"end":3,"text":"foo"}]}"#,
+                "\n",
+                r#"{"path":null,"line_number":3,"byte_offset":13,"text":"foo","#,
+                r#""submatches":[{"start":0,"end":3,"text":"foo"}]}"#,
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_find_matches_code_only() {
+        let input = b"foo(); // foo\n/* foo\nfoo */\nputs(\"foo\");\nbar(foo);\n";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            code_only: Some(Lang::C),
+            color: ColorChoice::Always,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+
+        // The whole line is written, but only the match in the code counts
+        assert_eq!(num_matches, 2);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!(
+                "{n}1{r}: {m}foo{r}(); // foo\n{n}5{r}: bar({m}foo{r});\n",
+                n = color::LINE_NUMBER,
+                m = color::MATCH,
+                r = color::RESET,
+            )
+        );
+    }
+
+    #[test]
+    fn test_find_matches_decode() {
+        // "hello world", "goodbye", and a line that is not base64
+        let input = b"aGVsbG8gd29ybGQ=\nZ29vZGJ5ZQ==\nhello world!\n";
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            decode: Some(Encoding::Base64),
+            color: ColorChoice::Always,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "world", &options).unwrap();
+
+        // The original line is written, without highlighting
+        assert_eq!(num_matches, 1);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!(
+                "{}1{}: aGVsbG8gd29ybGQ=\n",
+                color::LINE_NUMBER,
+                color::RESET
+            )
+        );
+
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            decode: Some(Encoding::Hex),
+            only_matching: true,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(
+            &b"666f6f626172\nfoobar\n"[..],
+            &mut result,
+            None,
+            "o+",
+            &options,
+        )
+        .unwrap();
+
+        // Only the matches are from the decoded line
+        assert_eq!(num_matches, 1);
+        assert_eq!(String::from_utf8(result).unwrap(), "1: oo\n");
+
+        // The line that is left out is also matched in the decoded line
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            decode: Some(Encoding::Base64),
+            not_pattern: Some(String::from("hello")),
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "o", &options).unwrap();
+        assert_eq!(num_matches, 1);
+        assert_eq!(String::from_utf8(result).unwrap(), "Z29vZGJ5ZQ==\n");
+    }
+
+    #[test]
+    fn test_find_matches_binary_files() {
+        let input = b"foo\0bar\nbaz\nfoo \xff\n";
+        let run = |binary_files, count_only| {
+            let mut result = Vec::new();
+            let options = MatchOptions {
+                show_line_numbers: true,
+                binary_files: Some(binary_files),
+                count_only,
+                ..Default::default()
+            };
+            let num_matches = find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+            (String::from_utf8(result).unwrap(), num_matches)
+        };
+
+        assert_eq!(
+            run(BinaryFiles::Binary, false),
+            (String::from("Binary file matches\n"), 1)
+        );
+        // Every matching line is still counted
+        assert_eq!(run(BinaryFiles::Binary, true), (String::new(), 2));
+        assert_eq!(
+            run(BinaryFiles::Text, false),
+            (String::from("1: foo\0bar\n3: foo \u{FFFD}\n"), 2)
+        );
+        assert_eq!(run(BinaryFiles::WithoutMatch, false), (String::new(), 0));
+
+        // Text is searched the same way in every mode
+        for binary_files in [
+            BinaryFiles::Binary,
+            BinaryFiles::Text,
+            BinaryFiles::WithoutMatch,
+        ] {
+            let mut result = Vec::new();
+            let options = MatchOptions {
+                binary_files: Some(binary_files),
+                ..Default::default()
+            };
+            let num_matches =
+                find_pattern(&b"foo\nbar\n"[..], &mut result, None, "foo", &options).unwrap();
+            assert_eq!((result, num_matches), (b"foo\n".to_vec(), 1));
+        }
+    }
+
+    #[test]
+    fn test_find_matches_empty_pattern() {
+        let input = b"foo\n\nbar\n";
+
+        // Like grep, every line matches
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "", &options).unwrap();
+        assert_eq!(num_matches, 3);
+        assert_eq!(String::from_utf8(result).unwrap(), "1: foo\n2: \n3: bar\n");
+
+        // The matches are all empty, so nothing is written
+        let mut result = Vec::new();
+        let options = MatchOptions {
+            show_line_numbers: true,
+            only_matching: true,
+            ..Default::default()
+        };
+        let num_matches = find_pattern(&input[..], &mut result, None, "", &options).unwrap();
+        assert_eq!(num_matches, 3);
+        assert_eq!(String::from_utf8(result).unwrap(), "");
+
+        // Only the empty matches are skipped
+        let mut result = Vec::new();
+        let num_matches = find_pattern(&input[..], &mut result, None, "o*", &options).unwrap();
+        assert_eq!(num_matches, 3);
+        assert_eq!(String::from_utf8(result).unwrap(), "1: oo\n");
+    }
+
+    #[test]
+    fn test_find_submatches_offsets() {
+        let regex = Regex::new("CRAB").unwrap();
+
+        // No matches
+        assert_eq!(find_submatches(&regex, "lobster", None), vec![]);
+
+        // 🦀 is 4 bytes but only 1 char, 게 is 3 bytes but only 1 char
+        let submatches = find_submatches(&regex, "🦀 CRAB 게 CRAB", None);
+        assert_eq!(
+            submatches,
+            vec![
+                Submatch {
+                    byte_start: 5,
+                    byte_end: 9,
+                    char_start: 2,
+                    char_end: 6,
+                    text: String::from("CRAB"),
+                },
+                Submatch {
+                    byte_start: 14,
+                    byte_end: 18,
+                    char_start: 9,
+                    char_end: 13,
+                    text: String::from("CRAB"),
+                },
+            ]
+        );
+
+        // Matching multibyte characters themselves
+        let regex = Regex::new("🦀+").unwrap();
+        let submatches = find_submatches(&regex, "a🦀🦀b", None);
+        assert_eq!(submatches.len(), 1);
+        assert_eq!((submatches[0].byte_start, submatches[0].byte_end), (1, 9));
+        assert_eq!((submatches[0].char_start, submatches[0].char_end), (1, 3));
+        assert_eq!(submatches[0].text, "🦀🦀");
+    }
+
+    #[test]
+    fn test_max_matches_per_line() {
+        let regex = Regex::new("a").unwrap();
+        let line = "a".repeat(1000);
+
+        assert_eq!(find_submatches(&regex, &line, None).len(), 1000);
+        assert_eq!(find_submatches(&regex, &line, Some(3)).len(), 3);
+        assert_eq!(find_submatches(&regex, "aa", Some(3)).len(), 2);
+
+        // The line is still printed exactly once
+        let input = format!("{}\nbbb\n", line);
+        let mut result = Vec::new();
+        find_pattern(
+            input.as_bytes(),
+            &mut result,
+            None,
+            "a",
+            &MatchOptions {
+                max_matches_per_line: Some(3),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(result, format!("{}\n", line).as_bytes());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let regex = Regex::new("o+").unwrap();
+        let line = "foo bar boo";
+
+        assert_eq!(
+            highlight(line, &find_submatches(&regex, line, None)),
+            "f\x1b[1;31moo\x1b[0m bar b\x1b[1;31moo\x1b[0m"
+        );
+        assert_eq!(
+            highlight(line, &find_submatches(&regex, line, Some(1))),
+            "f\x1b[1;31moo\x1b[0m bar boo"
+        );
+    }
+
+    #[test]
+    fn test_any_of() {
+        let patterns = vec![String::from("foo")];
+        assert_eq!(any_of(&patterns), "foo");
+
+        let patterns = vec![String::from("foo"), String::from("ba|r")];
+        assert_eq!(any_of(&patterns), "(?:foo)|(?:ba|r)");
+    }
+
+    #[test]
+    fn test_contains_all_patterns() {
+        let patterns = vec![String::from("foo"), String::from("bar")];
+        let compile = |case| {
+            let options = MatchOptions {
+                case,
+                ..Default::default()
+            };
+            PatternSet::new(&patterns, &options).unwrap()
+        };
+
+        let sensitive = compile(CaseMode::Sensitive);
+        let contains_all = |input: &[u8]| contains_all_patterns(input, &sensitive).unwrap();
+
+        // Neither
+        assert!(!contains_all(b"lorem\nipsum\n"));
+        // Only one of them
+        assert!(!contains_all(b"foo\nipsum\n"));
+        assert!(!contains_all(b"lorem\nbar\nbar\n"));
+        // Both, on different lines
+        assert!(contains_all(b"foo\nipsum\nbar"));
+        // Both, on the same line
+        assert!(contains_all(b"lorem\nbarfoo\n"));
+
+        // Case insensitive
+        assert!(!contains_all(b"FOO\nbar\n"));
+        let insensitive = compile(CaseMode::Insensitive);
+        assert!(contains_all_patterns(&b"FOO\nbar\n"[..], &insensitive).unwrap());
+    }
+
+    #[test]
+    fn test_pattern_set() {
+        let compile = |patterns: &[&str], options: &MatchOptions| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            PatternSet::new(&patterns, options)
+        };
+
+        let patterns = compile(&["foo", "ba|r"], &MatchOptions::default()).unwrap();
+        assert!(patterns.regex().is_match("a bar"));
+        assert!(!patterns.regex().is_match("qux"));
+
+        // Every pattern is case sensitive if any of them has an uppercase letter
+        let smart = MatchOptions {
+            case: CaseMode::Smart,
+            ..Default::default()
+        };
+        let patterns = compile(&["foo", "Bar"], &smart).unwrap();
+        assert!(!patterns.regex().is_match("FOO"));
+        assert!(!contains_all_patterns(&b"FOO\nBar\n"[..], &patterns).unwrap());
+
+        // Everything is checked up front, rather than in every search
+        assert!(compile(&["foo", "[a"], &MatchOptions::default()).is_err());
+        let invalid_not_pattern = MatchOptions {
+            not_pattern: Some(String::from("[a")),
+            ..Default::default()
+        };
+        assert!(compile(&["foo"], &invalid_not_pattern).is_err());
+        let invalid_replace = MatchOptions {
+            replace: Some(String::from("${0:unknown}")),
+            ..Default::default()
+        };
+        assert!(compile(&["foo"], &invalid_replace).is_err());
+        let missing_group = MatchOptions {
+            captures: vec![CaptureGroup::Index(1)],
+            ..Default::default()
+        };
+        assert!(compile(&["foo"], &missing_group).is_err());
+        assert!(compile(&["(foo)"], &missing_group).is_ok());
+    }
+
+    #[test]
+    fn test_pattern_set_literal() {
+        let compile = |patterns: &[&str], options: MatchOptions| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            PatternSet::new(&patterns, &options).unwrap()
+        };
+        let fixed = || MatchOptions {
+            fixed_strings: true,
+            ..Default::default()
+        };
+
+        // A single fixed string is searched for with memmem, with the same matches as the regex
+        let patterns = compile(&["a.c"], fixed());
+        assert!(patterns.literal.is_some());
+        let line = "é a.c abc a.ca.c";
+        let submatches = patterns.find_submatches(line, None);
+        assert_eq!(submatches, find_submatches(patterns.regex(), line, None));
+        assert_eq!(
+            submatches
+                .iter()
+                .map(|m| (m.byte_start, m.char_start, m.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(3, 2, "a.c"), (11, 10, "a.c"), (14, 13, "a.c")]
+        );
+        assert_eq!(patterns.find_submatches(line, Some(1)).len(), 1);
+
+        // Otherwise, the regex is still needed
+        assert!(compile(&["a.c"], MatchOptions::default()).literal.is_none());
+        assert!(compile(&["a.c", "foo"], fixed()).literal.is_none());
+        assert!(compile(&[""], fixed()).literal.is_none());
+        let ignore_case = MatchOptions {
+            case: CaseMode::Insensitive,
+            ..fixed()
+        };
+        assert!(compile(&["a.c"], ignore_case).literal.is_none());
+        let word = MatchOptions {
+            word_regexp: true,
+            ..fixed()
+        };
+        assert!(compile(&["a.c"], word).literal.is_none());
+
+        // Several fixed strings are each escaped before they are combined
+        let patterns = compile(&["a.c", "(b"], fixed());
+        assert!(patterns.regex().is_match("x(b"));
+        assert!(!patterns.regex().is_match("abc"));
+    }
+
+    #[test]
+    fn test_case_mode() {
+        let smart = |pattern: &str| CaseMode::Smart.is_insensitive(pattern);
+
+        assert!(smart("foo"));
+        assert!(!smart("Foo"));
+        assert!(!smart("[A-Z]+"));
+        assert!(!smart(r"\\Foo"));
+        assert!(!smart("(?i:Foo)"));
+        // The letters of escapes, flags and group names are not literals
+        assert!(smart(r"\W\S\D\B\Afoo\z"));
+        assert!(smart(r"\p{Lu}\PL\pL"));
+        assert!(smart(r"\x4F\x{4F}\u004F\U0000004F"));
+        assert!(!smart(r"\x4FA"));
+        assert!(smart("(?U)foo+"));
+        assert!(smart("(?P<Name>foo)(?<Other>bar)"));
+        assert!(smart("[[:upper:]]"));
+
+        assert!(!CaseMode::Sensitive.is_insensitive("foo"));
+        assert!(CaseMode::Insensitive.is_insensitive("Foo"));
+    }
+
+    #[test]
+    fn test_build_regex() {
+        let is_match = |pattern: &str, line: &str, options: MatchOptions| {
+            build_regex(pattern, &options).unwrap().is_match(line)
+        };
+
+        // Fixed strings
+        assert!(is_match("a.c", "abc", MatchOptions::default()));
+        let fixed = || MatchOptions {
+            fixed_strings: true,
+            ..Default::default()
+        };
+        assert!(!is_match("a.c", "abc", fixed()));
+        assert!(is_match("a.c", "xa.cx", fixed()));
+        assert!(is_match("foo(bar)", "foo(bar)", fixed()));
+        // Patterns that are not valid regexes are fine
+        assert!(build_regex("[a", &MatchOptions::default()).is_err());
+        assert!(is_match("[a", "x[a]", fixed()));
+        assert!(is_match(r"a\b", r"xa\by", fixed()));
+
+        // Whole words
+        let word = || MatchOptions {
+            word_regexp: true,
+            ..Default::default()
+        };
+        assert!(is_match("foo", "a foo b", word()));
+        assert!(!is_match("foo", "foobar", word()));
+        assert!(is_match("foo|bar", "bar", word()));
+
+        // Whole lines
+        let line = || MatchOptions {
+            line_regexp: true,
+            ..Default::default()
+        };
+        assert!(is_match("foo", "foo", line()));
+        assert!(!is_match("foo", "foo ", line()));
+        assert!(is_match("foo|bar", "bar", line()));
+
+        // Composes with case insensitivity
+        assert!(is_match(
+            "FOO",
+            "a foo b",
+            MatchOptions {
+                word_regexp: true,
+                case: CaseMode::Insensitive,
+                ..Default::default()
+            }
+        ));
+
+        // The letters of escapes like `\W` do not count, unless the pattern is a fixed string,
+        // which is escaped before smart case is decided on it
+        let smart = |fixed_strings: bool| MatchOptions {
+            case: CaseMode::Smart,
+            fixed_strings,
+            ..Default::default()
+        };
+        assert!(is_match("foo", "FOO", smart(false)));
+        assert!(!is_match("Foo", "FOO", smart(false)));
+        assert!(is_match(r"\wfoo", "_FOO", smart(false)));
+        assert!(!is_match(r"\Wfoo", r"\wFOO", smart(true)));
+        assert!(is_match(r"\wfoo", r"\WFOO", smart(true)));
+    }
+
+    #[test]
+    fn test_count_distinct() {
+        let input = b"error: a\nerror: a\nok\nerror: b\nerror: a\n";
+
+        let count = |options: MatchOptions| {
+            let mut result = Vec::new();
+            let num_matches =
+                find_pattern(&input[..], &mut result, None, "error", &options).unwrap();
+            assert_eq!(result, b"");
+            num_matches
+        };
+
+        assert_eq!(
+            count(MatchOptions {
+                count_only: true,
+                ..Default::default()
+            }),
+            4
+        );
+        assert_eq!(
+            count(MatchOptions {
+                count_only: true,
+                distinct: true,
+                max_distinct: Some(10),
+                ..Default::default()
+            }),
+            2
+        );
+
+        // Too many distinct lines
+        let result = find_pattern(
+            &input[..],
+            std::io::sink(),
+            None,
+            "error",
+            &MatchOptions {
+                count_only: true,
+                distinct: true,
+                max_distinct: Some(1),
+                ..Default::default()
+            },
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_not_pattern() {
+        let input = b"foo\nfoo bar\nneither\nbar\n";
+        let mut result = Vec::new();
+
+        let num_matches = find_pattern(
+            &input[..],
+            &mut result,
+            None,
+            "foo",
+            &MatchOptions {
+                not_pattern: Some(String::from("bar")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(result, b"foo\n");
+        assert_eq!(num_matches, 1);
+    }
+
+    #[test]
+    fn test_invert_match() {
+        let input = b"foo\nfoo bar\nneither\nbar\n";
+        let find = |options: &MatchOptions| {
+            let mut result = Vec::new();
+            let num_matches = find_pattern(&input[..], &mut result, None, "foo", options).unwrap();
+            (String::from_utf8(result).unwrap(), num_matches)
+        };
+
+        let options = MatchOptions {
+            invert_match: true,
+            ..Default::default()
+        };
+        assert_eq!(find(&options), (String::from("neither\nbar\n"), 2));
+
+        // Lines excluded by the not pattern are not matches, so they are selected
+        let options = MatchOptions {
+            invert_match: true,
+            not_pattern: Some(String::from("bar")),
+            ..Default::default()
+        };
+        assert_eq!(find(&options), (String::from("foo bar\nneither\nbar\n"), 3));
+
+        // Nothing is highlighted, since the selected lines have no matches
+        let options = MatchOptions {
+            invert_match: true,
+            color: ColorChoice::Always,
+            ..Default::default()
+        };
+        assert_eq!(find(&options), (String::from("neither\nbar\n"), 2));
+    }
+
+    #[test]
+    fn test_replace() {
+        let input = b"foo = 1\nbar = 2\n";
+        let mut result = Vec::new();
+
+        find_pattern(
+            &input[..],
+            &mut result,
+            Some(Path::new("a.txt")),
+            r"(\w+) = (\d+)",
+            &MatchOptions {
+                replace: Some(String::from("{path}:{lineno}: $2 = $1")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(result, b"a.txt:1: 1 = foo\na.txt:2: 2 = bar\n");
+    }
+
+    #[test]
+    fn test_regex_cache() {
+        let cache = Arc::new(RegexCache::new(8));
+        let options = MatchOptions {
+            regex_cache: Some(Arc::clone(&cache)),
+            not_pattern: Some(String::from("bar")),
+            ..Default::default()
+        };
+        let input = b"foo\nfoo bar\n";
+
+        for _ in 0..3 {
+            let mut result = Vec::new();
+            find_pattern(&input[..], &mut result, None, "foo", &options).unwrap();
+            assert_eq!(result, b"foo\n");
+        }
+
+        // Only the pattern (as a regex and as a set) and the not pattern were compiled, once each
+        assert_eq!(cache.num_compiles(), 3);
+    }
+
+    #[test]
+    fn test_first_and_last() {
+        let input = b"a\nerror 1\nb\nerror 2\nerror 3\nc\n";
+
+        let find = |select: LineSelection| {
+            let mut result = Vec::new();
+            let options = MatchOptions {
+                show_line_numbers: true,
+                select,
+                ..Default::default()
+            };
+            let num_matches =
+                find_pattern(&input[..], &mut result, None, "error", &options).unwrap();
+            (String::from_utf8(result).unwrap(), num_matches)
+        };
+
+        assert_eq!(
+            find(LineSelection::All),
+            (String::from("2: error 1\n4: error 2\n5: error 3\n"), 3)
+        );
+        assert_eq!(
+            find(LineSelection::First),
+            (String::from("2: error 1\n"), 1)
+        );
+        assert_eq!(find(LineSelection::Last), (String::from("5: error 3\n"), 1));
+    }
+
+    #[test]
+    fn test_context_auto() {
+        // Matches on lines 2, 3 (clustered) and 14 (isolated)
+        let input: String = (1..=20)
+            .map(|i| match i {
+                2 | 3 | 14 => format!("match {}\n", i),
+                _ => format!("{}\n", i),
+            })
+            .collect();
+        let mut result = Vec::new();
+
+        let options = MatchOptions {
+            show_line_numbers: true,
+            context_auto: Some(3),
+            ..Default::default()
+        };
+        let num_matches =
+            find_pattern(input.as_bytes(), &mut result, None, "match", &options).unwrap();
+
+        // Line 2: 1 line before (half of 1, clamped to 1), 1 line after (its neighbour)
+        // Line 3: 1 line before (its neighbour), 3 lines after (half of 10, clamped to 3)
+        // Line 14: 3 lines before (half of 10, clamped to 3), 3 lines after (half of 6)
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            "1- 1\n2: match 2\n3: match 3\n4- 4\n5- 5\n6- 6\n--\n11- 11\n12- 12\n13- 13\n14: match 14\n15- 15\n16- 16\n17- 17\n"
+        );
+        assert_eq!(num_matches, 3);
     }
 }
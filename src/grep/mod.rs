@@ -1 +1,7 @@
+pub mod basic;
+pub mod code;
+pub mod decode;
 pub mod matcher;
+pub mod replace;
+pub mod rewrite;
+pub mod search;
@@ -0,0 +1,221 @@
+//! Replacement templates.
+//!
+//! On top of the capture group references supported by the regex crate (e.g. `$1`, `${name}`),
+//! templates can reference these variables:
+//! * `{path}`: the path of the file
+//! * `{lineno}`: the line number of the line
+//!
+//! Use `{{` and `}}` for literal braces. Any other `{...}` is left as is.
+//!
+//! Capture groups can also be transformed with `${group:transform}`, e.g. `${1:upper}` or
+//! `${name:trim}`. See `Transform` for the supported transforms.
+
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use regex::Captures;
+
+use super::matcher::CaptureGroup;
+
+/// A transform that is applied to a capture group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    /// Converts to uppercase.
+    Upper,
+    /// Converts to lowercase.
+    Lower,
+    /// Removes leading and trailing whitespace.
+    Trim,
+}
+
+impl Transform {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "upper" => Transform::Upper,
+            "lower" => Transform::Lower,
+            "trim" => Transform::Trim,
+            _ => bail!(
+                "unknown transform '{}' in replacement (expected upper, lower or trim)",
+                name
+            ),
+        })
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match self {
+            Transform::Upper => text.to_uppercase(),
+            Transform::Lower => text.to_lowercase(),
+            Transform::Trim => text.trim().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    /// Expanded by `Captures::expand`, so it can contain capture group references.
+    Expand(String),
+    Transform(CaptureGroup, Transform),
+    Path,
+    LineNumber,
+}
+
+/// A replacement template with variables and transformed capture groups, e.g. `{lineno}` and
+/// `${1:upper}`. It is parsed once, and then expanded for each match.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parses the variables and transforms in `template`, which fails if a transform is unknown.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+
+        let mut rest = template;
+        while let Some(idx) = rest.find(['$', '{', '}']) {
+            literal.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+
+            if let Some(after) = rest.strip_prefix("$$") {
+                literal.push_str("$$");
+                rest = after;
+                continue;
+            }
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                literal.push_str(&rest[..1]);
+                rest = &rest[2..];
+                continue;
+            }
+
+            let transform = rest
+                .strip_prefix("${")
+                .and_then(|after| after.split_once('}'))
+                .and_then(|(inner, after)| Some((inner.split_once(':')?, after)));
+            let (part, after) = if let Some(after) = rest.strip_prefix("{path}") {
+                (Part::Path, after)
+            } else if let Some(after) = rest.strip_prefix("{lineno}") {
+                (Part::LineNumber, after)
+            } else if let Some(((group, transform), after)) = transform {
+                let group = group.parse().unwrap_or_else(|err| match err {});
+                (Part::Transform(group, Transform::parse(transform)?), after)
+            } else {
+                literal.push_str(&rest[..1]);
+                rest = &rest[1..];
+                continue;
+            };
+
+            if !literal.is_empty() {
+                parts.push(Part::Expand(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+            rest = after;
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Expand(literal));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Expands the template for a match on line `line_number` of the file at `path`, if it has
+    /// one. Groups that did not participate in the match are empty.
+    pub fn expand(&self, captures: &Captures, path: Option<&Path>, line_number: usize) -> String {
+        let mut expanded = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Expand(literal) => captures.expand(literal, &mut expanded),
+                Part::Path => {
+                    if let Some(path) = path {
+                        expanded.push_str(&path.display().to_string());
+                    }
+                }
+                Part::LineNumber => expanded.push_str(&line_number.to_string()),
+                Part::Transform(group, transform) => {
+                    let m = match group {
+                        CaptureGroup::Index(index) => captures.get(*index),
+                        CaptureGroup::Name(name) => captures.name(name),
+                    };
+                    if let Some(m) = m {
+                        expanded.push_str(&transform.apply(m.as_str()));
+                    }
+                }
+            }
+        }
+
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_variables() {
+        let path = Some(Path::new("src/main.rs"));
+        let regex = regex::Regex::new("(.)").unwrap();
+        let captures = regex.captures("a").unwrap();
+        let expand = |template: &str, path: Option<&Path>, line_number: usize| {
+            Template::parse(template)
+                .unwrap()
+                .expand(&captures, path, line_number)
+        };
+
+        // No variables
+        assert_eq!(expand("", path, 1), "");
+        assert_eq!(expand("$0", path, 1), "a");
+        // Variables
+        assert_eq!(expand("{path}:{lineno}: $0", path, 12), "src/main.rs:12: a");
+        assert_eq!(expand("{path}", None, 12), "");
+        // Escaped braces
+        assert_eq!(expand("{{path}}", path, 1), "{path}");
+        assert_eq!(expand("{{{lineno}}}", path, 3), "{3}");
+        // Unknown variables are left as is, and capture groups still work next to variables
+        assert_eq!(expand("{unknown}", path, 1), "{unknown}");
+        assert_eq!(expand("${1}{lineno}", path, 3), "a3");
+        assert_eq!(expand("}{", path, 1), "}{");
+        // `$` in the path is not a capture group reference
+        assert_eq!(expand("{path}", Some(Path::new("$1.txt")), 1), "$1.txt");
+    }
+
+    #[test]
+    fn test_template_parse() {
+        assert_eq!(
+            Template::parse("$1-${2:upper}!").unwrap(),
+            Template {
+                parts: vec![
+                    Part::Expand(String::from("$1-")),
+                    Part::Transform(CaptureGroup::Index(2), Transform::Upper),
+                    Part::Expand(String::from("!")),
+                ]
+            }
+        );
+        // Escaped `$` and plain references are not transforms
+        assert_eq!(
+            Template::parse("$${1:upper}${name}").unwrap(),
+            Template {
+                parts: vec![Part::Expand(String::from("$${1:upper}${name}"))]
+            }
+        );
+        assert!(Template::parse("${1:title}").is_err());
+    }
+
+    #[test]
+    fn test_template_expand() {
+        let regex = regex::Regex::new(r"(?<key>\w+)=(\s*\w+\s*)").unwrap();
+        let captures = regex.captures("Name= Alice ").unwrap();
+
+        let expand = |template: &str| {
+            Template::parse(template)
+                .unwrap()
+                .expand(&captures, None, 1)
+        };
+        assert_eq!(expand("${key:upper}"), "NAME");
+        assert_eq!(expand("${key:lower}=${2:trim}"), "name=Alice");
+        assert_eq!(expand("${2:upper}|$2"), " ALICE | Alice ");
+        // Groups that do not exist are empty
+        assert_eq!(expand("[${3:upper}]"), "[]");
+    }
+}
@@ -0,0 +1,119 @@
+//! Replaces the matches of a pattern in files, in place.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use super::replace::Template;
+
+/// Replaces every match of `regex` in each line of `contents` with `template`, like
+/// `MatchOptions::replace` (see `replace`). Line terminators are kept as they are.
+///
+/// Returns the new contents, along with the number of lines that changed.
+pub fn replace_lines(
+    contents: &str,
+    regex: &Regex,
+    template: &Template,
+    path: Option<&Path>,
+) -> (String, usize) {
+    let mut replaced = String::with_capacity(contents.len());
+    let mut num_changed = 0;
+
+    for (i, line) in contents.split_inclusive('\n').enumerate() {
+        let (line, terminator) = match line.strip_suffix("\r\n") {
+            Some(line) => (line, "\r\n"),
+            None => match line.strip_suffix('\n') {
+                Some(line) => (line, "\n"),
+                None => (line, ""),
+            },
+        };
+        if !regex.is_match(line) {
+            replaced.push_str(line);
+            replaced.push_str(terminator);
+            continue;
+        }
+
+        let new_line = regex.replace_all(line, |captures: &regex::Captures| {
+            template.expand(captures, path, i + 1)
+        });
+        if new_line != line {
+            num_changed += 1;
+        }
+        replaced.push_str(&new_line);
+        replaced.push_str(terminator);
+    }
+
+    (replaced, num_changed)
+}
+
+/// Replaces the contents of the file at `path`, atomically: `contents` is written to a temporary
+/// file next to it, which is then renamed over it, so the file is never left half written, even
+/// if writing fails. The file keeps its permissions, and a symlink is followed rather than
+/// replaced.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let path =
+        std::fs::canonicalize(path).with_context(|| format!("could not read file {:?}", path))?;
+    let permissions = std::fs::metadata(&path)?.permissions();
+    let tmp_path = temp_path(&path);
+
+    let result = (|| -> std::io::Result<()> {
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(contents)?;
+        f.set_permissions(permissions)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp_path, &path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+
+    result.with_context(|| format!("could not write file {:?}", path))
+}
+
+/// A hidden path next to `path`, in the same directory so that it can be renamed over it.
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.grrs-{}.tmp", file_name, std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_replace_lines() {
+        let regex = Regex::new(r"foo_(\w+)").unwrap();
+        let replace = |contents: &str, template: &str| {
+            replace_lines(contents, &regex, &Template::parse(template).unwrap(), None)
+        };
+
+        assert_eq!(
+            replace("foo_a foo_b\nbar\r\nfoo_c", "${1:upper}"),
+            (String::from("A B\nbar\r\nC"), 2)
+        );
+        assert_eq!(
+            replace("bar\nfoo_a\n", "{lineno}:$1"),
+            (String::from("bar\n2:a\n"), 1)
+        );
+        // A match that is replaced with itself does not change the line
+        assert_eq!(replace("foo_a\n", "$0"), (String::from("foo_a\n"), 0));
+        assert!(Template::parse("${1:unknown}").is_err());
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = TempDir::new();
+        let path = dir.write("a.txt", "foo\n");
+
+        write_atomically(&path, b"bar\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "bar\n");
+        // The temporary file is gone
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        assert!(write_atomically(&dir.path().join("missing.txt"), b"bar\n").is_err());
+    }
+}
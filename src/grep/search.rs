@@ -0,0 +1,296 @@
+//! Searches files and hands each matching line to a callback as soon as it is found, e.g. for a
+//! TUI that renders the results incrementally.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result, anyhow};
+use crossbeam::channel::{Sender, bounded};
+use log::warn;
+
+use crate::core::ignore::WalkOptions;
+use crate::core::line_reader::LineReader;
+use crate::core::threads::ThreadPool;
+use crate::grep::code::CodeMasker;
+use crate::grep::matcher::{MatchOptions, PatternSet, Submatch, select_submatches};
+
+/// The number of matching lines that can be found before the callback has to catch up.
+const CHANNEL_SIZE: usize = 256;
+
+/// A matching line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchRecord {
+    pub path: PathBuf,
+    /// The 1-based line number.
+    pub line_number: usize,
+    /// The line, without its line terminator.
+    pub line: String,
+    pub submatches: Vec<Submatch>,
+}
+
+#[derive(Default)]
+pub struct SearchOptions {
+    pub walk: WalkOptions,
+    /// Only the options that change which lines match and where are used (e.g. `case`,
+    /// `not_pattern` and `invert_match`, see `select_submatches`), since nothing is written. With
+    /// `decode` or `invert_match`, the records have no submatches, since they would not line up
+    /// with the line.
+    pub matching: MatchOptions,
+    /// Searches this many files at a time, or as many as there are cores if None.
+    pub threads: Option<NonZeroUsize>,
+}
+
+/// Searches the files under `paths` for `pattern`, calling `callback` with each matching line.
+///
+/// The files are searched in parallel, so the lines of different files can be interleaved, but
+/// `callback` is always called from the calling thread. Returning `ControlFlow::Break` stops the
+/// search: `callback` is not called again, and the files that are being searched are abandoned.
+pub fn search_with_callback<F>(
+    paths: Vec<PathBuf>,
+    pattern: &str,
+    options: SearchOptions,
+    mut callback: F,
+) -> Result<()>
+where
+    F: FnMut(MatchRecord) -> ControlFlow<()>,
+{
+    let SearchOptions {
+        walk: walk_options,
+        matching,
+        threads,
+    } = options;
+    let patterns = Arc::new(PatternSet::new(&[pattern.to_string()], &matching)?);
+    let matching = Arc::new(matching);
+
+    let (tx, rx) = bounded(CHANNEL_SIZE);
+    let is_stopped = Arc::new(AtomicBool::new(false));
+
+    // The files are handed to the pool from another thread, since handing them over blocks
+    // while the pool is busy, and this thread has to keep calling the callback meanwhile
+    let producer = {
+        let is_stopped = Arc::clone(&is_stopped);
+        std::thread::spawn(move || -> Result<()> {
+            let thread_pool = match threads {
+                Some(threads) => ThreadPool::new(threads.get()),
+                None => ThreadPool::all_cores(),
+            };
+
+            'paths: for path in paths {
+                for file_path in crate::core::ignore::walk_iter(path, &walk_options)? {
+                    let file_path = file_path?;
+                    if is_stopped.load(Ordering::Relaxed) {
+                        break 'paths;
+                    }
+
+                    let patterns = Arc::clone(&patterns);
+                    let matching = Arc::clone(&matching);
+                    let tx = tx.clone();
+                    let is_stopped = Arc::clone(&is_stopped);
+                    let search_zip = walk_options.search_zip;
+                    thread_pool.execute(move || {
+                        let file = SearchedFile {
+                            path: &file_path,
+                            patterns: &patterns,
+                            options: &matching,
+                            search_zip,
+                        };
+                        if let Err(err) = file.search(&tx, &is_stopped) {
+                            warn!("failed to read {}: {}", file_path.display(), err);
+                        }
+                    });
+                }
+            }
+
+            thread_pool.wait().map_err(|errors| {
+                anyhow!(
+                    "{} searches failed, e.g. {}",
+                    errors.len(),
+                    errors[0].message
+                )
+            })
+        })
+    };
+
+    // Ends once every sender is dropped, i.e. when all of the files are searched
+    for record in rx.iter() {
+        if callback(record).is_break() {
+            is_stopped.store(true, Ordering::Relaxed);
+            break;
+        }
+    }
+    // Any searches that are still sending fail, so they stop too
+    drop(rx);
+
+    producer
+        .join()
+        .map_err(|_| anyhow!("the search thread panicked"))?
+}
+
+/// A file to search in a worker thread.
+struct SearchedFile<'a> {
+    path: &'a Path,
+    patterns: &'a PatternSet,
+    options: &'a MatchOptions,
+    search_zip: bool,
+}
+
+impl SearchedFile<'_> {
+    /// Sends each matching line to `tx`, until the search is stopped or the receiver is dropped.
+    fn search(&self, tx: &Sender<MatchRecord>, is_stopped: &AtomicBool) -> Result<()> {
+        let f = File::open(self.path)
+            .with_context(|| format!("could not read file {:?}", self.path))?;
+        let reader: Box<dyn Read> = if self.search_zip {
+            crate::core::compressed::reader(self.path, f)
+        } else {
+            Box::new(f)
+        };
+
+        let mut masker = self.options.code_only.map(CodeMasker::new);
+        let mut lines = LineReader::new(BufReader::new(reader));
+        while let Some((line_number, _, line)) = lines.next_line()? {
+            if is_stopped.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let mut submatches =
+                select_submatches(self.patterns, masker.as_mut(), line, self.options);
+            if submatches.is_empty() != self.options.invert_match {
+                continue;
+            }
+            if self.options.decode.is_some() || self.options.invert_match {
+                submatches.clear();
+            }
+            let record = MatchRecord {
+                path: self.path.to_path_buf(),
+                line_number,
+                line: line.to_string(),
+                submatches,
+            };
+            if tx.send(record).is_err() {
+                // The search was stopped
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grep::code::Lang;
+    use crate::grep::matcher::CaseMode;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_search_with_callback() {
+        let temp_dir = TempDir::new();
+        let dir = temp_dir.path();
+        temp_dir.write("a.txt", "foo\nbar\nfoo foo\n");
+        temp_dir.write("sub/b.txt", "FOO\n");
+        temp_dir.write("c.txt", "bar\n");
+
+        let mut records = Vec::new();
+        let options = SearchOptions {
+            matching: MatchOptions {
+                case: CaseMode::Insensitive,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        search_with_callback(vec![dir.to_path_buf()], "foo", options, |record| {
+            records.push(record);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        records.sort_by(|a, b| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)));
+        let found: Vec<_> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.path.clone(),
+                    record.line_number,
+                    record.line.as_str(),
+                    record.submatches.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (dir.join("a.txt"), 1, "foo", 1),
+                (dir.join("a.txt"), 3, "foo foo", 2),
+                (dir.join("sub/b.txt"), 1, "FOO", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_with_callback_options() {
+        let dir = TempDir::new();
+        dir.write("a.c", "foo();\n// foo\nfoo(foo);\nfoo(bar);\nbaz();\n");
+
+        let search = |matching: MatchOptions| {
+            let mut records = Vec::new();
+            let options = SearchOptions {
+                matching,
+                ..Default::default()
+            };
+            search_with_callback(vec![dir.path().to_path_buf()], "foo", options, |record| {
+                records.push((record.line_number, record.submatches.len()));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+            records
+        };
+
+        assert_eq!(
+            search(MatchOptions {
+                code_only: Some(Lang::C),
+                not_pattern: Some(String::from("bar")),
+                ..Default::default()
+            }),
+            vec![(1, 1), (3, 2)]
+        );
+        assert_eq!(
+            search(MatchOptions {
+                nth_match: Some(2),
+                ..Default::default()
+            }),
+            vec![(3, 1)]
+        );
+        // The selected lines do not match, so there is nothing in them to point at
+        assert_eq!(
+            search(MatchOptions {
+                invert_match: true,
+                ..Default::default()
+            }),
+            vec![(5, 0)]
+        );
+    }
+
+    #[test]
+    fn test_search_with_callback_break() {
+        let dir = TempDir::new();
+        // Far more matches than fit in the channel, so the search cannot finish on its own
+        for i in 0..8 {
+            dir.write(&format!("{}.txt", i), &"foo\n".repeat(10_000));
+        }
+
+        let mut num_calls = 0;
+        let paths = vec![dir.path().to_path_buf()];
+        search_with_callback(paths, "foo", SearchOptions::default(), |_| {
+            num_calls += 1;
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(num_calls, 1);
+    }
+}
@@ -1,2 +1,6 @@
 pub mod core;
 pub mod grep;
+#[cfg(test)]
+mod test_util;
+
+pub use grep::search::{MatchRecord, SearchOptions, search_with_callback};
@@ -1,9 +1,21 @@
-use anyhow::Result;
+use std::process::ExitCode;
 
 mod commands;
+#[cfg(test)]
+mod test_util;
 
-fn main() -> Result<()> {
+/// Like grep, 1 means that nothing was found, so errors are 2 instead.
+const ERROR_EXIT_CODE: u8 = 2;
+
+fn main() -> ExitCode {
     env_logger::init();
 
-    commands::run()
+    match commands::run() {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            // The same as returning the error from `main`
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(ERROR_EXIT_CODE)
+        }
+    }
 }
@@ -0,0 +1,2204 @@
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+#[path = "../src/test_util.rs"]
+mod test_util;
+
+use test_util::TempDir;
+
+/// The grrs binary, without the user's global ignore file, which would change what is searched.
+fn grrs_command() -> Command {
+    let no_config = std::env::temp_dir().join("grrs-test-no-config");
+    let mut command = Command::new(env!("CARGO_BIN_EXE_grrs"));
+    command
+        .env("XDG_CONFIG_HOME", &no_config)
+        .env("GIT_CONFIG", no_config.join("config"));
+    command
+}
+
+fn grrs<I: IntoIterator<Item = S>, S: AsRef<std::ffi::OsStr>>(args: I) -> Output {
+    grrs_command().args(args).output().unwrap()
+}
+
+/// Returns the lines of stdout, sorted because files are searched in parallel.
+fn sorted_lines(output: &Output) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn test_files_without_pattern() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "hello\n");
+    let b = dir.write("sub/b.txt", "world\n");
+    let expected = vec![a.display().to_string(), b.display().to_string()];
+
+    let output = grrs([Path::new("files"), dir.path()]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), expected);
+
+    let output = grrs([Path::new("grep"), Path::new("--files"), dir.path()]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), expected);
+
+    // The pattern is never compiled, so an invalid pattern is fine
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--files"),
+        Path::new("("),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), expected);
+}
+
+#[test]
+fn test_files_all() {
+    let dir = TempDir::new();
+    dir.write("none.txt", "lorem\nipsum\n");
+    dir.write("foo.txt", "foo\nipsum\n");
+    dir.write("bar.txt", "lorem\nbar\n");
+    let both = dir.write("both.txt", "foo\nlorem\nbar\n");
+    let same_line = dir.write("same_line.txt", "foobar\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--files-all"),
+        Path::new("-e"),
+        Path::new("foo"),
+        Path::new("-e"),
+        Path::new("bar"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        vec![both.display().to_string(), same_line.display().to_string()]
+    );
+}
+
+#[test]
+fn test_match() {
+    let output = grrs(["match", "fo+", "a foo b"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"a foo b\n");
+
+    let output = grrs(["match", "fo+", "a bar b"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"");
+
+    let output = grrs(["match", "-o", "fo+", "a foo b fooo"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"foo\nfooo\n");
+
+    let output = grrs(["match", "-o", "--column", "fo+", "a foo b fooo"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"3:foo\n9:fooo\n");
+
+    // Flags that change the pattern
+    assert_eq!(
+        grrs(["match", "-w", "foo", "foobar"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        grrs(["match", "-x", "foo", "foo bar"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(grrs(["match", "-i", "FOO", "foo"]).status.code(), Some(0));
+    assert_eq!(grrs(["match", "-F", "a.c", "abc"]).status.code(), Some(1));
+    assert_eq!(grrs(["match", "-F", "a.c", "a.c"]).status.code(), Some(0));
+}
+
+#[test]
+fn test_base() {
+    let dir = TempDir::new();
+    dir.write("src/a.txt", "hello\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--base"),
+        dir.path(),
+        Path::new("hello"),
+        &dir.path().join("src"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"src/a.txt:\n1: hello\n");
+}
+
+#[test]
+fn test_count_distinct() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "error: a\nerror: a\nok\nerror: b\nerror: a\n");
+    dir.write("b.txt", "ok\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-c"),
+        Path::new("error"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), vec![format!("{}:4", a.display())]);
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-c"),
+        Path::new("--distinct"),
+        Path::new("error"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), vec![format!("{}:2", a.display())]);
+
+    // Only the distinct lines are limited
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-c"),
+        Path::new("--max-distinct"),
+        Path::new("1"),
+        Path::new("error"),
+        dir.path(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_count_include_zero() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "error: a\nok\nerror: b\n");
+    let b = dir.write("b.txt", "ok\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-c"),
+        Path::new("--include-zero"),
+        Path::new("error"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        vec![format!("{}:2", a.display()), format!("{}:0", b.display())]
+    );
+}
+
+#[test]
+fn test_multiple_paths() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\n");
+    let b = dir.write("b.txt", "foo\n");
+
+    // The first positional argument is the pattern
+    let output = grrs([Path::new("grep"), Path::new("-c"), Path::new("foo"), &a]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), vec![format!("{}:1", a.display())]);
+
+    // With `-e`, every positional argument is a path
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-c"),
+        Path::new("-e"),
+        Path::new("foo"),
+        &a,
+        &b,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        vec![format!("{}:1", a.display()), format!("{}:1", b.display())]
+    );
+}
+
+#[test]
+fn test_log_rotation() {
+    use std::io::Write;
+
+    let dir = TempDir::new();
+    let live = dir.write("app.log", "boot 3\n");
+    let rotated = dir.write("app.log.1", "boot 2\n");
+    let compressed = dir.path().join("app.log.2.gz");
+    let mut encoder = flate2::write::GzEncoder::new(
+        std::fs::File::create(&compressed).unwrap(),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(b"boot 1\n").unwrap();
+    encoder.finish().unwrap();
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-z"),
+        Path::new("--log-rotation"),
+        Path::new("-N"),
+        Path::new("boot"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}:\nboot 3\n{}:\nboot 2\n{}:\nboot 1\n",
+            live.display(),
+            rotated.display(),
+            compressed.display()
+        )
+    );
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-z"),
+        Path::new("--log-rotation=oldest-first"),
+        Path::new("-N"),
+        Path::new("boot"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}:\nboot 1\n{}:\nboot 2\n{}:\nboot 3\n",
+            compressed.display(),
+            rotated.display(),
+            live.display()
+        )
+    );
+
+    // Compressed files are skipped without `-z`
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-c"),
+        Path::new("boot"),
+        dir.path(),
+    ]);
+    assert_eq!(
+        sorted_lines(&output),
+        vec![
+            format!("{}:1", rotated.display()),
+            format!("{}:1", live.display())
+        ]
+    );
+}
+
+#[test]
+fn test_summary_only() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "foo\nbar\nfoo\n");
+    dir.write("sub/b.txt", "foo\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--summary-only"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"3\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--summary-only"),
+        Path::new("baz"),
+        dir.path(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"0\n");
+}
+
+#[test]
+fn test_markdown() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\nbar\n");
+    let b = dir.write("b.txt", "bar\nuse `foo`\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--markdown"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+
+    let a_section = format!("### {}\n\n```\n1: foo\n```\n", a.display());
+    let b_section = format!("### {}\n\n```\n2: use `foo`\n```\n", b.display());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Files are searched in parallel, so they can be in either order
+    assert!(
+        stdout == a_section.clone() + "\n" + &b_section || stdout == b_section + "\n" + &a_section,
+        "unexpected output: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_pager() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\n");
+    let paged = dir.path().join("paged.txt");
+
+    let output = grrs_command()
+        .args([
+            Path::new("grep"),
+            Path::new("--pager=always"),
+            Path::new("foo"),
+            &a,
+        ])
+        .env("PAGER", format!("tee {}", paged.display()))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(&paged).unwrap(),
+        format!("{}:\n1: foo\n", a.display())
+    );
+
+    // A pager that exits without reading everything is not an error
+    let b = dir.write("b.txt", &"foo\n".repeat(100_000));
+    let output = grrs_command()
+        .args([
+            Path::new("grep"),
+            Path::new("--pager=always"),
+            Path::new("foo"),
+            &b,
+        ])
+        .env("PAGER", "true")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // Never pages when stdout is not a terminal
+    let output = grrs_command()
+        .args([
+            Path::new("grep"),
+            Path::new("--pager"),
+            Path::new("foo"),
+            &a,
+        ])
+        .env("PAGER", "false")
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.stdout,
+        format!("{}:\n1: foo\n", a.display()).as_bytes()
+    );
+}
+
+#[test]
+fn test_single_thread_is_reproducible() {
+    let dir = TempDir::new();
+    let mut expected = String::new();
+    let mut file_paths = Vec::new();
+    for i in 0..50 {
+        file_paths.push(dir.write(&format!("{}/{}.txt", i % 7, i), "foo\n"));
+    }
+    file_paths.sort();
+    for file_path in &file_paths {
+        expected.push_str(&format!("{}:\n1: foo\n", file_path.display()));
+    }
+
+    let run = || {
+        let output = grrs([
+            Path::new("grep"),
+            Path::new("-j"),
+            Path::new("1"),
+            Path::new("foo"),
+            dir.path(),
+        ]);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(run(), expected);
+    assert_eq!(run(), expected);
+}
+
+#[test]
+fn test_bytes_searched() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "foo\nbar\n");
+    dir.write("sub/b.txt", "foo\n");
+    // Binary files are skipped, so they are not searched
+    dir.write("c.bin", "foo\0\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--bytes-searched"),
+        Path::new("-c"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().any(|line| line == "12 bytes searched"),
+        "{}",
+        stdout
+    );
+
+    // Files that fail partway count the bytes that were read
+    // The invalid line is after the part that is probed for binary files
+    let mut invalid = "a".repeat(2000).into_bytes();
+    invalid.extend(b"\n\xff\nfoo\n");
+    std::fs::write(dir.path().join("sub/invalid.txt"), invalid).unwrap();
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--bytes-searched"),
+        Path::new("-c"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().any(|line| line == "2015 bytes searched"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_unique_output() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "TODO: fix\nTODO: test\n");
+    dir.write("b.txt", "TODO: test\nTODO: fix\nTODO: docs\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--unique-output"),
+        Path::new("-j"),
+        Path::new("1"),
+        Path::new("-N"),
+        Path::new("TODO"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}:\nTODO: fix\nTODO: test\n{}:\nTODO: docs\n",
+            dir.path().join("a.txt").display(),
+            dir.path().join("b.txt").display()
+        )
+    );
+}
+
+#[test]
+fn test_max_count() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo 1\nbar\nfoo 2\nbar\nfoo 3\n");
+
+    let max_count = |args: &[&str]| {
+        let mut command = grrs_command();
+        command
+            .args(["grep", "-m", "2"])
+            .args(args)
+            .arg("foo")
+            .arg(&a);
+        String::from_utf8_lossy(&command.output().unwrap().stdout).into_owned()
+    };
+
+    assert_eq!(
+        max_count(&[]),
+        format!("{}:\n1: foo 1\n3: foo 2\n", a.display())
+    );
+    // The context after the last match is still printed
+    assert_eq!(
+        max_count(&["-A", "1"]),
+        format!("{}:\n1: foo 1\n2- bar\n3: foo 2\n4- bar\n", a.display())
+    );
+    assert_eq!(max_count(&["-c"]), format!("{}:2\n", a.display()));
+}
+
+#[test]
+fn test_show_truncation() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo 1\nfoo 2\nfoo 3\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-m"),
+        Path::new("2"),
+        Path::new("--show-truncation"),
+        Path::new("foo"),
+        &a,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}:\n1: foo 1\n2: foo 2\n... (more matches in {})\n",
+            a.display(),
+            a.display()
+        )
+    );
+}
+
+#[test]
+fn test_command() {
+    let output = grrs(["grep", "--cmd", "echo foo bar", "bar"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1: foo bar\n");
+
+    // The command fails
+    let output = grrs(["grep", "--cmd", "false", "bar"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("command 'false' failed"));
+
+    // The command does not exist
+    let output = grrs(["grep", "--cmd", "grrs-no-such-command", "bar"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("could not run command 'grrs-no-such-command'")
+    );
+}
+
+#[test]
+fn test_directories() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\n");
+    let b = dir.write("sub/b.txt", "foo\n");
+    let sub = dir.path().join("sub");
+
+    let directories = |action: &str| {
+        grrs([
+            Path::new("grep"),
+            Path::new("-c"),
+            Path::new("--directories"),
+            Path::new(action),
+            Path::new("foo"),
+            &a,
+            &sub,
+        ])
+    };
+
+    let output = directories("recurse");
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        vec![format!("{}:1", a.display()), format!("{}:1", b.display())]
+    );
+
+    let output = directories("skip");
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), vec![format!("{}:1", a.display())]);
+
+    // Like grep, directories cannot be read as files
+    let output = directories("read");
+    assert!(!output.status.success());
+    assert_eq!(sorted_lines(&output), vec![format!("{}:1", a.display())]);
+}
+
+#[test]
+fn test_fixed_strings() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "a.b.c\naxbxc\nfoo(bar)\n");
+
+    let output = grrs([Path::new("grep"), Path::new("-F"), Path::new("a.b.c"), &a]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:\n1: a.b.c\n", a.display())
+    );
+
+    // Each pattern is literal, even when there are several
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-F"),
+        Path::new("-i"),
+        Path::new("-e"),
+        Path::new("FOO(bar)"),
+        Path::new("-e"),
+        Path::new("a.b"),
+        &a,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:\n1: a.b.c\n3: foo(bar)\n", a.display())
+    );
+}
+
+#[test]
+fn test_trailing_newline() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "foo\n\nbar");
+    dir.write("b.txt", "bar\nfoo");
+
+    let modes: [&[&str]; 6] = [
+        &[],
+        &["-N"],
+        &["-c"],
+        &["--markdown"],
+        &["--paragraph"],
+        &["-C", "1"],
+    ];
+    for mode in modes {
+        let search = |pattern: &str| {
+            let mut args: Vec<&std::ffi::OsStr> = vec!["grep".as_ref(), pattern.as_ref()];
+            args.push(dir.path().as_os_str());
+            args.extend(mode.iter().map(std::ffi::OsStr::new));
+            grrs(args)
+        };
+
+        // Nothing at all when there are no matches
+        let output = search("baz");
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"", "mode {:?}", mode);
+
+        // Exactly one newline at the end, even without one in the input
+        let output = search("foo");
+        assert!(output.status.success());
+        assert!(
+            output.stdout.ends_with(b"\n") && !output.stdout.ends_with(b"\n\n"),
+            "mode {:?}: {:?}",
+            mode,
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+}
+
+#[test]
+fn test_grep_only_matching() {
+    let dir = TempDir::new();
+    let a = dir.write(
+        "a.txt",
+        "see https://a.com and http://b.org\nnothing\nhttps://c.net\n",
+    );
+
+    // Each match is on its own line, with the line number of its line
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-o"),
+        Path::new(r"https?://\S+"),
+        &a,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}:\n1: https://a.com\n1: http://b.org\n3: https://c.net\n",
+            a.display()
+        )
+    );
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-o"),
+        Path::new("-N"),
+        Path::new(r"https?://\S+"),
+        &a,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}:\nhttps://a.com\nhttp://b.org\nhttps://c.net\n",
+            a.display()
+        )
+    );
+
+    // Context lines would be whole lines among the matches
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-o"),
+        Path::new("-C"),
+        Path::new("1"),
+        Path::new(r"https?://\S+"),
+        &a,
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_color() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "a foo b\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--color"),
+        Path::new("always"),
+        Path::new("foo"),
+        &a,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "\x1b[35m{}\x1b[0m:\n\x1b[32m1\x1b[0m: a \x1b[1;31mfoo\x1b[0m b\n",
+            a.display()
+        )
+    );
+
+    // Never colored unless asked for
+    let output = grrs([Path::new("grep"), Path::new("foo"), &a]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:\n1: a foo b\n", a.display())
+    );
+}
+
+/// Runs grrs with `input` piped to stdin.
+fn grrs_with_stdin<I: IntoIterator<Item = S>, S: AsRef<std::ffi::OsStr>>(
+    args: I,
+    input: &[u8],
+) -> Output {
+    let mut child = grrs_command()
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Fails if grrs exits without reading stdin, which is fine
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_stdin() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo a\n");
+
+    // Without a header, and not the current directory
+    let output = grrs_with_stdin(["grep", "foo"], b"bar\nfoo\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2: foo\n");
+
+    let output = grrs_with_stdin(["grep", "-c", "foo", "-"], b"bar\nfoo\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+
+    // With other paths, stdin has a header
+    let output = grrs_with_stdin(
+        [
+            Path::new("grep"),
+            Path::new("-j"),
+            Path::new("1"),
+            Path::new("foo"),
+            Path::new("-"),
+            &a,
+        ],
+        b"foo\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("(standard input):\n1: foo\n{}:\n1: foo a\n", a.display())
+    );
+}
+
+#[test]
+fn test_stdin_match_options() {
+    let input = b"foo 1\nbar\nFOOO 2\n";
+    let stdout = |args: &[&str]| {
+        let mut all_args = vec!["grep"];
+        all_args.extend(args);
+        let output = grrs_with_stdin(all_args, input);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(stdout(&["-i", "-o", "fo+"]), "1: foo\n3: FOOO\n");
+    assert_eq!(stdout(&["-v", "-N", "foo"]), "bar\nFOOO 2\n");
+    // Like the counts, the ranges do not have the path
+    assert_eq!(stdout(&["-i", "-c", "foo"]), "2\n");
+    assert_eq!(stdout(&["-i", "--ranges", "foo"]), "1,3\n");
+    assert_eq!(stdout(&["-l", "bar"]), "(standard input)\n");
+    assert_eq!(stdout(&["-A", "1", "1"]), "1: foo 1\n2- bar\n");
+    assert!(
+        stdout(&["--json", "bar"]).starts_with(r#"{"path":"(standard input)","line_number":2,"#)
+    );
+}
+
+#[test]
+fn test_stdin_and_files() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo a\n");
+
+    // Piped stdin is searched first, along with the given path
+    let expected = format!(
+        "(standard input):\n2: foo stdin\n{}:\n1: foo a\n",
+        a.display()
+    );
+    let output = grrs_with_stdin(
+        [
+            Path::new("grep"),
+            Path::new("-j"),
+            Path::new("1"),
+            Path::new("foo"),
+            &a,
+        ],
+        b"bar\nfoo stdin\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    // Giving it as `-` as well does not search it twice
+    let output = grrs_with_stdin(
+        [
+            Path::new("grep"),
+            Path::new("-j"),
+            Path::new("1"),
+            Path::new("foo"),
+            &a,
+            Path::new("-"),
+        ],
+        b"bar\nfoo stdin\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn test_sort_path() {
+    let dir = TempDir::new();
+    let mut expected = String::new();
+    for i in 0..20 {
+        let path = dir.write(&format!("{:02}.txt", i), "foo\n");
+        expected.push_str(&format!("{}:\n1: foo\n", path.display()));
+    }
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--sort"),
+        Path::new("path"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    // Across the paths, which are not given in order
+    let d1 = dir.write("d1/a.txt", "foo\n");
+    let d2 = dir.write("d2/a.txt", "foo\n");
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--sort"),
+        Path::new("path"),
+        Path::new("foo"),
+        &dir.path().join("d2"),
+        &dir.path().join("d1"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:\n1: foo\n{}:\n1: foo\n", d1.display(), d2.display())
+    );
+}
+
+#[test]
+fn test_ranges() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\nfoo\nbar\nfoo\nbar\nbar\nfoo\nfoo\nfoo\n");
+    dir.write("b.txt", "bar\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--ranges"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:1-2,4,7-9\n", a.display())
+    );
+}
+
+#[test]
+fn test_json() {
+    let dir = TempDir::new();
+    let a = dir.write("a:b.txt", "foo: \"x\"\nbar\n");
+
+    let output = grrs([Path::new("grep"), Path::new("--json"), Path::new("foo"), &a]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            concat!(
+                "{{\"path\":\"{}\",\"line_number\":1,\"byte_offset\":0,\"text\":\"foo: \\\"x\\\"\",",
+                "\"submatches\":[{{\"start\":0,\"end\":3,\"text\":\"foo\"}}]}}\n"
+            ),
+            a.display()
+        )
+    );
+
+    // With -o, the offsets are relative to the match, which is all of the text
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-o"),
+        Path::new("--json"),
+        Path::new("x"),
+        &a,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            concat!(
+                "{{\"path\":\"{}\",\"line_number\":1,\"byte_offset\":0,\"text\":\"x\",",
+                "\"submatches\":[{{\"start\":0,\"end\":1,\"text\":\"x\"}}]}}\n"
+            ),
+            a.display()
+        )
+    );
+}
+
+#[test]
+fn test_code_only() {
+    let dir = TempDir::new();
+    let a = dir.write("a.sh", "echo foo # foo\n# foo\necho 'foo'\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--code-only"),
+        Path::new("--lang"),
+        Path::new("shell"),
+        Path::new("foo"),
+        &a,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:\n1: echo foo # foo\n", a.display())
+    );
+}
+
+#[test]
+fn test_show_skipped() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\n");
+    let bin = dir.write("a.bin", "foo\0\n");
+    let log = dir.write("b.log", "foo\n");
+    dir.write(".gitignore", "*.log\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--show-skipped"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:\n1: foo\n", a.display())
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("skipped {} (binary)\n", bin.display())));
+    assert!(stderr.contains(&format!("skipped {} (ignored by *.log)\n", log.display())));
+
+    // Nothing is printed by default
+    let output = grrs([Path::new("grep"), Path::new("foo"), dir.path()]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("skipped"));
+}
+
+#[test]
+fn test_files_with_matches() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\nfoo\n");
+    let b = dir.write("b.txt", "bar\n");
+    let c = dir.write("sub/c.txt", "bar foo\n");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-l"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        vec![a.display().to_string(), c.display().to_string()]
+    );
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-L"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), vec![b.display().to_string()]);
+
+    // -L fails if every file matched, since nothing was listed
+    let output = grrs([Path::new("grep"), Path::new("-L"), Path::new("foo"), &a]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let output = grrs([Path::new("grep"), Path::new("-L"), Path::new("foo"), &b]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // They cannot be used together
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-l"),
+        Path::new("-L"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_decode() {
+    let dir = TempDir::new();
+    // "secret: hunter2" in base64
+    let file = dir.write(
+        "data.txt",
+        "plain secret\nc2VjcmV0OiBodW50ZXIy\nbm90aGluZw==\n",
+    );
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--decode"),
+        Path::new("base64"),
+        Path::new("secret"),
+        &file,
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}:\n2: c2VjcmV0OiBodW50ZXIy\n", file.display())
+    );
+}
+
+#[test]
+fn test_word_and_line_regexp() {
+    let dir = TempDir::new();
+    let file = dir.write("a.txt", "foo\nfoobar\nFOO bar\n");
+    let search = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("grep"), OsStr::new("-j"), OsStr::new("1")];
+        args.extend(flags.iter().map(OsStr::new));
+        args.push(file.as_os_str());
+        let output = grrs(args);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let header = format!("{}:\n", file.display());
+
+    assert_eq!(search(&["-w", "foo"]), format!("{}1: foo\n", header));
+    assert_eq!(
+        search(&["-w", "-i", "foo"]),
+        format!("{}1: foo\n3: FOO bar\n", header)
+    );
+    assert_eq!(
+        search(&["-x", "foo|foobar"]),
+        format!("{}1: foo\n2: foobar\n", header)
+    );
+    assert_eq!(search(&["-x", "-i", "foo"]), format!("{}1: foo\n", header));
+
+    // They cannot be used together
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-w"),
+        Path::new("-x"),
+        Path::new("foo"),
+        &file,
+    ]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_ignore_test() {
+    let dir = TempDir::new();
+    let gitignore = dir.write(".gitignore", "*.log\n!keep.log\ntarget/\n/docs/*.html\n");
+    let rule = |line_num: usize, pattern: &str| {
+        format!("by {}:{}: {}", gitignore.display(), line_num, pattern)
+    };
+    let expected = format!(
+        "a.log: ignored {}\nkeep.log: not ignored {}\ntarget/: ignored {}\ntarget: not ignored\ndocs/a.html: ignored {}\nsrc/docs/a.html: not ignored\n",
+        rule(1, "*.log"),
+        rule(2, "!keep.log"),
+        rule(3, "target/"),
+        rule(4, "/docs/*.html"),
+    );
+    let paths = [
+        "a.log",
+        "keep.log",
+        "target/",
+        "target",
+        "docs/a.html",
+        "src/docs/a.html",
+    ];
+
+    let mut args = vec![OsStr::new("ignore-test"), gitignore.as_os_str()];
+    args.extend(paths.iter().map(OsStr::new));
+    let output = grrs(args);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    // The paths can also be piped
+    let output = grrs_with_stdin(
+        [OsStr::new("ignore-test"), gitignore.as_os_str()],
+        paths.join("\n").as_bytes(),
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    // Fails if nothing is ignored
+    let output = grrs([
+        OsStr::new("ignore-test"),
+        gitignore.as_os_str(),
+        OsStr::new("a.txt"),
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a.txt: not ignored\n"
+    );
+}
+
+#[test]
+fn test_pattern_file() {
+    let dir = TempDir::new();
+    let file = dir.write("a.txt", "foo\nbar\nbaz\nqux\n");
+    let patterns = dir.write("patterns.txt", "foo\n\nba+r\r\n");
+    let empty = dir.write("empty.txt", "\n\n");
+
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("-f"),
+        patterns.as_os_str(),
+        OsStr::new("-e"),
+        OsStr::new("qux"),
+        file.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}:\n1: foo\n2: bar\n4: qux\n", file.display())
+    );
+
+    // From stdin, which is then not searched
+    let output = grrs_with_stdin(
+        [
+            OsStr::new("grep"),
+            OsStr::new("-f"),
+            OsStr::new("-"),
+            file.as_os_str(),
+        ],
+        b"baz\nqux\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}:\n3: baz\n4: qux\n", file.display())
+    );
+    let output = grrs_with_stdin(
+        [
+            OsStr::new("grep"),
+            OsStr::new("-f"),
+            OsStr::new("-"),
+            OsStr::new("-"),
+        ],
+        b"foo\n",
+    );
+    assert_eq!(output.status.code(), Some(2));
+
+    // No patterns would otherwise match everything
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("-f"),
+        empty.as_os_str(),
+        file.as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("no patterns given")
+    );
+}
+
+#[test]
+fn test_include_exclude() {
+    let dir = TempDir::new();
+    let main = dir.write("src/main.rs", "");
+    let lib = dir.write("src/lib.rs", "");
+    dir.write("src/main.c", "");
+    dir.write("build.rs", "");
+    dir.write("Cargo.lock", "");
+
+    let output = grrs([
+        OsStr::new("files"),
+        OsStr::new("--include"),
+        OsStr::new("src/*.rs"),
+        OsStr::new("--include"),
+        OsStr::new("*.lock"),
+        OsStr::new("--exclude"),
+        OsStr::new("*.lock"),
+        dir.path().as_os_str(),
+    ]);
+    assert!(output.status.success());
+    // The globs are relative to the searched path, and excludes take precedence
+    assert_eq!(
+        sorted_lines(&output),
+        vec![lib.display().to_string(), main.display().to_string()]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new();
+    let file = dir.write("src/a.txt", "foo\n");
+    dir.write("linked/b.txt", "foo\n");
+    symlink(dir.path().join("linked"), dir.path().join("src/link")).unwrap();
+    // A cycle back to the root
+    symlink(dir.path().join("src"), dir.path().join("src/link/cycle")).unwrap();
+
+    let src = dir.path().join("src");
+    let search = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("files")];
+        args.extend(flags.iter().map(OsStr::new));
+        args.push(src.as_os_str());
+        let output = grrs(args);
+        assert!(output.status.success());
+        sorted_lines(&output)
+    };
+
+    // Symlinks are skipped by default
+    assert_eq!(search(&[]), vec![file.display().to_string()]);
+    assert_eq!(
+        search(&["--follow"]),
+        vec![
+            file.display().to_string(),
+            dir.path().join("src/link/b.txt").display().to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_max_buffer() {
+    let dir = TempDir::new();
+    let file = dir.write("a.txt", &"foo\n".repeat(100));
+
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("--max-buffer"),
+        OsStr::new("100"),
+        OsStr::new("foo"),
+        file.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    // Every line is still printed, but the path is repeated for each part
+    let header = format!("{}:", file.display());
+    let num_headers = stdout.lines().filter(|line| *line == header).count();
+    assert!(num_headers > 1);
+    assert_eq!(stdout.lines().count(), 100 + num_headers);
+    let line_nums: Vec<_> = stdout
+        .lines()
+        .filter(|line| *line != header)
+        .map(|line| line.split(':').next().unwrap().parse::<usize>().unwrap())
+        .collect();
+    assert_eq!(line_nums, (1..=100).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_dash_prefixed_paths() {
+    let dir = TempDir::new();
+    let weird = dir.write("-weird.txt", "foo\n");
+    dir.write("-", "foo\n");
+    let expected = |path: &str| format!("{}:\n1: foo\n", path);
+
+    // Relative paths, so that they start with a dash
+    let output = grrs_command()
+        .args(["grep", "foo", "--", "-weird.txt"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        expected("-weird.txt")
+    );
+
+    let output = grrs_command()
+        .args(["grep", "foo", "--path", "-weird.txt"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        expected("-weird.txt")
+    );
+
+    // With --path, `-` is a file rather than stdin
+    let output = grrs_command()
+        .args(["grep", "foo", "--path", "-", "--path"])
+        .arg(&weird)
+        .current_dir(dir.path())
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        vec![
+            String::from("-:"),
+            format!("{}:", weird.display()),
+            String::from("1: foo"),
+            String::from("1: foo"),
+        ]
+    );
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = TempDir::new();
+    let file = dir.write("a.txt", "foo\n");
+    let exit_code = |args: &[&OsStr]| {
+        let mut all_args = vec![OsStr::new("grep")];
+        all_args.extend(args);
+        grrs(all_args).status.code()
+    };
+
+    // 0 when something matched, 1 when nothing did, and 2 on errors
+    assert_eq!(exit_code(&[OsStr::new("foo"), file.as_os_str()]), Some(0));
+    assert_eq!(exit_code(&[OsStr::new("bar"), file.as_os_str()]), Some(1));
+    assert_eq!(
+        exit_code(&[OsStr::new("-c"), OsStr::new("bar"), file.as_os_str()]),
+        Some(1)
+    );
+    assert_eq!(
+        exit_code(&[
+            OsStr::new("foo"),
+            dir.path().join("missing.txt").as_os_str()
+        ]),
+        Some(2)
+    );
+    assert_eq!(exit_code(&[OsStr::new("(foo"), file.as_os_str()]), Some(2));
+    assert_eq!(
+        exit_code(&[
+            OsStr::new("--files-all"),
+            OsStr::new("-e"),
+            OsStr::new("foo"),
+            OsStr::new("-e"),
+            OsStr::new("bar"),
+            file.as_os_str()
+        ]),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_stats_json() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "foo foo\nbar\n");
+    dir.write("b.txt", "foo\n");
+    dir.write("c.txt", "baz\n");
+
+    // Removes the elapsed time, which differs between runs
+    let without_elapsed = |stats: &str| {
+        let (stats, elapsed) = stats.trim_end().split_once(",\"elapsed_ms\":").unwrap();
+        assert!(elapsed.strip_suffix('}').unwrap().parse::<u64>().is_ok());
+        stats.to_string()
+    };
+    let expected = r#"{"files_searched":3,"files_matched":2,"matching_lines":2,"matches":3,"bytes_searched":20"#;
+
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("--stats-json"),
+        OsStr::new("foo"),
+        dir.path().as_os_str(),
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(without_elapsed(&stderr), expected);
+
+    // Printed once at the end, even when each file is only searched until its first match
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("--stats-json=stdout"),
+        OsStr::new("-l"),
+        OsStr::new("-j"),
+        OsStr::new("1"),
+        OsStr::new("foo"),
+        dir.path().as_os_str(),
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let last_line = stdout.lines().last().unwrap();
+    assert_eq!(stdout.matches("files_searched").count(), 1);
+    assert_eq!(
+        without_elapsed(last_line),
+        r#"{"files_searched":3,"files_matched":2,"matching_lines":2,"matches":3,"bytes_searched":16"#
+    );
+}
+
+#[test]
+fn test_stats() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "foo foo\nbar\n");
+    dir.write("b.txt", "baz\n");
+    dir.write("c.bin", "\0foo\n");
+    dir.write(".gitignore", "*.log\nignored/\n");
+    dir.write("d.log", "foo\n");
+    dir.write("ignored/e.txt", "foo\n");
+
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("--stats"),
+        OsStr::new("foo"),
+        dir.path().as_os_str(),
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // After the results and an empty line, with the elapsed time last since it differs between runs
+    let (results, stats) = stdout.split_once("\n\n").unwrap();
+    assert_eq!(
+        results,
+        format!("{}:\n1: foo foo", dir.path().join("a.txt").display())
+    );
+    let (stats, elapsed) = stats.trim_end().rsplit_once('\n').unwrap();
+    assert_eq!(
+        stats,
+        "2 matches\n1 matched lines\n1 files contained matches\n2 files searched\n1 binary files skipped\n2 ignored paths skipped\n16 bytes searched"
+    );
+    assert!(elapsed.ends_with(" seconds"));
+
+    // Even if the walk fails
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("--stats"),
+        OsStr::new("--glob"),
+        OsStr::new("["),
+        OsStr::new("foo"),
+        dir.path().as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\n0 files searched\n"));
+}
+
+#[test]
+fn test_global_ignore() {
+    let dir = TempDir::new();
+    let config_home = dir.path().join("config");
+    dir.write("config/git/ignore", "*.swp\n.DS_Store\n");
+    dir.write("config/git/custom_ignore", "*.bak\n");
+    let kept = dir.write("src/a.txt", "foo\n");
+    let swp = dir.write("src/a.txt.swp", "foo\n");
+    let bak = dir.write("src/a.txt.bak", "foo\n");
+    let src = dir.path().join("src");
+
+    let files = |flags: &[&str], git_config: Option<&Path>| {
+        let mut command = grrs_command();
+        command
+            .arg("files")
+            .args(flags)
+            .arg(&src)
+            .env("HOME", dir.path())
+            .env("XDG_CONFIG_HOME", &config_home)
+            .env_remove("GIT_CONFIG");
+        if let Some(git_config) = git_config {
+            command.env("GIT_CONFIG", git_config);
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        sorted_lines(&output)
+    };
+
+    // $XDG_CONFIG_HOME/git/ignore is the default
+    assert_eq!(
+        files(&[], None),
+        vec![kept.display().to_string(), bak.display().to_string()]
+    );
+    assert_eq!(
+        files(&["--no-global-ignore"], None),
+        vec![
+            kept.display().to_string(),
+            bak.display().to_string(),
+            swp.display().to_string(),
+        ]
+    );
+
+    // Or core.excludesFile in the git config
+    let git_config = dir.write(
+        "gitconfig",
+        &format!(
+            "[core]\n\texcludesFile = {}\n",
+            config_home.join("git/custom_ignore").display()
+        ),
+    );
+    assert_eq!(
+        files(&[], Some(&git_config)),
+        vec![kept.display().to_string(), swp.display().to_string()]
+    );
+}
+
+#[test]
+fn test_filesize() {
+    let dir = TempDir::new();
+    let small = dir.write("small.txt", "foo\n");
+    let medium = dir.write("medium.txt", &"foo\n".repeat(256));
+    let large = dir.write("large.txt", &"foo\n".repeat(1024));
+
+    let files = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("files")];
+        args.extend(flags.iter().map(OsStr::new));
+        args.push(dir.path().as_os_str());
+        let output = grrs(&args);
+        assert!(output.status.success());
+        sorted_lines(&output)
+    };
+
+    // medium.txt is exactly 1K, and both bounds are inclusive
+    assert_eq!(
+        files(&["--min-filesize", "1K"]),
+        vec![large.display().to_string(), medium.display().to_string()]
+    );
+    assert_eq!(
+        files(&["--max-filesize", "1k"]),
+        vec![medium.display().to_string(), small.display().to_string()]
+    );
+    assert_eq!(
+        files(&["--min-filesize", "5", "--max-filesize", "1024"]),
+        vec![medium.display().to_string()]
+    );
+
+    let output = grrs([
+        OsStr::new("files"),
+        OsStr::new("--min-filesize=2K"),
+        OsStr::new("--max-filesize=1K"),
+        dir.path().as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is larger than --max-filesize"));
+
+    let output = grrs([
+        OsStr::new("files"),
+        OsStr::new("--min-filesize=big"),
+        dir.path().as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid size"));
+}
+
+#[test]
+fn test_invert_match() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\nbar\nfoo bar\nbaz\n");
+    dir.write("b.txt", "foo\n");
+
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("-v"),
+        OsStr::new("foo"),
+        a.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}:\n2: bar\n4: baz\n", a.display())
+    );
+
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("--invert-match"),
+        OsStr::new("-c"),
+        OsStr::new("--include-zero"),
+        OsStr::new("foo"),
+        dir.path().as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        vec![
+            format!("{}:2", a.display()),
+            format!("{}:0", dir.path().join("b.txt").display()),
+        ]
+    );
+
+    // Every line matches, so nothing is selected
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("-v"),
+        OsStr::new(""),
+        a.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_binary_files() {
+    let dir = TempDir::new();
+    let text = dir.write("a.txt", "foo\n");
+    let binary = dir.write("b.bin", "foo\0\nbar\n");
+
+    let grep = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("grep"), OsStr::new("-j"), OsStr::new("1")];
+        args.extend(flags.iter().map(OsStr::new));
+        args.extend([OsStr::new("foo"), dir.path().as_os_str()]);
+        let output = grrs(args);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // Skipped by default
+    assert_eq!(grep(&[]), format!("{}:\n1: foo\n", text.display()));
+    assert_eq!(
+        grep(&["--binary-files", "without-match"]),
+        format!("{}:\n1: foo\n", text.display())
+    );
+    assert_eq!(
+        grep(&["--binary-files", "binary"]),
+        format!(
+            "{}:\n1: foo\n{}:\nBinary file matches\n",
+            text.display(),
+            binary.display()
+        )
+    );
+    let expected = format!(
+        "{}:\n1: foo\n{}:\n1: foo\0\n",
+        text.display(),
+        binary.display()
+    );
+    assert_eq!(grep(&["-a"]), expected);
+    assert_eq!(grep(&["--binary-files", "text"]), expected);
+
+    // Also applies to stdin
+    let output = grrs_with_stdin(["grep", "--binary-files", "binary", "foo"], b"\0foo\n");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Binary file matches\n");
+    let output = grrs_with_stdin(["grep", "foo"], b"\0foo\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_file_types() {
+    let dir = TempDir::new();
+    let rs = dir.write("main.rs", "foo\n");
+    let py = dir.write("main.py", "foo\n");
+    let ron = dir.write("config.ron", "foo\n");
+    let html = dir.write("index.html", "foo\n");
+
+    let files = |flags: &[&str]| {
+        let output = grrs(
+            ["files"]
+                .iter()
+                .chain(flags)
+                .chain(&[dir.path().to_str().unwrap()]),
+        );
+        assert!(output.status.success());
+        sorted_lines(&output)
+    };
+
+    assert_eq!(
+        files(&["-T", "rust"]),
+        vec![
+            ron.display().to_string(),
+            html.display().to_string(),
+            py.display().to_string(),
+        ]
+    );
+    assert_eq!(
+        files(&["-t", "rust", "--type-add", "rust:ron"]),
+        vec![ron.display().to_string(), rs.display().to_string()]
+    );
+    assert_eq!(
+        files(&["-t", "web", "-T", "py", "--type-add", "web:html,*.py"]),
+        vec![html.display().to_string()]
+    );
+
+    // Unknown types are rejected, unless they are added
+    let output = grrs(["grep", "foo", "-t", "web", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = grrs(["grep", "--type-list", "--type-add", "web:html"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().any(|line| line == "rust: *.rs"));
+    assert!(stdout.lines().any(|line| line == "web: *.html"));
+}
+
+#[test]
+fn test_parent_ignore_files() {
+    let dir = TempDir::new();
+    std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+    dir.write(".gitignore", "*.log\n");
+    dir.write("src/a.rs", "foo\n");
+    dir.write("src/a.log", "foo\n");
+    let src = dir.path().join("src");
+
+    let files = |flags: &[&str]| {
+        let output = grrs_command()
+            .arg("files")
+            .args(flags)
+            .current_dir(&src)
+            .env_remove("GIT_CONFIG")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        sorted_lines(&output)
+    };
+
+    assert_eq!(files(&[]), vec!["./a.rs"]);
+    assert_eq!(files(&["--no-ignore-parent"]), vec!["./a.log", "./a.rs"]);
+}
+
+#[test]
+fn test_repo_ignore_files() {
+    let dir = TempDir::new();
+    let repo = dir.path().join("repo");
+    let config_home = dir.path().join("config");
+    // Relative to the root of the repository
+    dir.write("config/git/ignore", "*.md\n/src/gen.rs\n");
+    dir.write("repo/.git/info/exclude", "!notes.md\n*.tmp\n");
+    dir.write("repo/.gitignore", "!keep.tmp\n");
+    for name in ["a.rs", "gen.rs", "a.md", "notes.md", "a.tmp", "keep.tmp"] {
+        dir.write(&format!("repo/src/{}", name), "foo\n");
+    }
+
+    let files = |flags: &[&str]| {
+        let output = grrs_command()
+            .arg("files")
+            .args(flags)
+            .arg("src")
+            .current_dir(&repo)
+            .env("HOME", dir.path())
+            .env("XDG_CONFIG_HOME", &config_home)
+            .env_remove("GIT_CONFIG")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        sorted_lines(&output)
+    };
+
+    // In increasing precedence: the global ignore file, .git/info/exclude and .gitignore files
+    assert_eq!(files(&[]), vec!["src/a.rs", "src/keep.tmp", "src/notes.md"]);
+    assert_eq!(
+        files(&["--no-ignore-exclude"]),
+        vec!["src/a.rs", "src/a.tmp", "src/keep.tmp"]
+    );
+    assert_eq!(
+        files(&["--no-global-ignore", "--no-ignore-exclude"]),
+        vec![
+            "src/a.md",
+            "src/a.rs",
+            "src/a.tmp",
+            "src/gen.rs",
+            "src/keep.tmp",
+            "src/notes.md",
+        ]
+    );
+}
+
+#[test]
+fn test_unrestricted() {
+    let dir = TempDir::new();
+    dir.write(".gitignore", "ignored.txt\n");
+    let text = dir.write("a.txt", "foo\n");
+    let ignored = dir.write("ignored.txt", "foo\n");
+    let hidden = dir.write(".hidden.txt", "foo\n");
+    let binary = dir.write("b.bin", "foo\0\n");
+
+    let grep = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("grep"), OsStr::new("-l")];
+        args.extend(flags.iter().map(OsStr::new));
+        args.extend([OsStr::new("foo"), dir.path().as_os_str()]);
+        let output = grrs(args);
+        assert!(output.status.success());
+        sorted_lines(&output)
+    };
+
+    let text = text.display().to_string();
+    let ignored = ignored.display().to_string();
+    let hidden = hidden.display().to_string();
+    let binary = binary.display().to_string();
+    assert_eq!(grep(&[]), vec![text.clone()]);
+    assert_eq!(grep(&["--no-ignore"]), vec![text.clone(), ignored.clone()]);
+    assert_eq!(grep(&["--hidden"]), vec![hidden.clone(), text.clone()]);
+    assert_eq!(grep(&["-u"]), vec![text.clone(), ignored.clone()]);
+    assert_eq!(
+        grep(&["-uu"]),
+        vec![hidden.clone(), text.clone(), ignored.clone()]
+    );
+    assert_eq!(grep(&["-uuu"]), vec![hidden, text, binary, ignored]);
+}
+
+#[test]
+fn test_check_ignore() {
+    let dir = TempDir::new();
+    std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+    dir.write(".gitignore", "*.log\ntarget/\n");
+    dir.write("src/.ignore", "!keep.log\n");
+    dir.write("src/a.rs", "");
+
+    let check_ignore = |args: &[&str], stdin: &[u8]| {
+        let mut child = grrs_command()
+            .arg("check-ignore")
+            .args(args)
+            .current_dir(dir.path())
+            .env_remove("GIT_CONFIG")
+            .env("XDG_CONFIG_HOME", dir.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    // Like git, the sources are relative to the root of the repository
+    let output = check_ignore(
+        &["a.log", "src/keep.log", "target/", "src/a.rs", "src/b.log"],
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        ".gitignore:1:*.log\ta.log\nsrc/.ignore:1:!keep.log\tsrc/keep.log\n.gitignore:2:target/\ttarget/\n.gitignore:1:*.log\tsrc/b.log\n"
+    );
+
+    // The paths can also be piped
+    let output = check_ignore(&["-n"], b"src/a.rs\ntarget/\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "::\tsrc/a.rs\n.gitignore:2:target/\ttarget/\n"
+    );
+
+    // Fails if nothing is ignored
+    let output = check_ignore(&["src/a.rs", "src/keep.log"], b"");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_replace() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "fn foo_bar() {}\nbaz\nfoo_qux foo_bar\n");
+
+    let grep = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("grep")];
+        args.extend(flags.iter().map(OsStr::new));
+        args.extend([OsStr::new(r"foo_(\w+)"), a.as_os_str()]);
+        let output = grrs(args);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let expected = format!(
+        "{}:\n1: fn bar_foo() {{}}\n3: qux_foo bar_foo\n",
+        a.display()
+    );
+    assert_eq!(grep(&["-r", "${1}_foo"]), expected);
+    assert_eq!(grep(&["--replace", "${1}_foo"]), expected);
+    assert_eq!(
+        grep(&["-r", "${1:upper}:{lineno}"]),
+        format!("{}:\n1: fn BAR:1() {{}}\n3: QUX:3 BAR:3\n", a.display())
+    );
+    // Only a preview
+    assert_eq!(
+        std::fs::read_to_string(&a).unwrap(),
+        "fn foo_bar() {}\nbaz\nfoo_qux foo_bar\n"
+    );
+}
+
+#[test]
+fn test_replace_command() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "fn foo_bar() {}\r\nbaz\nfoo_qux foo_bar\n");
+    let b = dir.write("sub/b.txt", "baz\n");
+    dir.write(".gitignore", "ignored.txt\n");
+    let ignored = dir.write("ignored.txt", "foo_bar\n");
+    let replace = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("replace")];
+        args.extend(flags.iter().map(OsStr::new));
+        args.extend([
+            OsStr::new(r"foo_(\w+)"),
+            OsStr::new("${1}_foo"),
+            dir.path().as_os_str(),
+        ]);
+        grrs(args)
+    };
+
+    // Nothing is written
+    let output = replace(&["--dry-run"]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), [format!("{}:2", a.display())]);
+    assert_eq!(
+        std::fs::read_to_string(&a).unwrap(),
+        "fn foo_bar() {}\r\nbaz\nfoo_qux foo_bar\n"
+    );
+
+    let output = replace(&["--backup"]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output), [format!("{}:2", a.display())]);
+    assert_eq!(
+        std::fs::read_to_string(&a).unwrap(),
+        "fn bar_foo() {}\r\nbaz\nqux_foo bar_foo\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("a.txt.bak")).unwrap(),
+        "fn foo_bar() {}\r\nbaz\nfoo_qux foo_bar\n"
+    );
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "baz\n");
+    assert_eq!(std::fs::read_to_string(&ignored).unwrap(), "foo_bar\n");
+
+    // Nothing is left to replace, since the backup is not rewritten
+    let output = replace(&["--backup"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("a.txt.bak")).unwrap(),
+        "fn foo_bar() {}\r\nbaz\nfoo_qux foo_bar\n"
+    );
+
+    // Without --backup, files named like backups are like any other file
+    let c_bak = dir.write("c.txt.bak", "foo_c\n");
+    dir.write("c.txt", "c\n");
+    let output = replace(&[]);
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output),
+        [
+            format!("{}:2", dir.path().join("a.txt.bak").display()),
+            format!("{}:1", c_bak.display()),
+        ]
+    );
+    assert_eq!(std::fs::read_to_string(&c_bak).unwrap(), "c_foo\n");
+}
+
+#[test]
+fn test_quiet() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "foo\nfoo\n");
+    dir.write("b.txt", "bar\n");
+    let quiet = |args: &[&str]| {
+        let mut all_args = vec![OsStr::new("grep"), OsStr::new("-q")];
+        all_args.extend(args.iter().map(OsStr::new));
+        all_args.push(dir.path().as_os_str());
+        let output = grrs(all_args);
+        // Nothing is printed, only the exit code tells if anything matched
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+        output.status.code()
+    };
+
+    assert_eq!(quiet(&["foo"]), Some(0));
+    assert_eq!(quiet(&["-j", "1", "bar"]), Some(0));
+    assert_eq!(quiet(&["baz"]), Some(1));
+    assert_eq!(quiet(&["-l", "foo"]), Some(0));
+    assert_eq!(quiet(&["-c", "foo"]), Some(0));
+    assert_eq!(quiet(&["--files-all", "-e", "foo", "-e", "bar"]), Some(1));
+
+    let output = grrs_with_stdin(["grep", "-q", "foo"], b"foo\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_smart_case() {
+    let dir = TempDir::new();
+    let a = dir.write("a.txt", "foo\nFoo\nFOO\n");
+    let grep = |args: &[&str]| {
+        let mut all_args = vec![OsStr::new("grep"), OsStr::new("-N")];
+        all_args.extend(args.iter().map(OsStr::new));
+        all_args.push(a.as_os_str());
+        let output = grrs(all_args);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let expected = |lines: &str| format!("{}:\n{}", a.display(), lines);
+
+    assert_eq!(grep(&["-S", "foo"]), expected("foo\nFoo\nFOO\n"));
+    assert_eq!(grep(&["--smart-case", "Foo"]), expected("Foo\n"));
+    // Escapes do not count as uppercase letters
+    assert_eq!(grep(&["-S", r"^\Soo"]), expected("foo\nFoo\nFOO\n"));
+    // The last of -i and -S wins
+    assert_eq!(grep(&["-i", "-S", "Foo"]), expected("Foo\n"));
+    assert_eq!(grep(&["-S", "-i", "Foo"]), expected("foo\nFoo\nFOO\n"));
+}
+
+#[test]
+fn test_git_tracked() {
+    let dir = TempDir::new();
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(args)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    };
+    if !git(&["init", "-q"]) {
+        // git is not installed
+        return;
+    }
+    let a = dir.write("a.txt", "foo\n");
+    dir.write("b.rs", "foo\n");
+    dir.write("deleted.txt", "foo\n");
+    dir.write("untracked.txt", "foo\n");
+    assert!(git(&["add", "a.txt", "b.rs", "deleted.txt"]));
+    std::fs::remove_file(dir.path().join("deleted.txt")).unwrap();
+
+    // The deleted file is skipped, and the glob still applies
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--git-tracked"),
+        Path::new("--glob"),
+        Path::new("*.txt"),
+        Path::new("-l"),
+        Path::new("foo"),
+        dir.path(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(sorted_lines(&output), vec![a.display().to_string()]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_max_matches_per_line() {
+    let dir = TempDir::new();
+    let file = dir.write("a.txt", "foo foo foo\n");
+
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("-o"),
+        OsStr::new("--max-matches-per-line"),
+        OsStr::new("2"),
+        OsStr::new("foo"),
+        file.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}:\n1: foo\n1: foo\n", file.display())
+    );
+
+    // No line could ever match
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("--max-matches-per-line"),
+        OsStr::new("0"),
+        OsStr::new("foo"),
+        file.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_invalid_not_pattern() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "foo\n");
+    dir.write("b.txt", "foo bar\n");
+
+    // Fails once before searching, rather than once for every file
+    let output = grrs([
+        OsStr::new("grep"),
+        OsStr::new("foo"),
+        OsStr::new("--not-pattern=("),
+        dir.path().as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid search pattern"));
+    assert!(!stderr.contains("failed to read"));
+}
+
+#[test]
+fn test_output_file() {
+    let dir = TempDir::new();
+    let a = dir.write("src/a.txt", "hello\nworld\n");
+    let output_path = dir.path().join("results.txt");
+
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--output"),
+        &output_path,
+        Path::new("hello"),
+        &dir.path().join("src"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"");
+    assert_eq!(
+        std::fs::read_to_string(&output_path).unwrap(),
+        format!("{}:\n1: hello\n", a.display())
+    );
+
+    // The output file is not searched, even if it is in a searched directory. There are enough
+    // results that some of them are already written when it would be searched, after big.txt
+    let big = dir.write("big.txt", &"hello\n".repeat(10_000));
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("-j"),
+        Path::new("1"),
+        Path::new("--output"),
+        &output_path,
+        Path::new("hello"),
+        dir.path(),
+    ]);
+    assert!(output.status.success());
+    let big_results: String = (1..=10_000).map(|i| format!("{}: hello\n", i)).collect();
+    assert_eq!(
+        std::fs::read_to_string(&output_path).unwrap(),
+        format!(
+            "{}:\n{}{}:\n1: hello\n",
+            big.display(),
+            big_results,
+            a.display()
+        )
+    );
+
+    // The output file cannot be created
+    let output = grrs([
+        Path::new("grep"),
+        Path::new("--output"),
+        &dir.path().join("missing/results.txt"),
+        Path::new("hello"),
+        &dir.path().join("src"),
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"");
+}
+
+#[test]
+fn test_invalid_path() {
+    let output = grrs(["grep", "foo", "/nonexistent"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(output.stdout, b"");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: path '/nonexistent' does not exist\n"
+    );
+}
+
+#[test]
+fn test_unreadable_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    let unreadable = dir.path().join("unreadable");
+    std::fs::create_dir(&unreadable).unwrap();
+    std::fs::set_permissions(&unreadable, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+    // Permissions are not enforced for privileged users (e.g. root)
+    if std::fs::read_dir(&unreadable).is_err() {
+        let output = grrs([Path::new("grep"), Path::new("foo"), &unreadable]);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            format!("error: path '{}' is not readable\n", unreadable.display())
+        );
+    }
+
+    std::fs::set_permissions(&unreadable, std::fs::Permissions::from_mode(0o755)).unwrap();
+}